@@ -0,0 +1,496 @@
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use crate::ids::BREAKPOINT_ID_BASE;
+
+// ============================================================================
+// Breakpoint Persistence
+// ============================================================================
+
+/// A breakpoint's location as the target identifies it. The target rejects a
+/// second breakpoint at the same location, whatever its condition.
+fn location_key(params: &Value) -> String {
+    format!(
+        "{}|{}|{}|{}",
+        params.get("url").and_then(|u| u.as_str()).unwrap_or(""),
+        params
+            .get("urlRegex")
+            .and_then(|u| u.as_str())
+            .unwrap_or(""),
+        params
+            .get("lineNumber")
+            .and_then(|l| l.as_u64())
+            .unwrap_or(0),
+        params
+            .get("columnNumber")
+            .and_then(|c| c.as_u64())
+            .unwrap_or(0),
+    )
+}
+
+/// Breakpoints the clients of one context have set through
+/// `Debugger.setBreakpointByUrl`, in the form the target knows (full URLs,
+/// since client messages are translated by `UrlMap` first).
+/// Shared by every connection of the context and kept across target changes.
+#[derive(Debug, Default)]
+pub struct BreakpointStore {
+    breakpoints: Vec<Value>,
+}
+
+impl BreakpointStore {
+    fn set(&mut self, params: Value) {
+        let key = location_key(&params);
+        self.breakpoints.retain(|b| location_key(b) != key);
+        self.breakpoints.push(params);
+    }
+
+    fn remove(&mut self, key: &str) {
+        self.breakpoints.retain(|b| location_key(b) != key);
+    }
+}
+
+/// A breakpoint set on the current target: its parameters and the target's answer.
+#[derive(Debug)]
+struct Applied {
+    params: Value,
+    result: Value,
+}
+
+/// An outstanding `setBreakpointByUrl` request.
+#[derive(Debug)]
+struct Pending {
+    key: String,
+    params: Value,
+    /// Sent by the proxy to restore a stored breakpoint
+    restore: bool,
+}
+
+/// Breakpoint bookkeeping for one client connection.
+///
+/// As soon as the debugger is enabled on a new target, every stored breakpoint
+/// is set again without waiting for the client. When the client re-sends one of
+/// them (VS Code does after reconnecting), it is answered from the result of
+/// that request instead of being rejected by the target as a duplicate.
+/// Breakpoints the proxy restored but the client never claimed are dropped
+/// from the store when it disconnects, but only if the client re-sent any
+/// breakpoints at all: a connection that ended before the client caught up
+/// says nothing about them, so they are kept for the next target.
+#[derive(Debug)]
+pub struct BreakpointSync {
+    store: Arc<Mutex<BreakpointStore>>,
+    debugger_enabled: bool,
+    applied: HashMap<String, Applied>,
+    /// Locations the proxy restored on its own
+    restored: HashSet<String>,
+    /// Locations the client set itself during this connection
+    claimed: HashSet<String>,
+    /// Outstanding `setBreakpointByUrl` requests by message id
+    pending: HashMap<u64, Pending>,
+    /// Client ids waiting for a restore of the same breakpoint to finish
+    waiting: HashMap<String, Vec<u64>>,
+    /// Internal requests whose responses are dropped without further handling
+    ignored: HashSet<u64>,
+    next_id: u64,
+}
+
+impl BreakpointSync {
+    pub fn new(store: Arc<Mutex<BreakpointStore>>) -> Self {
+        Self {
+            store,
+            debugger_enabled: false,
+            applied: HashMap::new(),
+            restored: HashSet::new(),
+            claimed: HashSet::new(),
+            pending: HashMap::new(),
+            waiting: HashMap::new(),
+            ignored: HashSet::new(),
+            next_id: BREAKPOINT_ID_BASE,
+        }
+    }
+
+    fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Forget everything about the previous target. Called for each new upstream connection.
+    pub fn reset_target(&mut self) {
+        self.debugger_enabled = false;
+        self.applied.clear();
+        self.pending.clear();
+        self.waiting.clear();
+        self.ignored.clear();
+    }
+
+    /// Handle a client message. Returns the messages to send to the target and
+    /// the replies to send straight back to the client.
+    pub fn handle_client_message(
+        &mut self,
+        text: &str,
+        parsed: &Value,
+    ) -> (Vec<String>, Vec<String>) {
+        let method = parsed.get("method").and_then(|m| m.as_str());
+        let id = parsed.get("id").and_then(|id| id.as_u64());
+
+        match (method, id) {
+            (Some("Debugger.setBreakpointByUrl"), Some(id)) => self.set_breakpoint(id, parsed),
+            (Some("Debugger.removeBreakpoint"), _) => {
+                let breakpoint_id = parsed
+                    .pointer("/params/breakpointId")
+                    .and_then(|b| b.as_str());
+                let key = self
+                    .applied
+                    .iter()
+                    .find(|(_, a)| {
+                        a.result.get("breakpointId").and_then(|b| b.as_str()) == breakpoint_id
+                    })
+                    .map(|(key, _)| key.clone());
+                if let Some(key) = key {
+                    self.applied.remove(&key);
+                    self.claimed.remove(&key);
+                    self.store.lock().unwrap().remove(&key);
+                }
+                (vec![text.to_string()], Vec::new())
+            }
+            _ => (vec![text.to_string()], Vec::new()),
+        }
+    }
+
+    fn set_breakpoint(&mut self, id: u64, parsed: &Value) -> (Vec<String>, Vec<String>) {
+        let params = parsed.get("params").cloned().unwrap_or_else(|| json!({}));
+        let key = location_key(&params);
+
+        if let Some(applied) = self.applied.get(&key) {
+            if applied.params == params {
+                self.claimed.insert(key);
+                let reply = json!({ "id": id, "result": applied.result });
+                return (Vec::new(), vec![reply.to_string()]);
+            }
+        } else if self
+            .pending
+            .values()
+            .any(|p| p.key == key && p.params == params)
+        {
+            self.claimed.insert(key.clone());
+            self.waiting.entry(key).or_default().push(id);
+            return (Vec::new(), Vec::new());
+        }
+
+        let mut to_target = Vec::new();
+
+        // Same location, different condition: replace the restored breakpoint
+        if let Some(old) = self.applied.remove(&key)
+            && let Some(breakpoint_id) = old.result.get("breakpointId")
+        {
+            let remove_id = self.next_id();
+            self.ignored.insert(remove_id);
+            to_target.push(
+                json!({
+                    "id": remove_id,
+                    "method": "Debugger.removeBreakpoint",
+                    "params": { "breakpointId": breakpoint_id }
+                })
+                .to_string(),
+            );
+        }
+
+        self.pending.insert(
+            id,
+            Pending {
+                key,
+                params,
+                restore: false,
+            },
+        );
+        to_target.push(parsed.to_string());
+        (to_target, Vec::new())
+    }
+
+    /// Called for every command sent to the target, whether it came from the
+    /// client or was replayed. Once the debugger is enabled, returns the
+    /// commands that restore the stored breakpoints.
+    pub fn after_sent(&mut self, parsed: &Value) -> Vec<String> {
+        if self.debugger_enabled
+            || parsed.get("method").and_then(|m| m.as_str()) != Some("Debugger.enable")
+        {
+            return Vec::new();
+        }
+        self.debugger_enabled = true;
+
+        let stored = self.store.lock().unwrap().breakpoints.clone();
+        let mut commands = Vec::new();
+        for params in stored {
+            let key = location_key(&params);
+            if self.applied.contains_key(&key) {
+                continue;
+            }
+            let id = self.next_id();
+            self.restored.insert(key.clone());
+            commands.push(
+                json!({ "id": id, "method": "Debugger.setBreakpointByUrl", "params": params })
+                    .to_string(),
+            );
+            self.pending.insert(
+                id,
+                Pending {
+                    key,
+                    params,
+                    restore: true,
+                },
+            );
+        }
+        commands
+    }
+
+    /// Handle a target message. Returns `None` if it should be forwarded as
+    /// usual, or `Some(replies)` if it was consumed and the replies should be
+    /// sent to the client instead.
+    pub fn handle_target_message(&mut self, parsed: &Value) -> Option<Vec<String>> {
+        let id = parsed.get("id").and_then(|id| id.as_u64())?;
+        if self.ignored.remove(&id) {
+            return Some(Vec::new());
+        }
+        let Pending {
+            key,
+            params,
+            restore,
+        } = self.pending.remove(&id)?;
+
+        let Some(result) = parsed
+            .get("result")
+            .filter(|r| r.get("breakpointId").is_some())
+        else {
+            // Rejected by the target; answer anyone waiting with the same error
+            let replies = self
+                .waiting
+                .remove(&key)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|waiting_id| {
+                    let mut reply = parsed.clone();
+                    reply["id"] = json!(waiting_id);
+                    reply.to_string()
+                })
+                .collect();
+            return restore.then_some(replies);
+        };
+
+        if !restore {
+            self.claimed.insert(key.clone());
+            self.store.lock().unwrap().set(params.clone());
+        }
+        let replies = self
+            .waiting
+            .remove(&key)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|waiting_id| json!({ "id": waiting_id, "result": result }).to_string())
+            .collect();
+        self.applied.insert(
+            key,
+            Applied {
+                params,
+                result: result.clone(),
+            },
+        );
+
+        restore.then_some(replies)
+    }
+
+    /// Drop restored breakpoints the client never set again once it has set
+    /// others: it no longer has them. Returns how many were dropped.
+    pub fn finish(&self) -> usize {
+        if self.claimed.is_empty() {
+            return 0;
+        }
+        let mut store = self.store.lock().unwrap();
+        let unclaimed: Vec<&String> = self.restored.difference(&self.claimed).collect();
+        for key in &unclaimed {
+            store.remove(key);
+        }
+        unclaimed.len()
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONG: &str = "/screen_modules/Screen_Content/HMI_RT_1::HMI_Screen/Events.js";
+
+    fn new_sync(store: &Arc<Mutex<BreakpointStore>>) -> BreakpointSync {
+        BreakpointSync::new(store.clone())
+    }
+
+    fn set_request(id: u64, line: u64, condition: &str) -> Value {
+        json!({
+            "id": id,
+            "method": "Debugger.setBreakpointByUrl",
+            "params": {
+                "lineNumber": line,
+                "url": LONG,
+                "columnNumber": 0,
+                "condition": condition
+            }
+        })
+    }
+
+    fn client(sync: &mut BreakpointSync, parsed: &Value) -> (Vec<Value>, Vec<Value>) {
+        let (to_target, to_client) = sync.handle_client_message(&parsed.to_string(), parsed);
+        let parse = |v: Vec<String>| v.iter().map(|s| serde_json::from_str(s).unwrap()).collect();
+        (parse(to_target), parse(to_client))
+    }
+
+    fn respond(sync: &mut BreakpointSync, request: &Value, line: u64) -> Option<Vec<String>> {
+        sync.handle_target_message(&json!({
+            "id": request["id"],
+            "result": { "breakpointId": format!("1:{}:0:{}", line, LONG), "locations": [] }
+        }))
+    }
+
+    #[test]
+    fn stores_breakpoint_set_by_client() {
+        let store = Arc::new(Mutex::new(BreakpointStore::default()));
+        let mut sync = new_sync(&store);
+
+        let (to_target, to_client) = client(&mut sync, &set_request(1, 10, ""));
+        assert!(to_client.is_empty());
+        assert_eq!(to_target[0]["params"]["url"], LONG);
+
+        // Client responses are forwarded as usual
+        assert!(respond(&mut sync, &to_target[0], 10).is_none());
+        assert_eq!(store.lock().unwrap().breakpoints.len(), 1);
+        assert_eq!(store.lock().unwrap().breakpoints[0]["url"], LONG);
+    }
+
+    #[test]
+    fn restores_after_debugger_enable_and_answers_duplicates() {
+        let store = Arc::new(Mutex::new(BreakpointStore::default()));
+        let mut first = new_sync(&store);
+        let (to_target, _) = client(&mut first, &set_request(1, 10, ""));
+        respond(&mut first, &to_target[0], 10);
+        first.finish();
+
+        // A new connection to a new target
+        let mut sync = new_sync(&store);
+        assert!(
+            sync.after_sent(&json!({"id": 1, "method": "Runtime.enable"}))
+                .is_empty()
+        );
+        let restored = sync.after_sent(&json!({"id": 2, "method": "Debugger.enable"}));
+        assert_eq!(restored.len(), 1);
+        let restored: Value = serde_json::from_str(&restored[0]).unwrap();
+        assert!(restored["id"].as_u64().unwrap() >= BREAKPOINT_ID_BASE);
+        assert_eq!(restored["params"]["url"], LONG);
+        assert_eq!(respond(&mut sync, &restored, 10), Some(Vec::new()));
+
+        // The client re-sends the same breakpoint: answered from the cache
+        let (to_target, to_client) = client(&mut sync, &set_request(5, 10, ""));
+        assert!(to_target.is_empty());
+        assert_eq!(to_client[0]["id"], 5);
+        assert_eq!(
+            to_client[0]["result"]["breakpointId"],
+            format!("1:10:0:{}", LONG)
+        );
+    }
+
+    #[test]
+    fn duplicate_during_restore_waits_for_response() {
+        let store = Arc::new(Mutex::new(BreakpointStore::default()));
+        store
+            .lock()
+            .unwrap()
+            .set(json!({"lineNumber": 3, "url": LONG, "columnNumber": 0, "condition": ""}));
+        let mut sync = new_sync(&store);
+        let restored = sync.after_sent(&json!({"id": 1, "method": "Debugger.enable"}));
+        let restored: Value = serde_json::from_str(&restored[0]).unwrap();
+
+        let (to_target, to_client) = client(&mut sync, &set_request(9, 3, ""));
+        assert!(to_target.is_empty() && to_client.is_empty());
+
+        let replies = respond(&mut sync, &restored, 3).unwrap();
+        let reply: Value = serde_json::from_str(&replies[0]).unwrap();
+        assert_eq!(reply["id"], 9);
+    }
+
+    #[test]
+    fn changed_condition_replaces_restored_breakpoint() {
+        let store = Arc::new(Mutex::new(BreakpointStore::default()));
+        store
+            .lock()
+            .unwrap()
+            .set(json!({"lineNumber": 3, "url": LONG, "columnNumber": 0, "condition": ""}));
+        let mut sync = new_sync(&store);
+        let restored = sync.after_sent(&json!({"id": 1, "method": "Debugger.enable"}));
+        let restored: Value = serde_json::from_str(&restored[0]).unwrap();
+        respond(&mut sync, &restored, 3);
+
+        let (to_target, _) = client(&mut sync, &set_request(9, 3, "x > 1"));
+        assert_eq!(to_target[0]["method"], "Debugger.removeBreakpoint");
+        assert_eq!(to_target[1]["id"], 9);
+        assert_eq!(
+            sync.handle_target_message(&json!({"id": to_target[0]["id"], "result": {}})),
+            Some(Vec::new())
+        );
+        respond(&mut sync, &to_target[1], 3);
+        assert_eq!(store.lock().unwrap().breakpoints[0]["condition"], "x > 1");
+    }
+
+    #[test]
+    fn removed_and_unclaimed_breakpoints_leave_the_store() {
+        let store = Arc::new(Mutex::new(BreakpointStore::default()));
+        let mut sync = new_sync(&store);
+        let (to_target, _) = client(&mut sync, &set_request(1, 10, ""));
+        respond(&mut sync, &to_target[0], 10);
+        client(
+            &mut sync,
+            &json!({
+                "id": 2,
+                "method": "Debugger.removeBreakpoint",
+                "params": { "breakpointId": format!("1:10:0:{}", LONG) }
+            }),
+        );
+        assert!(store.lock().unwrap().breakpoints.is_empty());
+
+        // Restored but never re-sent by a client that re-sent another one
+        store
+            .lock()
+            .unwrap()
+            .set(json!({"lineNumber": 4, "url": LONG, "columnNumber": 0}));
+        let mut sync = new_sync(&store);
+        sync.after_sent(&json!({"id": 1, "method": "Debugger.enable"}));
+        let (to_target, _) = client(&mut sync, &set_request(3, 12, ""));
+        respond(&mut sync, &to_target[0], 12);
+        assert_eq!(sync.finish(), 1);
+        assert_eq!(store.lock().unwrap().breakpoints.len(), 1);
+        assert_eq!(store.lock().unwrap().breakpoints[0]["lineNumber"], 12);
+    }
+
+    #[test]
+    fn connection_that_claims_nothing_keeps_restored_breakpoints() {
+        let store = Arc::new(Mutex::new(BreakpointStore::default()));
+        store
+            .lock()
+            .unwrap()
+            .set(json!({"lineNumber": 4, "url": LONG, "columnNumber": 0}));
+        let mut sync = new_sync(&store);
+        let restored = sync.after_sent(&json!({"id": 1, "method": "Debugger.enable"}));
+        let restored: Value = serde_json::from_str(&restored[0]).unwrap();
+        respond(&mut sync, &restored, 4);
+
+        // The connection ends before the client re-sent its breakpoints
+        assert_eq!(sync.finish(), 0);
+        let mut next = new_sync(&store);
+        assert_eq!(
+            next.after_sent(&json!({"id": 1, "method": "Debugger.enable"}))
+                .len(),
+            1
+        );
+    }
+}
@@ -1,6 +1,76 @@
 use anyhow::Result;
+use colored::Colorize;
+use serde::Serialize;
 
-pub fn init_vscode(output_dir: &str, dynamics_port: u16, events_port: u16) -> Result<()> {
+use crate::config::{self, ConfigEntry, ConfigFiles, Configuration, ContextSpec, ProfileArgs};
+use crate::logging::Logger;
+
+#[derive(Serialize)]
+struct LaunchFile {
+    version: &'static str,
+    configurations: Vec<LaunchConfiguration>,
+    compounds: Vec<LaunchCompound>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LaunchConfiguration {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    request: &'static str,
+    name: String,
+    address: &'static str,
+    port: u16,
+    restart: bool,
+    timeout: u32,
+    /// Always `null`, so VS Code uses the source maps the proxy serves
+    resolve_source_map_locations: Option<()>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LaunchCompound {
+    name: &'static str,
+    configurations: Vec<String>,
+    stop_all: bool,
+}
+
+/// launch.json with an attach configuration per context and a compound
+/// that starts them all.
+fn launch_json(contexts: &[ContextSpec]) -> Result<String> {
+    let configurations: Vec<LaunchConfiguration> = contexts
+        .iter()
+        .map(|spec| LaunchConfiguration {
+            kind: "node",
+            request: "attach",
+            name: format!("WinCC:{}", spec.kind),
+            address: "localhost",
+            port: spec.port,
+            restart: true,
+            timeout: 30000,
+            resolve_source_map_locations: None,
+        })
+        .collect();
+    let names = configurations.iter().map(|c| c.name.clone()).collect();
+    let file = LaunchFile {
+        version: "0.2.0",
+        configurations,
+        compounds: vec![LaunchCompound {
+            name: "WinCC:All",
+            configurations: names,
+            stop_all: true,
+        }],
+    };
+
+    let mut out = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(b"    ");
+    file.serialize(&mut serde_json::Serializer::with_formatter(
+        &mut out, formatter,
+    ))?;
+    Ok(String::from_utf8(out)?)
+}
+
+pub fn init_vscode(output_dir: &str, contexts: &[ContextSpec]) -> Result<()> {
     use std::fs;
     use std::io::{self, Write};
     use std::path::Path;
@@ -10,39 +80,7 @@ pub fn init_vscode(output_dir: &str, dynamics_port: u16, events_port: u16) -> Re
     let vscode_dir = abs_base_path.join(".vscode");
     let launch_json_path = vscode_dir.join("launch.json");
 
-    let launch_json = format!(
-        r#"{{
-    "version": "0.2.0",
-    "configurations": [
-        {{
-            "type": "node",
-            "request": "attach",
-            "name": "WinCC:Dynamics",
-            "address": "localhost",
-            "port": {},
-            "restart": true,
-            "timeout": 30000
-        }},
-        {{
-            "type": "node",
-            "request": "attach",
-            "name": "WinCC:Events",
-            "address": "localhost",
-            "port": {},
-            "restart": true,
-            "timeout": 30000
-        }}
-    ],
-    "compounds": [
-        {{
-            "name": "WinCC:All",
-            "configurations": ["WinCC:Dynamics", "WinCC:Events"],
-            "stopAll": true
-        }}
-    ]
-}}"#,
-        dynamics_port, events_port
-    );
+    let launch_json = launch_json(contexts)?;
 
     // Check if launch.json already exists
     if launch_json_path.exists() {
@@ -79,9 +117,10 @@ pub fn init_vscode(output_dir: &str, dynamics_port: u16, events_port: u16) -> Re
     println!("Created: {}", launch_json_path.display());
     println!();
     println!("VS Code debug configurations added:");
-    println!("  - WinCC:Dynamics (port {})", dynamics_port);
-    println!("  - WinCC:Events (port {})", events_port);
-    println!("  - WinCC:All (both)");
+    for spec in contexts {
+        println!("  - WinCC:{} (port {})", spec.kind, spec.port);
+    }
+    println!("  - WinCC:All (all of the above)");
 
     Ok(())
 }
@@ -165,7 +204,10 @@ pause
         .collect();
 
     if !existing.is_empty() {
-        println!("Warning: the following files already exist in {}:", abs_base_path.display());
+        println!(
+            "Warning: the following files already exist in {}:",
+            abs_base_path.display()
+        );
         for name in &existing {
             let path = abs_base_path.join(name);
             println!("\n  {}", path.display());
@@ -217,9 +259,338 @@ pause
 
     println!();
     println!("Run these .bat files as Administrator on the WinCC machine:");
-    println!("  wincc-debug-setup-{}.bat   - First-time setup (port proxy + firewall rules)", addr_slug);
-    println!("  wincc-debug-restart-{}.bat - After Windows restart (re-apply port proxy)", addr_slug);
+    println!(
+        "  wincc-debug-setup-{}.bat   - First-time setup (port proxy + firewall rules)",
+        addr_slug
+    );
+    println!(
+        "  wincc-debug-restart-{}.bat - After Windows restart (re-apply port proxy)",
+        addr_slug
+    );
     println!("  wincc-debug-cleanup-{}.bat - Remove all rules", addr_slug);
 
     Ok(())
 }
+
+pub fn show_config(files: &ConfigFiles, entries: &[ConfigEntry]) {
+    println!("{}", "Configuration files:".cyan().bold());
+    match files.user_path {
+        Some(ref path) => println!("   user:    {}{}", path.display(), file_status(path)),
+        None => println!("   user:    (no user configuration directory)"),
+    }
+    println!(
+        "   project: {}{}",
+        files.project_path.display(),
+        file_status(&files.project_path)
+    );
+    println!();
+
+    println!("{}", "Effective configuration:".cyan().bold());
+    let key_width = entries.iter().map(|e| e.key.len()).max().unwrap_or(0);
+    let value_width = entries.iter().map(|e| e.value.len()).max().unwrap_or(0);
+    for entry in entries {
+        println!(
+            "   {:<key_width$}  {:<value_width$}  {}",
+            entry.key,
+            entry.value,
+            format!("({})", entry.source).dimmed(),
+        );
+    }
+}
+
+fn file_status(path: &std::path::Path) -> &'static str {
+    if path.exists() { "" } else { " (not found)" }
+}
+
+pub fn add_profile(files: &ConfigFiles, args: &ProfileArgs) -> Result<()> {
+    let path = if args.project {
+        files.project_path.clone()
+    } else {
+        files
+            .user_path
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("No user configuration directory; use --project"))?
+    };
+
+    let replaced = files.profile(&args.name).is_some_and(|(p, _)| p == path);
+    config::save_profile(&path, &args.name, &args.to_profile())?;
+
+    println!(
+        "{} profile '{}' in {}",
+        if replaced { "Replaced" } else { "Added" },
+        args.name,
+        path.display()
+    );
+    println!();
+    println!(
+        "Start it with: ./wincc-unified-debug-proxy.exe run --profile {}",
+        args.name
+    );
+    Ok(())
+}
+
+pub fn list_profiles(files: &ConfigFiles) {
+    let profiles = files.profiles();
+    if profiles.is_empty() {
+        println!("No profiles defined. Add one with 'profile add <name> -t <host>'.");
+        return;
+    }
+
+    println!("{}", "Profiles:".cyan().bold());
+    let name_width = profiles.keys().map(|n| n.len()).max().unwrap_or(0);
+    for (name, (path, profile)) in profiles {
+        let mut details = vec![format!(
+            "{}:{}",
+            profile.target_host.as_deref().unwrap_or("localhost"),
+            profile
+                .target_port
+                .map_or_else(|| "9222".to_string(), |p| p.to_string())
+        )];
+        if let Some(port) = profile.dynamics_port {
+            details.push(format!("dynamics {}", port));
+        }
+        if let Some(port) = profile.events_port {
+            details.push(format!("events {}", port));
+        }
+        if let Some(port) = profile.tasks_port {
+            details.push(format!("tasks {}", port));
+        }
+        if let Some(ref dump) = profile.dump {
+            details.push(format!("dump {}", dump));
+        }
+        println!(
+            "   {:<width$}  {}  {}",
+            name,
+            details.join(", "),
+            format!("({})", path.display()).dimmed(),
+            width = name_width
+        );
+    }
+}
+
+pub fn remove_profile(files: &ConfigFiles, name: &str) -> Result<()> {
+    let Some((path, _)) = files.profile(name) else {
+        anyhow::bail!("Unknown profile '{}'", name);
+    };
+    config::remove_profile(path, name)?;
+    println!("Removed profile '{}' from {}", name, path.display());
+    Ok(())
+}
+
+// ============================================================================
+// Proxy Banner
+// ============================================================================
+
+/// Write the configured styleguide to every dump directory. Returns the
+/// directories that got one, for `banner` and `npm_install`.
+pub fn write_styleguides(cfg: &Configuration, log: &Logger) -> Vec<String> {
+    let Some(ref version) = cfg.styleguide_version else {
+        return Vec::new();
+    };
+    let mut written = Vec::new();
+    for dump_dir in cfg.dump_dirs() {
+        match crate::styleguide::write_styleguide(version, dump_dir) {
+            Ok(_) => written.push(dump_dir.to_string()),
+            Err(e) => log.error(&format!("Failed to write styleguide: {}", e)),
+        }
+    }
+    written
+}
+
+/// The lines telling what a started proxy listens on and does, colored.
+/// `styleguide_dirs` are the dump directories `write_styleguides` wrote to.
+pub fn banner(cfg: &Configuration, styleguide_dirs: &[String]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let host = if cfg.bind_address.is_loopback() {
+        "localhost".to_string()
+    } else {
+        cfg.bind_address.to_string()
+    };
+
+    lines.push(String::new());
+    lines.push("Configuration:".cyan().bold().to_string());
+    for station in &cfg.stations {
+        if let Some(ref name) = station.name {
+            lines.push(format!("   Station:       {}", name));
+        }
+        lines.push(format!(
+            "   Target:        {}:{}",
+            station.target_host, station.target_port
+        ));
+        for spec in &station.contexts {
+            lines.push(format!(
+                "   {:<15}{}:{}",
+                format!("{}: ", spec.kind),
+                host,
+                spec.port
+            ));
+        }
+    }
+    lines.push(format!("   Poll interval: {}s", cfg.poll_interval));
+    lines.push(String::new());
+    lines.push("VS Code launch.json ports:".cyan().bold().to_string());
+    for station in &cfg.stations {
+        let width = station
+            .contexts
+            .iter()
+            .map(|c| c.kind.name().len() + 2)
+            .max()
+            .unwrap_or(0);
+        for spec in &station.contexts {
+            lines.push(format!(
+                "   {}{:<width$}{}",
+                station.prefix(),
+                format!("{}:", spec.kind),
+                spec.port
+            ));
+        }
+    }
+    lines.push(String::new());
+    lines.push("Features:".cyan().bold().to_string());
+    if cfg.keep_sessions {
+        lines.push(format!(
+            "   {} Sessions kept open when targets change",
+            "[+]".green()
+        ));
+        lines.push(format!(
+            "   {} Enables and breakpoints replayed on the new target",
+            "[+]".green()
+        ));
+    } else {
+        lines.push(format!(
+            "   {} Server restarts when targets change",
+            "[+]".green()
+        ));
+        lines.push(format!(
+            "   {} Forces VS Code debugger reconnect",
+            "[+]".green()
+        ));
+    }
+    lines.push(format!(
+        "   {} No manual intervention needed!",
+        "[+]".green()
+    ));
+    lines.push(format!(
+        "   {} Separate debug sessions for Dynamics, Events & Tasks",
+        "[+]".green()
+    ));
+    if cfg.stations.len() > 1 {
+        lines.push(format!(
+            "   {} {} stations proxied simultaneously",
+            "[+]".green(),
+            cfg.stations.len()
+        ));
+    }
+    lines.push(format!(
+        "   {} Script path shortening: {}",
+        "[+]".green(),
+        if cfg.file_urls {
+            "off (showing dumped files)"
+        } else if cfg.long_paths {
+            "off (showing full paths)"
+        } else {
+            "on"
+        }
+    ));
+
+    let dump_dirs = cfg.dump_dirs();
+    for dump_dir in &dump_dirs {
+        lines.push(format!(
+            "   {} Continuous script dump -> {}/",
+            "[+]".green(),
+            dump_dir
+        ));
+        if !cfg.file_urls {
+            lines.push(format!(
+                "   {} Source maps open dumped scripts from {}/",
+                "[+]".green(),
+                dump_dir
+            ));
+        }
+        if cfg.dump_git {
+            lines.push(format!(
+                "   {} Every completed dump is committed to {}/.git",
+                "[+]".green(),
+                dump_dir
+            ));
+        }
+
+        if let Some(ref version) = cfg.styleguide_version
+            && styleguide_dirs.iter().any(|dir| dir == dump_dir)
+        {
+            lines.push(format!(
+                "   {} Styleguide ({}) written to {}/",
+                "[+]".green(),
+                version,
+                dump_dir
+            ));
+        }
+    }
+    if !dump_dirs.is_empty() {
+        lines.push(String::new());
+        lines.push("   Start a VS Code debug session to trigger script dumping,".to_string());
+        lines.push("   or use the dump command to dump without one.".to_string());
+    }
+    lines.push(String::new());
+    lines.push(format!("Press {} to stop", "Ctrl+C".yellow().bold()));
+    lines.push(String::new());
+    lines
+}
+
+/// Install the styleguide's ESLint setup in a dump directory.
+pub async fn npm_install(dump_dir: String, log: Logger) {
+    log.log(&format!("Running npm install in {}...", dump_dir));
+    match tokio::task::spawn_blocking(move || {
+        std::process::Command::new("cmd")
+            .args(["/C", "npm", "install"])
+            .current_dir(&dump_dir)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::piped())
+            .status()
+    })
+    .await
+    {
+        Ok(Ok(status)) if status.success() => {
+            log.success("npm install completed — ESLint ready");
+        }
+        Ok(Ok(_)) => {
+            log.warn("npm install failed — run it manually in the dump directory");
+        }
+        Ok(Err(e)) => {
+            log.warn(&format!("Could not run npm install: {}", e));
+        }
+        Err(e) => {
+            log.warn(&format!("npm install task failed: {}", e));
+        }
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ContextEntry;
+
+    #[test]
+    fn launch_json_is_valid_json_for_any_context_name() {
+        let custom = ContextEntry {
+            name: "Alarm's {scripts}".to_string(),
+            title: "alarm".to_string(),
+            port: 9240,
+        };
+        let contexts = [ContextSpec::dynamics(9230), ContextSpec::from(&custom)];
+        let json: serde_json::Value =
+            serde_json::from_str(&launch_json(&contexts).unwrap()).unwrap();
+
+        assert_eq!(json["configurations"][1]["name"], "WinCC:Alarm's {scripts}");
+        assert_eq!(json["configurations"][1]["port"], 9240);
+        assert!(json["configurations"][0]["resolveSourceMapLocations"].is_null());
+        assert_eq!(
+            json["compounds"][0]["configurations"],
+            serde_json::json!(["WinCC:Dynamics", "WinCC:Alarm's {scripts}"])
+        );
+    }
+}
@@ -1,5 +1,11 @@
-use clap::{Parser, Subcommand};
-use std::sync::OnceLock;
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+
+use crate::events::EventOrigin;
 
 /// WinCC Unified Debug Proxy - Proxies Chrome DevTools Protocol connections
 #[derive(Parser, Debug)]
@@ -13,14 +19,26 @@ enabling VS Code debugging with automatic reconnection when scripts reload.
 The proxy monitors the WinCC debug server for target changes and automatically
 restarts connections, forcing VS Code to reconnect without manual intervention.
 
+Settings are read from wincc-debug-proxy.toml in the current directory and in the
+user configuration directory. Command line flags override values from these files.
+
 EXAMPLES:
   ./wincc-unified-debug-proxy.exe                            Start proxy (localhost:9222)
   ./wincc-unified-debug-proxy.exe run -t 192.168.1.100       Connect to remote WinCC
   ./wincc-unified-debug-proxy.exe init                       Create .vscode/launch.json
   ./wincc-unified-debug-proxy.exe generate -a 192.168.1.100  Generate netsh .bat scripts for remote setup
-  ./wincc-unified-debug-proxy.exe run --dump ./output    Dump scripts + write styleguide"#
+  ./wincc-unified-debug-proxy.exe run --dump ./output    Dump scripts + write styleguide
+  ./wincc-unified-debug-proxy.exe dump ./output              Dump all scripts once, no VS Code needed
+  ./wincc-unified-debug-proxy.exe diff ./before ./after      Show which scripts changed between two dumps
+  ./wincc-unified-debug-proxy.exe profile add line3 -t 192.168.3.10  Save a named target profile
+  ./wincc-unified-debug-proxy.exe run --profile line3        Connect using a saved profile
+  ./wincc-unified-debug-proxy.exe config show                Print the effective configuration"#
 )]
 pub struct Cli {
+    /// Configuration file to use instead of ./wincc-debug-proxy.toml
+    #[arg(short = 'c', long, global = true)]
+    pub config: Option<String>,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -33,24 +51,28 @@ pub enum Commands {
         #[arg(short, long, default_value = ".")]
         output: String,
 
-        /// Port for Dynamics proxy (used in launch.json)
-        #[arg(short = 'd', long, default_value_t = 9230)]
-        dynamics_port: u16,
+        /// Port for Dynamics proxy (used in launch.json) [default: 9230]
+        #[arg(short = 'd', long)]
+        dynamics_port: Option<u16>,
+
+        /// Port for Events proxy (used in launch.json) [default: 9231]
+        #[arg(short = 'e', long)]
+        events_port: Option<u16>,
 
-        /// Port for Events proxy (used in launch.json)
-        #[arg(short = 'e', long, default_value_t = 9231)]
-        events_port: u16,
+        /// Port for Tasks proxy (used in launch.json) [default: 9232]
+        #[arg(long)]
+        tasks_port: Option<u16>,
     },
 
     /// Generate .bat scripts for remote WinCC debugging (netsh port forwarding + firewall)
     Generate {
-        /// IP address of the WinCC machine
+        /// IP address of the WinCC machine (defaults to target_host from the config file)
         #[arg(short = 'a', long)]
-        address: String,
+        address: Option<String>,
 
-        /// WinCC debug port
-        #[arg(short = 'p', long, default_value_t = 9222)]
-        port: u16,
+        /// WinCC debug port [default: 9222]
+        #[arg(short = 'p', long)]
+        port: Option<u16>,
 
         /// Output directory for .bat files (defaults to current directory)
         #[arg(short, long, default_value = ".")]
@@ -59,104 +81,1742 @@ pub enum Commands {
 
     /// Start the debug proxy server (default command)
     #[command(name = "run")]
-    Run {
-        /// Target WinCC host address
-        #[arg(short = 't', long, default_value = "localhost")]
-        target_host: String,
-
-        /// Target WinCC debug port
-        #[arg(short = 'p', long, default_value_t = 9222)]
-        target_port: u16,
-
-        /// Local port for Dynamics proxy
-        #[arg(short = 'd', long, default_value_t = 9230)]
-        dynamics_port: u16,
-
-        /// Local port for Events proxy
-        #[arg(short = 'e', long, default_value_t = 9231)]
-        events_port: u16,
-
-        /// Poll interval in seconds
-        #[arg(short = 'i', long, default_value_t = 1)]
-        poll_interval: u64,
-
-        /// Enable verbose logging
-        #[arg(short = 'v', long)]
-        verbose: bool,
-
-        /// Enable very verbose logging
-        #[arg(short = 'V', long)]
-        very_verbose: bool,
-
-        /// Show full (long) script paths instead of shortened ones
-        #[arg(short = 'l', long)]
-        long_paths: bool,
-
-        /// Continuously dump runtime scripts to local files as they are loaded
-        #[arg(long, default_value = None)]
-        dump: Option<String>,
+    Run(RunArgs),
+
+    /// Dump all runtime scripts to local files without a VS Code session
+    Dump(DumpArgs),
+
+    /// Compare two script dumps and show what changed per screen and faceplate
+    Diff {
+        /// Older dump directory or history snapshot
+        old: String,
+
+        /// Newer dump directory or history snapshot
+        new: String,
+
+        /// Only list the changed scripts, without their diffs
+        #[arg(short, long)]
+        summary: bool,
+    },
+
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
     },
+
+    /// Manage named target profiles
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print the effective configuration and where each value came from
+    Show(RunArgs),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ProfileCommand {
+    /// Add a profile, replacing an existing one with the same name
+    Add(ProfileArgs),
+
+    /// List all profiles from the configuration files
+    List,
+
+    /// Remove a profile
+    Remove {
+        /// Profile name
+        name: String,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct ProfileArgs {
+    /// Profile name
+    pub name: String,
+
+    /// Target WinCC host address
+    #[arg(short = 't', long)]
+    pub target_host: String,
+
+    /// Target WinCC debug port
+    #[arg(short = 'p', long)]
+    pub target_port: Option<u16>,
+
+    /// Local port for Dynamics proxy
+    #[arg(short = 'd', long)]
+    pub dynamics_port: Option<u16>,
+
+    /// Local port for Events proxy
+    #[arg(short = 'e', long)]
+    pub events_port: Option<u16>,
+
+    /// Local port for Tasks proxy
+    #[arg(long)]
+    pub tasks_port: Option<u16>,
+
+    /// Dump directory for this station
+    #[arg(long)]
+    pub dump: Option<String>,
+
+    /// Store the profile in the project-local file instead of the user-level file
+    #[arg(long)]
+    pub project: bool,
+}
+
+impl ProfileArgs {
+    pub fn to_profile(&self) -> Profile {
+        Profile {
+            target_host: Some(self.target_host.clone()),
+            target_port: self.target_port,
+            dynamics_port: self.dynamics_port,
+            events_port: self.events_port,
+            tasks_port: self.tasks_port,
+            dump: self.dump.clone(),
+            contexts: Vec::new(),
+        }
+    }
+}
+
+/// Options of the `dump` command. Target and profile options work as for `run`.
+#[derive(Args, Debug)]
+pub struct DumpArgs {
+    /// Dump directory (defaults to 'dump' from the configuration)
+    pub output: Option<String>,
+
+    /// Named target profile from the configuration files (repeat to dump several stations)
+    #[arg(short = 'P', long)]
+    pub profile: Vec<String>,
+
+    /// Target WinCC host address [default: localhost]
+    #[arg(short = 't', long)]
+    pub target_host: Option<String>,
+
+    /// Target WinCC debug port [default: 9222]
+    #[arg(short = 'p', long)]
+    pub target_port: Option<u16>,
+
+    /// Keep running and dump scripts as they load, following target changes
+    #[arg(short = 'w', long)]
+    pub watch: bool,
+
+    /// Seconds to wait for each context's scripts before giving up
+    #[arg(long, default_value_t = 30)]
+    pub timeout: u64,
+
+    /// Keep the scripts of earlier targets in .history/ instead of deleting them
+    #[arg(long, overrides_with = "no_history")]
+    pub history: bool,
+
+    /// Delete the scripts of earlier targets (overrides the configuration files)
+    #[arg(long, overrides_with = "history")]
+    pub no_history: bool,
+
+    /// Make the dump directory a git repository and commit every completed dump
+    #[arg(long, overrides_with = "no_git")]
+    pub git: bool,
+
+    /// Do not commit the dump (overrides the configuration files)
+    #[arg(long, overrides_with = "git")]
+    pub no_git: bool,
+
+    /// How scripts are written: unchanged, or pretty-printed next to a .raw.js copy [default: raw]
+    #[arg(long, value_enum)]
+    pub format: Option<DumpFormat>,
+
+    /// Also write every top-level function of a script to its own file, by screen item and event
+    #[arg(long, overrides_with = "no_split")]
+    pub split: bool,
+
+    /// Do not split the scripts (overrides the configuration files)
+    #[arg(long, overrides_with = "split")]
+    pub no_split: bool,
+
+    /// Enable verbose logging
+    #[arg(short = 'v', long)]
+    pub verbose: bool,
+
+    /// Console output: colored text, or one JSON object per line [default: text]
+    #[arg(long, value_enum)]
+    pub log_format: Option<LogFormat>,
+
+    /// Also write the log to this file, uncolored, rotated daily and by size
+    #[arg(long)]
+    pub log_file: Option<String>,
+
+    /// Rotate the log file when it would grow beyond this many megabytes [default: 10]
+    #[arg(long)]
+    pub log_file_size: Option<u64>,
+
+    /// Rotated log files to keep, as <file>.1 (newest) to <file>.N [default: 7]
+    #[arg(long)]
+    pub log_file_keep: Option<usize>,
+
+    /// Most detailed records written to the log file, whatever the console shows [default: verbose]
+    #[arg(long, value_enum)]
+    pub log_file_level: Option<LogLevel>,
+}
+
+impl DumpArgs {
+    /// The equivalent `run` options, so the dump resolves the same stations.
+    pub fn to_run_args(&self) -> RunArgs {
+        RunArgs {
+            profile: self.profile.clone(),
+            target_host: self.target_host.clone(),
+            target_port: self.target_port,
+            verbose: self.verbose,
+            log_format: self.log_format,
+            log_file: self.log_file.clone(),
+            log_file_size: self.log_file_size,
+            log_file_keep: self.log_file_keep,
+            log_file_level: self.log_file_level,
+            dump: self.output.clone(),
+            dump_history: self.history,
+            no_dump_history: self.no_history,
+            dump_git: self.git,
+            no_dump_git: self.no_git,
+            dump_format: self.format,
+            dump_split: self.split,
+            no_dump_split: self.no_split,
+            ..Default::default()
+        }
+    }
+}
+
+/// Options of the `run` command. Unset options fall back to the config files,
+/// then to the built-in defaults.
+#[derive(Args, Debug, Default, Clone)]
+pub struct RunArgs {
+    /// Named target profile from the configuration files (repeat to proxy several stations)
+    #[arg(short = 'P', long)]
+    pub profile: Vec<String>,
+
+    /// Target WinCC host address [default: localhost]
+    #[arg(short = 't', long)]
+    pub target_host: Option<String>,
+
+    /// Target WinCC debug port [default: 9222]
+    #[arg(short = 'p', long)]
+    pub target_port: Option<u16>,
+
+    /// Local port for Dynamics proxy [default: 9230]
+    #[arg(short = 'd', long)]
+    pub dynamics_port: Option<u16>,
+
+    /// Local port for Events proxy [default: 9231]
+    #[arg(short = 'e', long)]
+    pub events_port: Option<u16>,
+
+    /// Local port for Tasks (scheduler / global module) proxy [default: 9232]
+    #[arg(long)]
+    pub tasks_port: Option<u16>,
+
+    /// Poll interval in seconds [default: 1, 5 without a command]
+    #[arg(short = 'i', long)]
+    pub poll_interval: Option<u64>,
+
+    /// Local address the proxy servers listen on [default: 127.0.0.1]. The
+    /// proxy is unauthenticated: only use other addresses on trusted networks
+    #[arg(long = "bind")]
+    pub bind_address: Option<IpAddr>,
+
+    /// Enable verbose logging
+    #[arg(short = 'v', long)]
+    pub verbose: bool,
+
+    /// Enable very verbose logging
+    #[arg(short = 'V', long)]
+    pub very_verbose: bool,
+
+    /// Console output: colored text, or one JSON object per line [default: text]
+    #[arg(long, value_enum)]
+    pub log_format: Option<LogFormat>,
+
+    /// Also write the log to this file, uncolored, rotated daily and by size
+    #[arg(long)]
+    pub log_file: Option<String>,
+
+    /// Rotate the log file when it would grow beyond this many megabytes [default: 10]
+    #[arg(long)]
+    pub log_file_size: Option<u64>,
+
+    /// Rotated log files to keep, as <file>.1 (newest) to <file>.N [default: 7]
+    #[arg(long)]
+    pub log_file_keep: Option<usize>,
+
+    /// Most detailed records written to the log file, whatever the console shows [default: verbose]
+    #[arg(long, value_enum)]
+    pub log_file_level: Option<LogLevel>,
+
+    /// Show full (long) script paths instead of shortened ones
+    #[arg(short = 'l', long, overrides_with = "no_long_paths")]
+    pub long_paths: bool,
+
+    /// Show shortened script paths again (overrides the configuration files)
+    #[arg(long, overrides_with = "long_paths")]
+    pub no_long_paths: bool,
+
+    /// Show scripts as file:// URLs of their dumped copies (needs --dump)
+    #[arg(long, overrides_with = "no_file_urls")]
+    pub file_urls: bool,
+
+    /// Show scripts by their runtime URLs again (overrides the configuration files)
+    #[arg(long, overrides_with = "file_urls")]
+    pub no_file_urls: bool,
+
+    /// Keep VS Code attached when a target changes instead of restarting the proxy server
+    #[arg(short = 'k', long, overrides_with = "no_keep_sessions")]
+    pub keep_sessions: bool,
+
+    /// Restart the proxy server when a target changes (overrides the configuration files)
+    #[arg(long, overrides_with = "keep_sessions")]
+    pub no_keep_sessions: bool,
+
+    /// Continuously dump runtime scripts to local files as they are loaded
+    #[arg(long)]
+    pub dump: Option<String>,
+
+    /// Keep the scripts of earlier targets in the dump directory's .history/ instead of deleting them
+    #[arg(long, overrides_with = "no_dump_history")]
+    pub dump_history: bool,
+
+    /// Delete the scripts of earlier targets (overrides the configuration files)
+    #[arg(long, overrides_with = "dump_history")]
+    pub no_dump_history: bool,
+
+    /// Make the dump directory a git repository and commit after every completed dump
+    #[arg(long, overrides_with = "no_dump_git")]
+    pub dump_git: bool,
+
+    /// Do not commit dumps (overrides the configuration files)
+    #[arg(long, overrides_with = "dump_git")]
+    pub no_dump_git: bool,
+
+    /// How dumped scripts are written: unchanged, or pretty-printed next to a .raw.js copy [default: raw]
+    #[arg(long, value_enum)]
+    pub dump_format: Option<DumpFormat>,
+
+    /// Also write every top-level function of a dumped script to its own file, by screen item and event
+    #[arg(long, overrides_with = "no_dump_split")]
+    pub dump_split: bool,
+
+    /// Do not split dumped scripts (overrides the configuration files)
+    #[arg(long, overrides_with = "dump_split")]
+    pub no_dump_split: bool,
+
+    /// Styleguide version written to the dump directory (v17-v21)
+    #[arg(long)]
+    pub styleguide: Option<String>,
+}
+
+/// A `--flag`/`--no-flag` pair as a configuration layer value: `None` when
+/// neither was given. clap keeps only the last of the two.
+fn switch(on: bool, off: bool) -> Option<bool> {
+    if on {
+        Some(true)
+    } else if off {
+        Some(false)
+    } else {
+        None
+    }
 }
 
+impl RunArgs {
+    /// The command line as the highest-precedence configuration layer.
+    /// Flags that were not given stay `None` so lower layers can fill them in.
+    fn to_file_config(&self) -> FileConfig {
+        FileConfig {
+            target_host: self.target_host.clone(),
+            target_port: self.target_port,
+            dynamics_port: self.dynamics_port,
+            events_port: self.events_port,
+            tasks_port: self.tasks_port,
+            poll_interval: self.poll_interval,
+            bind_address: self.bind_address,
+            log_format: self.log_format,
+            log_file: self.log_file.clone(),
+            log_file_size: self.log_file_size,
+            log_file_keep: self.log_file_keep,
+            log_file_level: self.log_file_level,
+            long_paths: switch(self.long_paths, self.no_long_paths),
+            file_urls: switch(self.file_urls, self.no_file_urls),
+            keep_sessions: switch(self.keep_sessions, self.no_keep_sessions),
+            dump: self.dump.clone(),
+            dump_history: switch(self.dump_history, self.no_dump_history),
+            dump_git: switch(self.dump_git, self.no_dump_git),
+            dump_format: self.dump_format,
+            dump_split: switch(self.dump_split, self.no_dump_split),
+            styleguide: self.styleguide.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+// ============================================================================
+// Configuration Files
+// ============================================================================
+
+pub const CONFIG_FILE_NAME: &str = "wincc-debug-proxy.toml";
+
+/// Contents of a `wincc-debug-proxy.toml` file. Every key is optional.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    pub target_host: Option<String>,
+    pub target_port: Option<u16>,
+    pub dynamics_port: Option<u16>,
+    pub events_port: Option<u16>,
+    pub tasks_port: Option<u16>,
+    pub poll_interval: Option<u64>,
+    pub bind_address: Option<IpAddr>,
+    pub log_format: Option<LogFormat>,
+    pub log_file: Option<String>,
+    pub log_file_size: Option<u64>,
+    pub log_file_keep: Option<usize>,
+    pub log_file_level: Option<LogLevel>,
+    pub long_paths: Option<bool>,
+    pub file_urls: Option<bool>,
+    pub keep_sessions: Option<bool>,
+    pub dump: Option<String>,
+    pub dump_history: Option<bool>,
+    pub dump_git: Option<bool>,
+    pub dump_format: Option<DumpFormat>,
+    pub dump_split: Option<bool>,
+    pub styleguide: Option<String>,
+    pub contexts: Vec<ContextEntry>,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// An additional script context, stored as `[[contexts]]` in a configuration file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ContextEntry {
+    /// Name used in logs and as the dump subfolder
+    #[serde(deserialize_with = "context_name")]
+    pub name: String,
+    /// Case-insensitive substring of the WinCC target title
+    pub title: String,
+    /// Local proxy port
+    pub port: u16,
+}
+
+/// Context names become dump subfolders that are replaced on every target
+/// change, so a name must be a single plain path segment: `..`, `/` or
+/// `C:\x` would point the dump outside the dump directory.
+fn check_context_name(name: &str) -> std::result::Result<(), String> {
+    const RESERVED: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+    if name.trim().is_empty() {
+        return Err("context name is empty".to_string());
+    }
+    if name == "." || name == ".." {
+        return Err(format!("context name '{}' is not a folder name", name));
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| RESERVED.contains(c) || c.is_control())
+    {
+        return Err(format!(
+            "context name '{}' contains '{}'; use a plain folder name",
+            name,
+            c.escape_default()
+        ));
+    }
+    Ok(())
+}
+
+fn context_name<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<String, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    check_context_name(&name).map_err(serde::de::Error::custom)?;
+    Ok(name)
+}
+
+impl FileConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&text)
+            .with_context(|| format!("Invalid configuration in {}", path.display()))
+    }
+}
+
+/// A named WinCC station, stored as `[profiles.<name>]` in a configuration file.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub target_host: Option<String>,
+    pub target_port: Option<u16>,
+    pub dynamics_port: Option<u16>,
+    pub events_port: Option<u16>,
+    pub tasks_port: Option<u16>,
+    pub dump: Option<String>,
+    pub contexts: Vec<ContextEntry>,
+}
+
+impl Profile {
+    /// The profile as a configuration layer between the command line and the files.
+    fn to_file_config(&self) -> FileConfig {
+        FileConfig {
+            target_host: self.target_host.clone(),
+            target_port: self.target_port,
+            dynamics_port: self.dynamics_port,
+            events_port: self.events_port,
+            tasks_port: self.tasks_port,
+            dump: self.dump.clone(),
+            contexts: self.contexts.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+fn read_document(path: &Path) -> Result<toml_edit::DocumentMut> {
+    if !path.exists() {
+        return Ok(toml_edit::DocumentMut::new());
+    }
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    text.parse()
+        .with_context(|| format!("Invalid configuration in {}", path.display()))
+}
+
+/// Write `profile` as `[profiles.<name>]` into the file at `path`, keeping the
+/// rest of the file (including comments) untouched.
+pub fn save_profile(path: &Path, name: &str, profile: &Profile) -> Result<()> {
+    use toml_edit::{Item, Table, value};
+
+    let mut doc = read_document(path)?;
+    let profiles = doc
+        .entry("profiles")
+        .or_insert_with(|| {
+            let mut table = Table::new();
+            table.set_implicit(true);
+            Item::Table(table)
+        })
+        .as_table_mut()
+        .with_context(|| format!("'profiles' in {} is not a table", path.display()))?;
+
+    let mut table = Table::new();
+    if let Some(ref host) = profile.target_host {
+        table["target_host"] = value(host.as_str());
+    }
+    if let Some(port) = profile.target_port {
+        table["target_port"] = value(i64::from(port));
+    }
+    if let Some(port) = profile.dynamics_port {
+        table["dynamics_port"] = value(i64::from(port));
+    }
+    if let Some(port) = profile.events_port {
+        table["events_port"] = value(i64::from(port));
+    }
+    if let Some(port) = profile.tasks_port {
+        table["tasks_port"] = value(i64::from(port));
+    }
+    if let Some(ref dump) = profile.dump {
+        table["dump"] = value(dump.as_str());
+    }
+    profiles.insert(name, Item::Table(table));
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, doc.to_string())
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Remove `[profiles.<name>]` from the file at `path`. Returns false if it was not there.
+pub fn remove_profile(path: &Path, name: &str) -> Result<bool> {
+    let mut doc = read_document(path)?;
+    let removed = doc
+        .get_mut("profiles")
+        .and_then(|p| p.as_table_mut())
+        .and_then(|p| p.remove(name))
+        .is_some();
+    if removed {
+        std::fs::write(path, doc.to_string())
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(removed)
+}
+
+/// The user-level configuration file, e.g. `%APPDATA%\wincc-debug-proxy\wincc-debug-proxy.toml`.
+pub fn user_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("wincc-debug-proxy").join(CONFIG_FILE_NAME))
+}
+
+/// The project-local configuration file in the current directory.
+pub fn project_config_path() -> PathBuf {
+    PathBuf::from(".").join(CONFIG_FILE_NAME)
+}
+
+/// All configuration files that were found, lowest precedence first.
+#[derive(Debug, Default)]
+pub struct ConfigFiles {
+    pub user_path: Option<PathBuf>,
+    pub project_path: PathBuf,
+    pub layers: Vec<(PathBuf, FileConfig)>,
+}
+
+impl ConfigFiles {
+    /// Load the user-level file and the project-local file (or `explicit`, if given).
+    /// Missing default files are skipped; a missing explicit file is an error.
+    pub fn load(explicit: Option<&str>) -> Result<Self> {
+        let user_path = user_config_path();
+        let project_path = explicit
+            .map(PathBuf::from)
+            .unwrap_or_else(project_config_path);
+
+        if explicit.is_some() && !project_path.exists() {
+            anyhow::bail!(
+                "Configuration file {} does not exist",
+                project_path.display()
+            );
+        }
+
+        let mut layers = Vec::new();
+        for path in user_path.iter().chain(std::iter::once(&project_path)) {
+            if path.exists() {
+                layers.push((path.clone(), FileConfig::load(path)?));
+            }
+        }
+
+        Ok(Self {
+            user_path,
+            project_path,
+            layers,
+        })
+    }
+
+    /// The highest-precedence value any file sets for a key.
+    pub fn value<T>(&self, get: impl Fn(&FileConfig) -> Option<T>) -> Option<T> {
+        self.layers.iter().rev().find_map(|(_, file)| get(file))
+    }
+
+    /// Look up a profile by name; project-local profiles shadow user-level ones.
+    pub fn profile(&self, name: &str) -> Option<(&Path, &Profile)> {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|(path, file)| file.profiles.get(name).map(|p| (path.as_path(), p)))
+    }
+
+    /// All profiles by name, with the file that defines them.
+    pub fn profiles(&self) -> BTreeMap<&str, (&Path, &Profile)> {
+        let mut all = BTreeMap::new();
+        for (path, file) in &self.layers {
+            for (name, profile) in &file.profiles {
+                all.insert(name.as_str(), (path.as_path(), profile));
+            }
+        }
+        all
+    }
+}
+
+// ============================================================================
+// Effective Configuration
+// ============================================================================
+
+/// Where an effective configuration value came from.
 #[derive(Debug, Clone)]
-pub struct Configuration {
+pub enum ValueSource {
+    Default,
+    File(PathBuf),
+    Profile(String),
+    CommandLine,
+}
+
+impl std::fmt::Display for ValueSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValueSource::Default => write!(f, "default"),
+            ValueSource::File(path) => write!(f, "{}", path.display()),
+            ValueSource::Profile(name) => write!(f, "profile {}", name),
+            ValueSource::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+/// One line of `config show` output.
+#[derive(Debug, Clone)]
+pub struct ConfigEntry {
+    pub key: String,
+    pub value: String,
+    pub source: ValueSource,
+}
+
+/// Walks the configuration layers (highest precedence first) and records
+/// where each resolved value came from.
+struct Resolver<'a> {
+    layers: Vec<(ValueSource, &'a FileConfig)>,
+    key_prefix: String,
+    entries: Vec<ConfigEntry>,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(
+        cli: &'a FileConfig,
+        profile: Option<(&str, &'a FileConfig)>,
+        files: &'a ConfigFiles,
+    ) -> Self {
+        let mut layers = vec![(ValueSource::CommandLine, cli)];
+        if let Some((name, layer)) = profile {
+            layers.push((ValueSource::Profile(name.to_string()), layer));
+        }
+        for (path, file) in files.layers.iter().rev() {
+            layers.push((ValueSource::File(path.clone()), file));
+        }
+        Self {
+            layers,
+            key_prefix: String::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    fn optional<T: std::fmt::Display>(
+        &mut self,
+        key: &str,
+        get: impl Fn(&FileConfig) -> Option<T>,
+    ) -> Option<T> {
+        let found = self
+            .layers
+            .iter()
+            .find_map(|(source, layer)| get(layer).map(|v| (v, source.clone())));
+        let (value, source) = match found {
+            Some((v, source)) => (Some(v), source),
+            None => (None, ValueSource::Default),
+        };
+        self.entries.push(ConfigEntry {
+            key: format!("{}{}", self.key_prefix, key),
+            value: value
+                .as_ref()
+                .map_or_else(|| "(none)".to_string(), |v| v.to_string()),
+            source,
+        });
+        value
+    }
+
+    fn value<T: std::fmt::Display>(
+        &mut self,
+        key: &str,
+        get: impl Fn(&FileConfig) -> Option<T>,
+        default: T,
+    ) -> T {
+        match self.optional(key, get) {
+            Some(v) => v,
+            None => {
+                self.entries.last_mut().unwrap().value = default.to_string();
+                default
+            }
+        }
+    }
+}
+
+/// How dumped scripts are written to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DumpFormat {
+    /// The source exactly as the runtime reports it
+    Raw,
+    /// Pretty-printed, with the runtime's source kept as `<name>.raw.js`
+    Pretty,
+}
+
+impl std::fmt::Display for DumpFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DumpFormat::Raw => write!(f, "raw"),
+            DumpFormat::Pretty => write!(f, "pretty"),
+        }
+    }
+}
+
+/// How log output is written to the console.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Colored lines for people
+    #[default]
+    Text,
+    /// One JSON object per line, for log collectors and scripts
+    Json,
+}
+
+impl std::fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// The most detailed records a log file gets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogLevel {
+    /// Everything but verbose records
+    Info,
+    /// Also verbose records, like dump progress
+    Verbose,
+    /// Also per-message records
+    VeryVerbose,
+}
+
+impl std::fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogLevel::Info => write!(f, "info"),
+            LogLevel::Verbose => write!(f, "verbose"),
+            LogLevel::VeryVerbose => write!(f, "very-verbose"),
+        }
+    }
+}
+
+pub const DEFAULT_DYNAMICS_PORT: u16 = 9230;
+pub const DEFAULT_EVENTS_PORT: u16 = 9231;
+pub const DEFAULT_TASKS_PORT: u16 = 9232;
+
+/// Title patterns of the scheduler and global module targets. Targets that
+/// match no context at all are not proxied.
+const TASKS_TITLES: &[&str] = &["scheduler", "globalmodule", "global module"];
+
+/// A WinCC script context (V8 isolate) that gets its own proxy port.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ContextKind {
+    Dynamics,
+    Events,
+    /// Scheduled tasks and global modules
+    Tasks,
+    /// A context configured with `[[contexts]]`
+    Custom(String),
+}
+
+impl ContextKind {
+    pub fn name(&self) -> &str {
+        match self {
+            ContextKind::Dynamics => "Dynamics",
+            ContextKind::Events => "Events",
+            ContextKind::Tasks => "Tasks",
+            ContextKind::Custom(name) => name,
+        }
+    }
+}
+
+impl std::fmt::Display for ContextKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// How a context recognises its targets on the WinCC `/json` list.
+#[derive(Debug, Clone)]
+pub enum TitleMatch {
+    /// Case-insensitive substring of the target title
+    Contains(String),
+    /// Any of several case-insensitive substrings
+    ContainsAny(Vec<String>),
+}
+
+/// Maps WinCC targets onto a local port.
+#[derive(Debug, Clone)]
+pub struct ContextSpec {
+    pub kind: ContextKind,
+    pub title: TitleMatch,
+    pub port: u16,
+}
+
+impl ContextSpec {
+    pub fn dynamics(port: u16) -> Self {
+        Self {
+            kind: ContextKind::Dynamics,
+            title: TitleMatch::Contains("dynamics".to_string()),
+            port,
+        }
+    }
+
+    pub fn events(port: u16) -> Self {
+        Self {
+            kind: ContextKind::Events,
+            title: TitleMatch::Contains("events".to_string()),
+            port,
+        }
+    }
+
+    pub fn tasks(port: u16) -> Self {
+        Self {
+            kind: ContextKind::Tasks,
+            title: TitleMatch::ContainsAny(TASKS_TITLES.iter().map(|t| t.to_string()).collect()),
+            port,
+        }
+    }
+
+    /// Whether the title contains this context's pattern.
+    fn matches_pattern(&self, title: &str) -> bool {
+        let title = title.to_lowercase();
+        match self.title {
+            TitleMatch::Contains(ref pattern) => title.contains(&pattern.to_lowercase()),
+            TitleMatch::ContainsAny(ref patterns) => {
+                patterns.iter().any(|p| title.contains(&p.to_lowercase()))
+            }
+        }
+    }
+}
+
+impl From<&ContextEntry> for ContextSpec {
+    fn from(entry: &ContextEntry) -> Self {
+        Self {
+            kind: ContextKind::Custom(entry.name.clone()),
+            title: TitleMatch::Contains(entry.title.clone()),
+            port: entry.port,
+        }
+    }
+}
+
+/// `[[contexts]]` entries formatted for `config show`.
+struct ContextList(Vec<ContextEntry>);
+
+impl std::fmt::Display for ContextList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let items: Vec<String> = self
+            .0
+            .iter()
+            .map(|c| format!("{}={} (\"{}\")", c.name, c.port, c.title))
+            .collect();
+        f.write_str(&items.join(", "))
+    }
+}
+
+/// One WinCC runtime the proxy connects to, with a local port per script context.
+#[derive(Debug, Clone)]
+pub struct Station {
+    /// Profile name, if the station was selected with `--profile`
+    pub name: Option<String>,
     pub target_host: String,
     pub target_port: u16,
-    pub dynamics_port: u16,
-    pub events_port: u16,
+    pub contexts: Vec<ContextSpec>,
+    pub dump_output: Option<String>,
+}
+
+impl Default for Station {
+    fn default() -> Self {
+        Self {
+            name: None,
+            target_host: "localhost".to_string(),
+            target_port: 9222,
+            contexts: vec![
+                ContextSpec::dynamics(DEFAULT_DYNAMICS_PORT),
+                ContextSpec::events(DEFAULT_EVENTS_PORT),
+                ContextSpec::tasks(DEFAULT_TASKS_PORT),
+            ],
+            dump_output: None,
+        }
+    }
+}
+
+impl Station {
+    /// Identifies one of the station's contexts in events.
+    pub fn origin(&self, kind: &ContextKind) -> EventOrigin {
+        EventOrigin {
+            station: self.name.clone(),
+            context: kind.clone(),
+        }
+    }
+
+    /// Prefix for log lines, e.g. `"[line3] "`; empty for an unnamed station.
+    pub fn prefix(&self) -> String {
+        match self.name {
+            Some(ref name) => format!("[{}] ", name),
+            None => String::new(),
+        }
+    }
+
+    /// Whether a WinCC target with this title belongs to `spec`. Tasks
+    /// leaves targets that a `[[contexts]]` entry maps to its own port.
+    pub fn matches(&self, spec: &ContextSpec, title: &str) -> bool {
+        if !spec.matches_pattern(title) {
+            return false;
+        }
+        spec.kind != ContextKind::Tasks
+            || !self
+                .contexts
+                .iter()
+                .any(|c| matches!(c.kind, ContextKind::Custom(_)) && c.matches_pattern(title))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Configuration {
+    pub stations: Vec<Station>,
     pub poll_interval: u64,
+    /// Local address every context's server listens on
+    pub bind_address: IpAddr,
     pub verbose: bool,
     pub very_verbose: bool,
+    pub log_format: LogFormat,
+    /// File the log is also written to, see `logging::LogFile`
+    pub log_file: Option<String>,
+    /// Megabytes after which the log file is rotated
+    pub log_file_size: u64,
+    pub log_file_keep: usize,
+    pub log_file_level: LogLevel,
     pub long_paths: bool,
-    pub dump_output: Option<String>,
+    pub file_urls: bool,
+    pub keep_sessions: bool,
+    pub dump_history: bool,
+    pub dump_git: bool,
+    pub dump_format: DumpFormat,
+    pub dump_split: bool,
     pub styleguide_version: Option<String>,
 }
 
 impl Configuration {
-    pub fn from_run_command(
-        target_host: String,
-        target_port: u16,
-        dynamics_port: u16,
-        events_port: u16,
-        poll_interval: u64,
-        verbose: bool,
-        very_verbose: bool,
-        long_paths: bool,
-        dump_output: Option<String>,
-        styleguide_version: Option<String>,
-    ) -> Self {
+    /// Merge command line flags over the selected profiles over the config files
+    /// over the defaults. Returns the configuration and the origin of every
+    /// file-configurable value.
+    pub fn resolve(args: &RunArgs, files: &ConfigFiles) -> Result<(Self, Vec<ConfigEntry>)> {
+        Self::resolve_with_defaults(Self::default(), args, files)
+    }
+
+    /// `resolve` with other built-in defaults, e.g. `run_defaults`.
+    pub fn resolve_with_defaults(
+        defaults: Self,
+        args: &RunArgs,
+        files: &ConfigFiles,
+    ) -> Result<(Self, Vec<ConfigEntry>)> {
+        let station_defaults = Station::default();
+        let cli = args.to_file_config();
+        let multiple = args.profile.len() > 1;
+
+        if multiple
+            && (cli.target_host.is_some()
+                || cli.target_port.is_some()
+                || cli.dynamics_port.is_some()
+                || cli.events_port.is_some()
+                || cli.tasks_port.is_some())
+        {
+            anyhow::bail!(
+                "--target-host, --target-port and the local port flags cannot be combined \
+                 with multiple profiles; set them in the profiles instead"
+            );
+        }
+
+        let names: Vec<Option<&str>> = if args.profile.is_empty() {
+            vec![None]
+        } else {
+            args.profile.iter().map(|n| Some(n.as_str())).collect()
+        };
+
+        let mut entries = Vec::new();
+        let mut stations = Vec::new();
+        for name in names {
+            let profile_layer = match name {
+                Some(name) => {
+                    let (_, profile) = files.profile(name).with_context(|| {
+                        format!(
+                            "Unknown profile '{}'. Use 'profile list' to see all profiles",
+                            name
+                        )
+                    })?;
+                    Some((name, profile.to_file_config()))
+                }
+                None => None,
+            };
+            let mut r = Resolver::new(
+                &cli,
+                profile_layer.as_ref().map(|(name, layer)| (*name, layer)),
+                files,
+            );
+            if multiple && let Some(name) = name {
+                r.key_prefix = format!("{}.", name);
+            }
+
+            let d = &station_defaults;
+            let target_host = r.value(
+                "target_host",
+                |f| f.target_host.clone(),
+                d.target_host.clone(),
+            );
+            let target_port = r.value("target_port", |f| f.target_port, d.target_port);
+            let dynamics_port =
+                r.value("dynamics_port", |f| f.dynamics_port, DEFAULT_DYNAMICS_PORT);
+            let events_port = r.value("events_port", |f| f.events_port, DEFAULT_EVENTS_PORT);
+            let tasks_port = r.value("tasks_port", |f| f.tasks_port, DEFAULT_TASKS_PORT);
+            let dump_output = r.optional("dump", |f| f.dump.clone());
+            let custom = r.value(
+                "contexts",
+                |f| (!f.contexts.is_empty()).then(|| ContextList(f.contexts.clone())),
+                ContextList(Vec::new()),
+            );
+
+            let mut contexts = vec![
+                ContextSpec::dynamics(dynamics_port),
+                ContextSpec::events(events_port),
+                ContextSpec::tasks(tasks_port),
+            ];
+            for entry in &custom.0 {
+                if contexts
+                    .iter()
+                    .any(|c| c.kind.name().eq_ignore_ascii_case(&entry.name))
+                {
+                    anyhow::bail!("Context name '{}' is used more than once", entry.name);
+                }
+                contexts.push(ContextSpec::from(entry));
+            }
+
+            stations.push(Station {
+                name: name.map(String::from),
+                target_host,
+                target_port,
+                contexts,
+                dump_output,
+            });
+            entries.extend(r.entries);
+        }
+
+        separate_shared_dump_dirs(&mut stations);
+        check_port_conflicts(&stations)?;
+
+        let mut r = Resolver::new(&cli, None, files);
+        let cfg = Self {
+            stations,
+            poll_interval: r.value("poll_interval", |f| f.poll_interval, defaults.poll_interval),
+            bind_address: r.value("bind_address", |f| f.bind_address, defaults.bind_address),
+            verbose: args.verbose,
+            very_verbose: args.very_verbose,
+            log_format: r.value("log_format", |f| f.log_format, defaults.log_format),
+            log_file: r.optional("log_file", |f| f.log_file.clone()),
+            log_file_size: r.value("log_file_size", |f| f.log_file_size, defaults.log_file_size),
+            log_file_keep: r.value("log_file_keep", |f| f.log_file_keep, defaults.log_file_keep),
+            log_file_level: r.value(
+                "log_file_level",
+                |f| f.log_file_level,
+                defaults.log_file_level,
+            ),
+            long_paths: r.value("long_paths", |f| f.long_paths, defaults.long_paths),
+            file_urls: r.value("file_urls", |f| f.file_urls, defaults.file_urls),
+            keep_sessions: r.value("keep_sessions", |f| f.keep_sessions, defaults.keep_sessions),
+            dump_history: r.value("dump_history", |f| f.dump_history, defaults.dump_history),
+            dump_git: r.value("dump_git", |f| f.dump_git, defaults.dump_git),
+            dump_format: r.value("dump_format", |f| f.dump_format, defaults.dump_format),
+            dump_split: r.value("dump_split", |f| f.dump_split, defaults.dump_split),
+            styleguide_version: r.optional("styleguide", |f| f.styleguide.clone()),
+        };
+        entries.extend(r.entries);
+
+        if cfg.log_file_size == 0 {
+            anyhow::bail!("log_file_size is in megabytes and must be at least 1");
+        }
+
+        if cfg.file_urls
+            && let Some(station) = cfg.stations.iter().find(|s| s.dump_output.is_none())
+        {
+            anyhow::bail!(
+                "file_urls points scripts at their dumped copies and needs a dump directory{}; \
+                 set --dump or 'dump' in the configuration",
+                station
+                    .name
+                    .as_ref()
+                    .map_or(String::new(), |n| format!(" for profile '{}'", n))
+            );
+        }
+
+        Ok((cfg, entries))
+    }
+
+    /// Every distinct dump directory across all stations.
+    pub fn dump_dirs(&self) -> Vec<&str> {
+        let mut dirs: Vec<&str> = Vec::new();
+        for dir in self
+            .stations
+            .iter()
+            .filter_map(|s| s.dump_output.as_deref())
+        {
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+        dirs
+    }
+}
+
+impl Configuration {
+    /// Defaults of the `run` command, which polls faster than a bare start.
+    pub fn run_defaults() -> Self {
         Self {
-            target_host,
-            target_port,
-            dynamics_port,
-            events_port,
-            poll_interval,
-            verbose,
-            very_verbose,
-            long_paths,
-            dump_output,
-            styleguide_version,
+            poll_interval: 1,
+            ..Self::default()
         }
     }
+}
 
-    pub fn default() -> Self {
+impl Default for Configuration {
+    fn default() -> Self {
         Self {
-            target_host: "localhost".to_string(),
-            target_port: 9222,
-            dynamics_port: 9230,
-            events_port: 9231,
+            stations: vec![Station::default()],
             poll_interval: 5,
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
             verbose: false,
             very_verbose: false,
+            log_format: LogFormat::Text,
+            log_file: None,
+            log_file_size: 10,
+            log_file_keep: 7,
+            log_file_level: LogLevel::Verbose,
             long_paths: false,
-            dump_output: None,
+            file_urls: false,
+            keep_sessions: false,
+            dump_history: false,
+            dump_git: false,
+            dump_format: DumpFormat::Raw,
+            dump_split: false,
             styleguide_version: None,
         }
     }
 }
 
-pub static CONFIG: OnceLock<Configuration> = OnceLock::new();
+/// Stations that would dump into the same directory get a subdirectory per
+/// station name instead, so their Dynamics/Events folders don't collide.
+fn separate_shared_dump_dirs(stations: &mut [Station]) {
+    let dirs: Vec<Option<String>> = stations.iter().map(|s| s.dump_output.clone()).collect();
+    for station in stations.iter_mut() {
+        let (Some(dir), Some(name)) = (station.dump_output.clone(), station.name.clone()) else {
+            continue;
+        };
+        let shared = dirs
+            .iter()
+            .filter(|d| d.as_deref() == Some(dir.as_str()))
+            .count()
+            > 1;
+        if shared {
+            station.dump_output = Some(
+                std::path::Path::new(&dir)
+                    .join(name)
+                    .to_string_lossy()
+                    .into_owned(),
+            );
+        }
+    }
+}
+
+fn check_port_conflicts(stations: &[Station]) -> Result<()> {
+    let mut used: Vec<u16> = Vec::new();
+    for station in stations {
+        for context in &station.contexts {
+            if used.contains(&context.port) {
+                anyhow::bail!(
+                    "Local port {} ({}{}) is used more than once; every context of every \
+                     station needs its own port",
+                    context.port,
+                    station.prefix(),
+                    context.kind
+                );
+            }
+            used.push(context.port);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn port_of(station: &Station, kind: &ContextKind) -> Option<u16> {
+        station
+            .contexts
+            .iter()
+            .find(|c| &c.kind == kind)
+            .map(|c| c.port)
+    }
+
+    fn files_with(file: FileConfig) -> ConfigFiles {
+        ConfigFiles {
+            layers: vec![(PathBuf::from("project.toml"), file)],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn resolve_uses_defaults_without_files_or_flags() {
+        let (cfg, entries) =
+            Configuration::resolve(&RunArgs::default(), &ConfigFiles::default()).unwrap();
+        assert_eq!(cfg.stations.len(), 1);
+        assert_eq!(cfg.stations[0].target_host, "localhost");
+        assert_eq!(cfg.stations[0].target_port, 9222);
+        assert_eq!(cfg.poll_interval, 5);
+        assert!(cfg.stations[0].dump_output.is_none());
+        assert!(
+            entries
+                .iter()
+                .all(|e| matches!(e.source, ValueSource::Default))
+        );
+    }
+
+    #[test]
+    fn resolve_file_value_overrides_default() {
+        let files = files_with(FileConfig {
+            target_host: Some("192.168.1.100".into()),
+            dump: Some("./output".into()),
+            ..Default::default()
+        });
+        let (cfg, entries) = Configuration::resolve(&RunArgs::default(), &files).unwrap();
+        assert_eq!(cfg.stations[0].target_host, "192.168.1.100");
+        assert_eq!(cfg.stations[0].dump_output.as_deref(), Some("./output"));
+        let host = entries.iter().find(|e| e.key == "target_host").unwrap();
+        assert!(matches!(host.source, ValueSource::File(_)));
+    }
+
+    #[test]
+    fn resolve_cli_flag_overrides_file_value() {
+        let files = files_with(FileConfig {
+            target_port: Some(9333),
+            ..Default::default()
+        });
+        let args = RunArgs {
+            target_port: Some(9444),
+            ..Default::default()
+        };
+        let (cfg, entries) = Configuration::resolve(&args, &files).unwrap();
+        assert_eq!(cfg.stations[0].target_port, 9444);
+        let port = entries.iter().find(|e| e.key == "target_port").unwrap();
+        assert!(matches!(port.source, ValueSource::CommandLine));
+    }
+
+    #[test]
+    fn resolve_project_file_overrides_user_file() {
+        let files = ConfigFiles {
+            layers: vec![
+                (
+                    PathBuf::from("user.toml"),
+                    FileConfig {
+                        poll_interval: Some(10),
+                        events_port: Some(9300),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    PathBuf::from("project.toml"),
+                    FileConfig {
+                        poll_interval: Some(2),
+                        ..Default::default()
+                    },
+                ),
+            ],
+            ..Default::default()
+        };
+        let (cfg, _) = Configuration::resolve(&RunArgs::default(), &files).unwrap();
+        assert_eq!(cfg.poll_interval, 2);
+        assert_eq!(port_of(&cfg.stations[0], &ContextKind::Events), Some(9300));
+    }
+
+    #[test]
+    fn resolve_profile_sits_between_cli_and_files() {
+        let files = files_with(
+            toml::from_str(
+                r#"
+            target_host = "localhost"
+            poll_interval = 4
+
+            [profiles.line3]
+            target_host = "192.168.3.10"
+            target_port = 9333
+            "#,
+            )
+            .unwrap(),
+        );
+        let args = RunArgs {
+            profile: vec!["line3".into()],
+            target_port: Some(9444),
+            ..Default::default()
+        };
+        let (cfg, entries) = Configuration::resolve(&args, &files).unwrap();
+        let station = &cfg.stations[0];
+        assert_eq!(station.name.as_deref(), Some("line3"));
+        assert_eq!(station.target_host, "192.168.3.10");
+        assert_eq!(station.target_port, 9444);
+        assert_eq!(cfg.poll_interval, 4);
+        let host = entries.iter().find(|e| e.key == "target_host").unwrap();
+        assert!(matches!(host.source, ValueSource::Profile(ref n) if n == "line3"));
+    }
+
+    #[test]
+    fn resolve_unknown_profile_is_an_error() {
+        let args = RunArgs {
+            profile: vec!["nope".into()],
+            ..Default::default()
+        };
+        assert!(Configuration::resolve(&args, &ConfigFiles::default()).is_err());
+    }
+
+    fn two_station_files() -> ConfigFiles {
+        files_with(
+            toml::from_str(
+                r#"
+            dump = "./output"
+
+            [profiles.line3]
+            target_host = "192.168.3.10"
+
+            [profiles.sim]
+            target_host = "192.168.9.2"
+            dynamics_port = 9240
+            events_port = 9241
+            tasks_port = 9242
+            "#,
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn resolve_multiple_profiles_gives_one_station_each() {
+        let args = RunArgs {
+            profile: vec!["line3".into(), "sim".into()],
+            ..Default::default()
+        };
+        let (cfg, entries) = Configuration::resolve(&args, &two_station_files()).unwrap();
+        assert_eq!(cfg.stations.len(), 2);
+        assert_eq!(cfg.stations[0].target_host, "192.168.3.10");
+        assert_eq!(
+            port_of(&cfg.stations[0], &ContextKind::Dynamics),
+            Some(9230)
+        );
+        assert_eq!(cfg.stations[1].target_host, "192.168.9.2");
+        assert_eq!(port_of(&cfg.stations[1], &ContextKind::Events), Some(9241));
+        assert!(entries.iter().any(|e| e.key == "sim.target_host"));
+
+        // Both inherit the top-level dump dir, so each gets its own subdirectory
+        let line3_dump = std::path::Path::new("./output").join("line3");
+        assert_eq!(cfg.stations[0].dump_output.as_deref(), line3_dump.to_str());
+        assert_eq!(cfg.dump_dirs().len(), 2);
+    }
+
+    #[test]
+    fn resolve_multiple_profiles_rejects_port_conflicts() {
+        let mut files = two_station_files();
+        files.layers[0]
+            .1
+            .profiles
+            .get_mut("sim")
+            .unwrap()
+            .dynamics_port = Some(9230);
+        let args = RunArgs {
+            profile: vec!["line3".into(), "sim".into()],
+            ..Default::default()
+        };
+        assert!(Configuration::resolve(&args, &files).is_err());
+    }
+
+    #[test]
+    fn resolve_multiple_profiles_rejects_station_flags() {
+        let args = RunArgs {
+            profile: vec!["line3".into(), "sim".into()],
+            target_host: Some("10.0.0.1".into()),
+            ..Default::default()
+        };
+        assert!(Configuration::resolve(&args, &two_station_files()).is_err());
+    }
+
+    #[test]
+    fn resolve_custom_contexts_from_file() {
+        let files = files_with(
+            toml::from_str(
+                r#"
+            [[contexts]]
+            name = "Simulation"
+            title = "simulation"
+            port = 9240
+            "#,
+            )
+            .unwrap(),
+        );
+        let (cfg, _) = Configuration::resolve(&RunArgs::default(), &files).unwrap();
+        let station = &cfg.stations[0];
+        assert_eq!(station.contexts.len(), 4);
+        let custom = ContextKind::Custom("Simulation".into());
+        assert_eq!(port_of(station, &custom), Some(9240));
+        assert!(station.matches(&station.contexts[3], " @localhost VCS_3 Simulation"));
+        assert!(!station.matches(&station.contexts[3], " @localhost VCS_3 Dynamics"));
+    }
+
+    #[test]
+    fn tasks_context_matches_scheduler_and_global_module_targets_only() {
+        let mut station = Station::default();
+        let tasks = ContextSpec::tasks(DEFAULT_TASKS_PORT);
+        assert!(station.matches(&tasks, " @localhost VCS_3 GlobalModules"));
+        assert!(station.matches(&tasks, " @localhost VCS_3 Scheduler"));
+        assert!(!station.matches(&tasks, " @localhost VCS_3 Dynamics"));
+        assert!(!station.matches(&tasks, " @localhost VCS_3 Events"));
+        // Unknown or future targets are not proxied on the Tasks port
+        assert!(!station.matches(&tasks, " @localhost VCS_3 Simulation"));
+        assert!(station.matches(&station.contexts[0], " @localhost VCS_3 Dynamics"));
+
+        station.contexts.push(ContextSpec::from(&ContextEntry {
+            name: "Scheduler".to_string(),
+            title: "scheduler".to_string(),
+            port: 9240,
+        }));
+        assert!(!station.matches(&tasks, " @localhost VCS_3 Scheduler"));
+        assert!(station.matches(&station.contexts[3], " @localhost VCS_3 Scheduler"));
+    }
+
+    #[test]
+    fn resolve_custom_context_port_conflict_is_an_error() {
+        let files = files_with(
+            toml::from_str(
+                r#"
+            [[contexts]]
+            name = "Scheduler"
+            title = "scheduler"
+            port = 9231
+            "#,
+            )
+            .unwrap(),
+        );
+        assert!(Configuration::resolve(&RunArgs::default(), &files).is_err());
+    }
+
+    #[test]
+    fn save_and_remove_profile_keep_other_content() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("wincc-debug-proxy.toml");
+        std::fs::write(&path, "# my settings\ntarget_port = 9222\n").unwrap();
+
+        let profile = Profile {
+            target_host: Some("10.0.0.7".into()),
+            dynamics_port: Some(9240),
+            ..Default::default()
+        };
+        save_profile(&path, "sim", &profile).unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.contains("# my settings"));
+        let parsed = FileConfig::load(&path).unwrap();
+        assert_eq!(parsed.target_port, Some(9222));
+        assert_eq!(
+            parsed.profiles["sim"].target_host.as_deref(),
+            Some("10.0.0.7")
+        );
+        assert_eq!(parsed.profiles["sim"].dynamics_port, Some(9240));
+
+        assert!(remove_profile(&path, "sim").unwrap());
+        assert!(!remove_profile(&path, "sim").unwrap());
+        assert!(FileConfig::load(&path).unwrap().profiles.is_empty());
+    }
+
+    #[test]
+    fn file_config_rejects_unknown_keys() {
+        assert!(toml::from_str::<FileConfig>("target_hots = \"x\"").is_err());
+        let parsed: FileConfig = toml::from_str("target_port = 9229\nlong_paths = true").unwrap();
+        assert_eq!(parsed.target_port, Some(9229));
+        assert_eq!(parsed.long_paths, Some(true));
+    }
+
+    #[test]
+    fn file_urls_require_dump_directory() {
+        let args = RunArgs {
+            file_urls: true,
+            ..Default::default()
+        };
+        assert!(Configuration::resolve(&args, &ConfigFiles::default()).is_err());
+
+        let args = RunArgs {
+            file_urls: true,
+            dump: Some("./out".into()),
+            ..Default::default()
+        };
+        let (cfg, _) = Configuration::resolve(&args, &ConfigFiles::default()).unwrap();
+        assert!(cfg.file_urls);
+    }
+
+    #[test]
+    fn dump_format_from_file_or_flag() {
+        let (cfg, _) =
+            Configuration::resolve(&RunArgs::default(), &ConfigFiles::default()).unwrap();
+        assert_eq!(cfg.dump_format, DumpFormat::Raw);
+
+        let files = files_with(toml::from_str(r#"dump_format = "pretty""#).unwrap());
+        let (cfg, entries) = Configuration::resolve(&RunArgs::default(), &files).unwrap();
+        assert_eq!(cfg.dump_format, DumpFormat::Pretty);
+        assert!(
+            entries
+                .iter()
+                .any(|e| e.key == "dump_format" && e.value == "pretty")
+        );
+
+        let args = RunArgs {
+            dump_format: Some(DumpFormat::Raw),
+            ..Default::default()
+        };
+        let (cfg, _) = Configuration::resolve(&args, &files).unwrap();
+        assert_eq!(cfg.dump_format, DumpFormat::Raw);
+    }
+
+    #[test]
+    fn dump_history_from_file_or_flag() {
+        let (cfg, _) =
+            Configuration::resolve(&RunArgs::default(), &ConfigFiles::default()).unwrap();
+        assert!(!cfg.dump_history);
+
+        let files = files_with(FileConfig {
+            dump_history: Some(true),
+            ..Default::default()
+        });
+        let (cfg, _) = Configuration::resolve(&RunArgs::default(), &files).unwrap();
+        assert!(cfg.dump_history);
 
-pub fn config() -> &'static Configuration {
-    CONFIG.get().expect("Configuration not initialized")
+        let args = RunArgs {
+            dump_history: true,
+            ..Default::default()
+        };
+        let (cfg, _) = Configuration::resolve(&args, &ConfigFiles::default()).unwrap();
+        assert!(cfg.dump_history);
+    }
+
+    #[test]
+    fn keep_sessions_from_file_or_flag() {
+        let files = files_with(FileConfig {
+            keep_sessions: Some(true),
+            ..Default::default()
+        });
+        let (cfg, _) = Configuration::resolve(&RunArgs::default(), &files).unwrap();
+        assert!(cfg.keep_sessions);
+
+        let args = RunArgs {
+            keep_sessions: true,
+            ..Default::default()
+        };
+        let (cfg, entries) = Configuration::resolve(&args, &ConfigFiles::default()).unwrap();
+        assert!(cfg.keep_sessions);
+        let entry = entries.iter().find(|e| e.key == "keep_sessions").unwrap();
+        assert!(matches!(entry.source, ValueSource::CommandLine));
+    }
+
+    #[test]
+    fn bind_address_from_file_or_flag() {
+        let (cfg, _) =
+            Configuration::resolve(&RunArgs::default(), &ConfigFiles::default()).unwrap();
+        assert_eq!(cfg.bind_address, IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+        let files = files_with(toml::from_str(r#"bind_address = "0.0.0.0""#).unwrap());
+        let (cfg, entries) = Configuration::resolve(&RunArgs::default(), &files).unwrap();
+        assert_eq!(cfg.bind_address, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        assert!(
+            entries
+                .iter()
+                .any(|e| e.key == "bind_address" && e.value == "0.0.0.0")
+        );
+
+        let args = RunArgs {
+            bind_address: Some("::1".parse().unwrap()),
+            ..Default::default()
+        };
+        let (cfg, _) = Configuration::resolve(&args, &files).unwrap();
+        assert_eq!(cfg.bind_address.to_string(), "::1");
+    }
+
+    #[test]
+    fn log_format_from_file_or_flag() {
+        let (cfg, _) =
+            Configuration::resolve(&RunArgs::default(), &ConfigFiles::default()).unwrap();
+        assert_eq!(cfg.log_format, LogFormat::Text);
+
+        let files = files_with(toml::from_str(r#"log_format = "json""#).unwrap());
+        let (cfg, _) = Configuration::resolve(&RunArgs::default(), &files).unwrap();
+        assert_eq!(cfg.log_format, LogFormat::Json);
+
+        let args = RunArgs {
+            log_format: Some(LogFormat::Text),
+            ..Default::default()
+        };
+        let (cfg, _) = Configuration::resolve(&args, &files).unwrap();
+        assert_eq!(cfg.log_format, LogFormat::Text);
+    }
+
+    #[test]
+    fn log_file_settings_from_file_or_flag() {
+        let (cfg, _) =
+            Configuration::resolve(&RunArgs::default(), &ConfigFiles::default()).unwrap();
+        assert_eq!(cfg.log_file, None);
+        assert_eq!(cfg.log_file_level, LogLevel::Verbose);
+
+        let files = files_with(
+            toml::from_str(
+                r#"
+                log_file = "proxy.log"
+                log_file_keep = 3
+                log_file_level = "very-verbose"
+                "#,
+            )
+            .unwrap(),
+        );
+        let args = RunArgs {
+            log_file_size: Some(1),
+            ..Default::default()
+        };
+        let (cfg, _) = Configuration::resolve(&args, &files).unwrap();
+        assert_eq!(cfg.log_file.as_deref(), Some("proxy.log"));
+        assert_eq!(cfg.log_file_size, 1);
+        assert_eq!(cfg.log_file_keep, 3);
+        assert_eq!(cfg.log_file_level, LogLevel::VeryVerbose);
+
+        let args = RunArgs {
+            log_file_size: Some(0),
+            ..Default::default()
+        };
+        assert!(Configuration::resolve(&args, &files).is_err());
+    }
+
+    #[test]
+    fn run_command_polls_faster_unless_configured() {
+        let run = |files: &ConfigFiles| {
+            Configuration::resolve_with_defaults(
+                Configuration::run_defaults(),
+                &RunArgs::default(),
+                files,
+            )
+            .unwrap()
+            .0
+        };
+        assert_eq!(run(&ConfigFiles::default()).poll_interval, 1);
+        let files = files_with(toml::from_str("poll_interval = 3").unwrap());
+        assert_eq!(run(&files).poll_interval, 3);
+    }
+
+    #[test]
+    fn no_flags_turn_off_switches_from_the_files() {
+        let files = files_with(toml::from_str("long_paths = true\ndump_git = true").unwrap());
+        let cli = Cli::try_parse_from(["proxy", "run", "--no-long-paths"]).unwrap();
+        let Some(Commands::Run(args)) = cli.command else {
+            panic!("not a run command")
+        };
+        let (cfg, _) = Configuration::resolve(&args, &files).unwrap();
+        assert!(!cfg.long_paths);
+        assert!(cfg.dump_git);
+
+        // The last of --flag and --no-flag wins
+        let cli = Cli::try_parse_from(["proxy", "run", "--no-dump-git", "--dump-git"]).unwrap();
+        let Some(Commands::Run(args)) = cli.command else {
+            panic!("not a run command")
+        };
+        assert_eq!(args.to_file_config().dump_git, Some(true));
+    }
+
+    #[test]
+    fn context_names_must_be_plain_folder_names() {
+        let entry = |name: &str| {
+            toml::from_str::<FileConfig>(&format!(
+                "[[contexts]]\nname = '{}'\ntitle = 'scheduler'\nport = 9240",
+                name
+            ))
+        };
+        assert!(entry("Scheduler").is_ok());
+        assert!(entry("Alarm Scripts").is_ok());
+        for name in ["..", ".", "", "a/b", "/", "C:\\x", "..\\up"] {
+            assert!(entry(name).is_err(), "{:?} was accepted", name);
+        }
+    }
 }
@@ -0,0 +1,361 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use similar::TextDiff;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::urlmap::shorten_dumped_path;
+
+// ============================================================================
+// Dump Comparison
+// ============================================================================
+
+/// Lines of unchanged code shown around each change.
+const CONTEXT_LINES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// A script that differs between two dumps.
+#[derive(Debug)]
+pub struct ScriptChange {
+    /// Shortened path, e.g. `Events/HMI_Screen/CM_Freq/Events.js`
+    pub key: String,
+    pub kind: ChangeKind,
+    pub old: Option<PathBuf>,
+    pub new: Option<PathBuf>,
+}
+
+impl ScriptChange {
+    /// The screen or faceplate the script belongs to: its key without the
+    /// file name.
+    fn group(&self) -> &str {
+        self.key.rsplit_once('/').map_or("", |(dir, _)| dir)
+    }
+
+    fn file_name(&self) -> &str {
+        self.key
+            .rsplit_once('/')
+            .map_or(self.key.as_str(), |(_, name)| name)
+    }
+}
+
+/// The report key of a dumped script: the context directory, if any, followed
+/// by the shortened script path. Paths that can't be shortened are kept.
+fn script_key(relative: &str) -> String {
+    let (context, script) = match relative.find("screen_modules/") {
+        Some(pos) => relative.split_at(pos),
+        None => return relative.to_string(),
+    };
+    match shorten_dumped_path(script) {
+        Some(short) => format!("{}{}", context, short),
+        None => relative.to_string(),
+    }
+}
+
+/// Every dumped script below `root`, by path relative to it. Hidden
+/// directories (the dump history) and `node_modules` of the styleguide are
+/// skipped, as are the `.raw.js` copies of pretty-printed scripts and the
+/// `.split` directories of split scripts that sit next to their script.
+fn collect_scripts(root: &Path) -> Result<BTreeMap<String, PathBuf>> {
+    fn walk(root: &Path, dir: &Path, scripts: &mut BTreeMap<String, PathBuf>) -> Result<()> {
+        for entry in
+            std::fs::read_dir(dir).with_context(|| format!("Cannot read {}", dir.display()))?
+        {
+            let path = entry?.path();
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if path.is_dir() {
+                if !name.starts_with('.') && name != "node_modules" && !is_companion(&path) {
+                    walk(root, &path, scripts)?;
+                }
+            } else if name.ends_with(".js") && !is_companion(&path) {
+                let relative = path.strip_prefix(root).unwrap_or(&path);
+                let relative: Vec<String> = relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                    .collect();
+                scripts.insert(relative.join("/"), path);
+            }
+        }
+        Ok(())
+    }
+
+    let mut scripts = BTreeMap::new();
+    walk(root, root, &mut scripts)?;
+    Ok(scripts)
+}
+
+/// Key the scripts of both dumps by `script_key`. Scripts whose short keys
+/// collide, like the same screen in two `HMI_RT_n` runtimes, keep their full
+/// relative path, in both dumps alike so they still pair up.
+fn key_scripts(
+    old: BTreeMap<String, PathBuf>,
+    new: BTreeMap<String, PathBuf>,
+) -> (BTreeMap<String, PathBuf>, BTreeMap<String, PathBuf>) {
+    let mut owners: HashMap<String, BTreeSet<&str>> = HashMap::new();
+    for relative in old.keys().chain(new.keys()) {
+        owners
+            .entry(script_key(relative))
+            .or_default()
+            .insert(relative);
+    }
+    let key = |relative: &str| {
+        let short = script_key(relative);
+        if owners[&short].len() > 1 {
+            relative.to_string()
+        } else {
+            short
+        }
+    };
+    let old_keyed = old
+        .iter()
+        .map(|(relative, path)| (key(relative), path.clone()))
+        .collect();
+    let new_keyed = new
+        .iter()
+        .map(|(relative, path)| (key(relative), path.clone()))
+        .collect();
+    (old_keyed, new_keyed)
+}
+
+/// Whether a dumped file or directory is a companion of a script next to it,
+/// `Events.raw.js` or `Events.split` of `Events.js`.
+fn is_companion(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let script = if let Some(stem) = name.strip_suffix(".split") {
+        format!("{}.js", stem)
+    } else if let Some(stem) = name.strip_suffix(".raw.js") {
+        format!("{}.js", stem)
+    } else {
+        return false;
+    };
+    path.with_file_name(script).is_file()
+}
+
+fn read_script(path: &Path) -> Result<String> {
+    let bytes = std::fs::read(path).with_context(|| format!("Cannot read {}", path.display()))?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// The scripts that were added, removed or modified between two dumps,
+/// sorted by group, and the number of unchanged scripts.
+fn compare(
+    old: &BTreeMap<String, PathBuf>,
+    new: &BTreeMap<String, PathBuf>,
+) -> Result<(Vec<ScriptChange>, usize)> {
+    let mut changes = Vec::new();
+    let mut unchanged = 0;
+    for (key, old_path) in old {
+        match new.get(key) {
+            Some(new_path) => {
+                if std::fs::read(old_path)? == std::fs::read(new_path)? {
+                    unchanged += 1;
+                } else {
+                    changes.push(ScriptChange {
+                        key: key.clone(),
+                        kind: ChangeKind::Modified,
+                        old: Some(old_path.clone()),
+                        new: Some(new_path.clone()),
+                    });
+                }
+            }
+            None => changes.push(ScriptChange {
+                key: key.clone(),
+                kind: ChangeKind::Removed,
+                old: Some(old_path.clone()),
+                new: None,
+            }),
+        }
+    }
+    for (key, new_path) in new {
+        if !old.contains_key(key) {
+            changes.push(ScriptChange {
+                key: key.clone(),
+                kind: ChangeKind::Added,
+                old: None,
+                new: Some(new_path.clone()),
+            });
+        }
+    }
+    changes.sort_by(|a, b| (a.group(), a.file_name()).cmp(&(b.group(), b.file_name())));
+    Ok((changes, unchanged))
+}
+
+/// Unified diff of one script, from empty for added and to empty for
+/// removed scripts.
+fn unified_diff(change: &ScriptChange) -> Result<String> {
+    let old = change
+        .old
+        .as_deref()
+        .map(read_script)
+        .transpose()?
+        .unwrap_or_default();
+    let new = change
+        .new
+        .as_deref()
+        .map(read_script)
+        .transpose()?
+        .unwrap_or_default();
+    let old_header = format!("a/{}", change.key);
+    let new_header = format!("b/{}", change.key);
+    Ok(TextDiff::from_lines(&old, &new)
+        .unified_diff()
+        .context_radius(CONTEXT_LINES)
+        .header(&old_header, &new_header)
+        .to_string())
+}
+
+fn print_diff(diff: &str) {
+    for line in diff.lines() {
+        if line.starts_with("+++") || line.starts_with("---") {
+            println!("      {}", line.bold());
+        } else if line.starts_with('+') {
+            println!("      {}", line.green());
+        } else if line.starts_with('-') {
+            println!("      {}", line.red());
+        } else if line.starts_with("@@") {
+            println!("      {}", line.cyan());
+        } else {
+            println!("      {}", line);
+        }
+    }
+}
+
+/// The `diff` command: report the scripts that changed between two dump
+/// directories or history snapshots, grouped by screen and faceplate. With
+/// `summary`, only lists the scripts without their diffs.
+pub fn diff_dumps(old_dir: &str, new_dir: &str, summary: bool) -> Result<()> {
+    let (old, new) = key_scripts(
+        collect_scripts(Path::new(old_dir))?,
+        collect_scripts(Path::new(new_dir))?,
+    );
+    let (changes, unchanged) = compare(&old, &new)?;
+
+    println!("{} {} -> {}", "Comparing".cyan().bold(), old_dir, new_dir);
+
+    let mut group = None;
+    for change in &changes {
+        if group != Some(change.group()) {
+            group = Some(change.group());
+            let title = if change.group().is_empty() {
+                "."
+            } else {
+                change.group()
+            };
+            println!();
+            println!("{}", title.bold());
+        }
+        let marker = match change.kind {
+            ChangeKind::Added => "+".green().bold(),
+            ChangeKind::Removed => "-".red().bold(),
+            ChangeKind::Modified => "~".yellow().bold(),
+        };
+        println!("   {} {}", marker, change.file_name());
+        if !summary {
+            print_diff(&unified_diff(change)?);
+        }
+    }
+
+    let count = |kind: ChangeKind| changes.iter().filter(|c| c.kind == kind).count();
+    println!();
+    println!(
+        "{} added, {} removed, {} modified, {} unchanged",
+        count(ChangeKind::Added).to_string().green(),
+        count(ChangeKind::Removed).to_string().red(),
+        count(ChangeKind::Modified).to_string().yellow(),
+        unchanged
+    );
+    Ok(())
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUMPED: &str = "Events/screen_modules/Screen_Content/HMI_RT_1__HMI_Screen/faceplate_modules/CM_Freq/Events.js";
+
+    #[test]
+    fn script_key_shortens_dumped_paths() {
+        assert_eq!(script_key(DUMPED), "Events/HMI_Screen/CM_Freq/Events.js");
+        assert_eq!(
+            script_key("screen_modules/Screen_Content/HMI_RT_1__HMI_Screen/Events.js"),
+            "HMI_Screen/Events.js"
+        );
+        assert_eq!(script_key("Tasks/global.js"), "Tasks/global.js");
+    }
+
+    #[test]
+    fn compare_classifies_changes_by_short_path() {
+        let scratch = tempfile::tempdir().unwrap();
+        let tmp = scratch.path();
+        let write = |dir: &str, relative: &str, source: &str| {
+            let path = tmp.join(dir).join(relative);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        };
+        write("old", DUMPED, "let a = 1;\n");
+        write("old", "Events/removed.js", "gone\n");
+        write("old", "Events/same.js", "same\n");
+        write("old", ".history/Events/x/ignored.js", "old\n");
+        write("old", "Events/same.raw.js", "raw\n");
+        write("old", "Events/same.split/f.js", "split\n");
+        write("old", "Events/Orphan.raw.js", "genuine\n");
+        write("new", DUMPED, "let a = 2;\n");
+        write("new", "Events/added.js", "new\n");
+        write("new", "Events/same.js", "same\n");
+        write("new", "Events/Orphan.raw.js", "genuine\n");
+
+        let (old, new) = key_scripts(
+            collect_scripts(&tmp.join("old")).unwrap(),
+            collect_scripts(&tmp.join("new")).unwrap(),
+        );
+        let (changes, unchanged) = compare(&old, &new).unwrap();
+        assert_eq!(unchanged, 2);
+        let summary: Vec<(&str, ChangeKind)> =
+            changes.iter().map(|c| (c.key.as_str(), c.kind)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("Events/added.js", ChangeKind::Added),
+                ("Events/removed.js", ChangeKind::Removed),
+                ("Events/HMI_Screen/CM_Freq/Events.js", ChangeKind::Modified),
+            ]
+        );
+        assert_eq!(changes[2].group(), "Events/HMI_Screen/CM_Freq");
+
+        let diff = unified_diff(&changes[2]).unwrap();
+        assert!(diff.contains("-let a = 1;"));
+        assert!(diff.contains("+let a = 2;"));
+        assert!(unified_diff(&changes[0]).unwrap().contains("+new"));
+    }
+
+    #[test]
+    fn colliding_short_keys_keep_the_full_path() {
+        let other = DUMPED.replace("HMI_RT_1", "HMI_RT_2");
+        let scripts = |paths: &[&str]| -> BTreeMap<String, PathBuf> {
+            paths
+                .iter()
+                .map(|p| (p.to_string(), PathBuf::from(p)))
+                .collect()
+        };
+        // The second runtime only exists in the new dump
+        let (old, new) = key_scripts(
+            scripts(&[DUMPED, "Events/a.js"]),
+            scripts(&[DUMPED, &other, "Events/a.js"]),
+        );
+        assert_eq!(old.keys().collect::<Vec<_>>(), vec!["Events/a.js", DUMPED]);
+        assert_eq!(
+            new.keys().collect::<Vec<_>>(),
+            vec!["Events/a.js", DUMPED, other.as_str()]
+        );
+        assert_eq!(new[other.as_str()], PathBuf::from(&other));
+    }
+}
@@ -0,0 +1,1626 @@
+use anyhow::{Context, Result, anyhow};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::config::{Configuration, ContextKind, ContextSpec, DumpFormat, Station};
+use crate::events::{EventOrigin, ProxyEvent};
+use crate::git::{DumpOrigin, commit_dump};
+use crate::ids::DUMP_ID_BASE;
+use crate::logging::{Logger, Tag};
+use crate::pathmap::script_path;
+use crate::pretty::{pretty_print, raw_path};
+use crate::proxy::current_targets;
+use crate::split::{INDEX_FILE, SplitIndex, split_dir, split_script};
+use crate::urlmap::{is_dumpable, shorten_script_url};
+
+// ============================================================================
+// Script Dump
+// ============================================================================
+
+/// Name of the file next to a context's dumped scripts that describes them.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Serializes manifest updates, since every connection of a context merges
+/// its entries into the same file.
+static MANIFEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// `manifest.json` of a context's dump directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub context: String,
+    pub scripts: Vec<ManifestEntry>,
+}
+
+/// What the runtime reported about a dumped script.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestEntry {
+    /// Path of the dumped file, relative to the context directory
+    pub file: String,
+    /// Path of the unformatted copy, if the dumped file was pretty-printed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_file: Option<String>,
+    pub script_id: String,
+    pub url: String,
+    pub short_url: Option<String>,
+    /// SHA-256 of the source the runtime reported, as hex
+    pub hash: String,
+    /// Size of the source the runtime reported, in bytes
+    pub length: usize,
+    pub execution_context_id: Option<i64>,
+    pub start_line: u64,
+    pub end_line: u64,
+    pub vcs: Option<u32>,
+    /// Websocket path of the target the script was dumped from
+    pub target: Option<String>,
+}
+
+/// How long the target gets to answer a `Debugger.getScriptSource` request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Requests that time out or are answered with an error are sent again, and
+/// files that can't be written are retried on the next poll, up to this many
+/// attempts per script.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// A script whose source was requested but is not on disk yet.
+struct PendingScript {
+    file_path: String,
+    entry: ManifestEntry,
+    sent: Instant,
+    attempts: u32,
+}
+
+/// Outcome of every script a dumper saw.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DumpStats {
+    pub written: u64,
+    pub failed: u64,
+    /// Scripts that are never dumped, like the debugger's own eval snippets
+    pub skipped: u64,
+    /// Requests to a previous target, dropped when the target changed
+    pub dropped: u64,
+}
+
+impl DumpStats {
+    pub fn summary(&self) -> String {
+        let summary = format!(
+            "{} written, {} failed, {} skipped",
+            self.written, self.failed, self.skipped
+        );
+        match self.dropped {
+            0 => summary,
+            dropped => format!("{}, {} dropped", summary, dropped),
+        }
+    }
+}
+
+/// Fetches the source of every script a target reports and writes it below
+/// `<dump_dir>/<context>/`, with a `manifest.json` describing the scripts.
+///
+/// Unanswered requests are sent again after `REQUEST_TIMEOUT` and failed
+/// writes are retried; both only happen from `poll`, which the owner calls
+/// periodically. Scripts that still fail are reported one by one and counted
+/// in `stats`.
+pub struct ScriptDumper {
+    dump_dir: String,
+    target_dir: String,
+    next_msg_id: u64,
+    pending: HashMap<u64, PendingScript>,
+    /// Sources that could not be written yet
+    unwritten: Vec<(PendingScript, String)>,
+    /// Scripts whose request was answered with an error, to ask again
+    unanswered: Vec<PendingScript>,
+    /// Requests given up on, whose late responses are still swallowed for a
+    /// while, with when they were given up
+    abandoned: HashMap<u64, Instant>,
+    /// Written entries not yet in `manifest.json`
+    unsaved: Vec<ManifestEntry>,
+    target: Option<(String, u32)>,
+    format: DumpFormat,
+    split: bool,
+    git: bool,
+    /// Whether the current target's first complete batch was committed
+    cycle_committed: bool,
+    /// Scripts were saved since the last commit
+    uncommitted: bool,
+    /// A commit waiting for `take_commit`
+    commit: Option<DumpCommit>,
+    log: Logger,
+    origin: Option<EventOrigin>,
+    pub stats: DumpStats,
+}
+
+impl ScriptDumper {
+    pub fn new(dump_dir: String, context_name: &str) -> Self {
+        Self {
+            dump_dir,
+            target_dir: context_name.to_string(),
+            next_msg_id: DUMP_ID_BASE,
+            pending: HashMap::new(),
+            unwritten: Vec::new(),
+            unanswered: Vec::new(),
+            abandoned: HashMap::new(),
+            unsaved: Vec::new(),
+            target: None,
+            format: DumpFormat::Raw,
+            split: false,
+            git: false,
+            cycle_committed: false,
+            uncommitted: false,
+            commit: None,
+            log: Logger::default(),
+            origin: None,
+            stats: DumpStats::default(),
+        }
+    }
+
+    /// Write scripts in `format` instead of as the runtime reports them.
+    pub fn with_format(mut self, format: DumpFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Also write every top-level function of a script to its own file in a
+    /// `<name>.split/` directory next to it, with an `index.json` of their
+    /// lines in the script.
+    pub fn with_split(mut self, split: bool) -> Self {
+        self.split = split;
+        self
+    }
+
+    /// Commit the context's scripts to the dump directory's git repository
+    /// once the dump of each target completes, and when the dumper finishes
+    /// with scripts written since. The commits are handed out by `take_commit`.
+    pub fn with_git(mut self, git: bool) -> Self {
+        self.git = git;
+        self
+    }
+
+    /// Report progress and problems to `log`. Without one, the dumper is silent.
+    pub fn with_logger(mut self, log: Logger) -> Self {
+        self.log = log;
+        self
+    }
+
+    /// Send a `ProxyEvent::DumpProgress` for `origin` after every completed
+    /// batch of scripts.
+    pub fn with_origin(mut self, origin: EventOrigin) -> Self {
+        self.origin = Some(origin);
+        self
+    }
+
+    /// The file holding the runtime's lines of a dumped script, which source
+    /// maps and breakpoints refer to.
+    fn runtime_file(&self, file: &str) -> String {
+        match self.format {
+            DumpFormat::Raw => file.to_string(),
+            DumpFormat::Pretty => raw_path(file),
+        }
+    }
+
+    /// The target scripts are dumped from from now on, recorded in the
+    /// manifest with its VCS number (0 if unknown). Requests to the previous
+    /// target are dropped and counted, the new target reports its scripts
+    /// again. Its connection is gone, so no late responses are expected.
+    pub fn set_target(&mut self, path: &str, vcs: u32) {
+        let dropped = self.pending.len() + self.unanswered.len();
+        if dropped > 0 {
+            self.log.verbose(&format!(
+                "[DUMP] Dropping {} requests to the previous {} target",
+                dropped, self.target_dir
+            ));
+            self.pending.clear();
+            self.unanswered.clear();
+            self.stats.dropped += dropped as u64;
+        }
+        self.abandoned.clear();
+        self.target = Some((path.to_string(), vcs));
+        self.cycle_committed = false;
+    }
+
+    /// True when no script is waiting for its source or to be written.
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_empty() && self.unanswered.is_empty() && self.unwritten.is_empty()
+    }
+
+    /// Point a dumped script's `sourceMapURL` at the proxy's `/sourcemap` route,
+    /// so VS Code shows the dumped file instead of a read-only copy of the
+    /// runtime script. Call after `handle_script_parsed` accepted the event.
+    /// `authority` is the host and port the client reaches the proxy under.
+    /// Returns true if the event changed.
+    pub fn attach_source_map(&self, parsed: &mut serde_json::Value, authority: &str) -> bool {
+        let Some(params) = parsed.get_mut("params").and_then(|p| p.as_object_mut()) else {
+            return false;
+        };
+        let has_source_map = params
+            .get("sourceMapURL")
+            .and_then(|s| s.as_str())
+            .is_some_and(|s| !s.is_empty());
+        let Some(script_url) = params.get("url").and_then(|s| s.as_str()) else {
+            return false;
+        };
+        if has_source_map {
+            return false;
+        }
+
+        let file = self.runtime_file(&script_path(script_url));
+        let encoded: Vec<String> = std::iter::once(self.target_dir.as_str())
+            .chain(file.split('/'))
+            .map(|segment| urlencoding::encode(segment).into_owned())
+            .collect();
+        let lines = params.get("endLine").and_then(|l| l.as_u64()).unwrap_or(0) + 1;
+        let map_url = format!(
+            "http://{}/sourcemap/{}?lines={}",
+            authority,
+            encoded.join("/"),
+            lines
+        );
+        params.insert(
+            "sourceMapURL".to_string(),
+            serde_json::Value::String(map_url),
+        );
+        true
+    }
+
+    /// If this is a scriptParsed event, returns a getScriptSource CDP request to send.
+    pub fn handle_script_parsed(&mut self, parsed: &serde_json::Value) -> Option<String> {
+        if parsed.get("method").and_then(|m| m.as_str()) != Some("Debugger.scriptParsed") {
+            return None;
+        }
+        let params = parsed.get("params")?;
+        let script_id = params
+            .get("scriptId")
+            .and_then(|s| s.as_str())
+            .unwrap_or("");
+        let script_url = params.get("url").and_then(|s| s.as_str()).unwrap_or("");
+
+        if !is_dumpable(script_url) {
+            self.stats.skipped += 1;
+            return None;
+        }
+
+        let file = script_path(script_url);
+        let file_path = format!("{}/{}/{}", self.dump_dir, self.target_dir, file);
+        let entry = ManifestEntry {
+            raw_file: (self.format == DumpFormat::Pretty).then(|| raw_path(&file)),
+            file,
+            script_id: script_id.to_string(),
+            url: script_url.to_string(),
+            short_url: shorten_script_url(script_url),
+            hash: String::new(),
+            length: 0,
+            execution_context_id: params.get("executionContextId").and_then(|c| c.as_i64()),
+            start_line: params
+                .get("startLine")
+                .and_then(|l| l.as_u64())
+                .unwrap_or(0),
+            end_line: params.get("endLine").and_then(|l| l.as_u64()).unwrap_or(0),
+            vcs: self
+                .target
+                .as_ref()
+                .map(|(_, vcs)| *vcs)
+                .filter(|vcs| *vcs > 0),
+            target: self.target.as_ref().map(|(path, _)| path.clone()),
+        };
+
+        Some(self.request(PendingScript {
+            file_path,
+            entry,
+            sent: Instant::now(),
+            attempts: 0,
+        }))
+    }
+
+    /// Send a (new) getScriptSource request for a script.
+    fn request(&mut self, mut script: PendingScript) -> String {
+        let id = self.next_msg_id;
+        self.next_msg_id += 1;
+        let request = serde_json::json!({
+            "id": id,
+            "method": "Debugger.getScriptSource",
+            "params": { "scriptId": script.entry.script_id }
+        });
+        script.sent = Instant::now();
+        script.attempts += 1;
+        self.pending.insert(id, script);
+        request.to_string()
+    }
+
+    /// If this is a response to one of our getScriptSource requests, write to disk.
+    /// Returns true if the message was consumed (should not be forwarded to client).
+    pub fn handle_response(&mut self, parsed: &serde_json::Value) -> bool {
+        let Some(id) = parsed.get("id").and_then(|id| id.as_u64()) else {
+            return false;
+        };
+        let Some(script) = self.pending.remove(&id) else {
+            return self.abandoned.remove(&id).is_some();
+        };
+
+        if let Some(error) = parsed.get("error") {
+            let message = error
+                .get("message")
+                .and_then(|m| m.as_str())
+                .unwrap_or("unknown error");
+            if script.attempts < MAX_ATTEMPTS {
+                self.log.verbose(&format!(
+                    "[DUMP] {} for {}, asking again",
+                    message, script.entry.url
+                ));
+                self.unanswered.push(script);
+            } else {
+                self.fail(&script, message);
+            }
+        } else {
+            match parsed
+                .get("result")
+                .and_then(|r| r.get("scriptSource"))
+                .and_then(|s| s.as_str())
+            {
+                Some(source) => self.write(script, source.to_string()),
+                None => self.fail(&script, "response has no scriptSource"),
+            }
+        }
+
+        self.finish_batch();
+        true // consumed, don't forward
+    }
+
+    /// Resend requests that timed out and retry failed writes. Returns the
+    /// requests to send to the target.
+    pub fn poll(&mut self) -> Vec<String> {
+        self.poll_at(Instant::now())
+    }
+
+    fn poll_at(&mut self, now: Instant) -> Vec<String> {
+        let expired: Vec<u64> = self
+            .pending
+            .iter()
+            .filter(|(_, script)| now.duration_since(script.sent) >= REQUEST_TIMEOUT)
+            .map(|(id, _)| *id)
+            .collect();
+
+        self.abandoned
+            .retain(|_, given_up| now.duration_since(*given_up) < REQUEST_TIMEOUT);
+
+        let mut requests = Vec::new();
+        for script in std::mem::take(&mut self.unanswered) {
+            requests.push(self.request(script));
+        }
+        for id in expired {
+            let Some(script) = self.pending.remove(&id) else {
+                continue;
+            };
+            self.abandoned.insert(id, now);
+            if script.attempts < MAX_ATTEMPTS {
+                self.log.verbose(&format!(
+                    "[DUMP] No response for {}, asking again",
+                    script.entry.url
+                ));
+                requests.push(self.request(script));
+            } else {
+                let reason = format!("no response after {} attempts", script.attempts);
+                self.fail(&script, &reason);
+            }
+        }
+
+        for (script, source) in std::mem::take(&mut self.unwritten) {
+            self.write(script, source);
+        }
+
+        self.finish_batch();
+        requests
+    }
+
+    /// Write a script's source, keeping it for another attempt if that fails.
+    fn write(&mut self, mut script: PendingScript, source: String) {
+        let path = Path::new(&script.file_path);
+        let result = match path.parent() {
+            Some(parent) => std::fs::create_dir_all(parent),
+            None => Ok(()),
+        }
+        .and_then(|_| match self.format {
+            DumpFormat::Raw => std::fs::write(path, &source),
+            DumpFormat::Pretty => {
+                std::fs::write(raw_path(&script.file_path), &source)?;
+                let pretty = pretty_print(&source);
+                if pretty.is_none() {
+                    self.log.verbose(&format!(
+                        "[DUMP] Cannot parse {}, written unformatted",
+                        script.entry.url
+                    ));
+                }
+                std::fs::write(path, pretty.as_deref().unwrap_or(&source))
+            }
+        });
+
+        match result {
+            Ok(()) => {
+                self.stats.written += 1;
+                self.log.verbose(&format!("[DUMP] {}", script.file_path));
+                if self.split
+                    && let Err(e) = self.write_split(&script.file_path, &source)
+                {
+                    self.log
+                        .warn(&format!("[DUMP] Cannot split {}: {}", script.file_path, e));
+                }
+                script.entry.hash = format!("{:x}", Sha256::digest(source.as_bytes()));
+                script.entry.length = source.len();
+                self.unsaved.push(script.entry);
+            }
+            Err(e) if script.attempts < MAX_ATTEMPTS => {
+                self.log.verbose(&format!(
+                    "[DUMP] Cannot write {}, retrying: {}",
+                    script.file_path, e
+                ));
+                script.attempts += 1;
+                self.unwritten.push((script, source));
+            }
+            Err(e) => self.fail(
+                &script,
+                &format!("cannot write {}: {}", script.file_path, e),
+            ),
+        }
+    }
+
+    /// Replace a script's split directory with its current functions. A
+    /// script that can't be parsed keeps only the file it was dumped to.
+    fn write_split(&self, file_path: &str, source: &str) -> Result<()> {
+        let Some(functions) = split_script(source) else {
+            self.log
+                .verbose(&format!("[DUMP] Cannot parse {}, not split", file_path));
+            return Ok(());
+        };
+        let dir = PathBuf::from(split_dir(file_path));
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)?;
+        }
+        if functions.is_empty() {
+            return Ok(());
+        }
+
+        for function in &functions {
+            let path = dir.join(&function.entry.file);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let pretty = match self.format {
+                DumpFormat::Raw => None,
+                DumpFormat::Pretty => pretty_print(&function.code),
+            };
+            std::fs::write(path, pretty.as_deref().unwrap_or(&function.code))?;
+        }
+        let index = SplitIndex {
+            script: Path::new(file_path)
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            functions: functions.into_iter().map(|f| f.entry).collect(),
+        };
+        std::fs::write(dir.join(INDEX_FILE), serde_json::to_string_pretty(&index)?)?;
+        Ok(())
+    }
+
+    fn fail(&mut self, script: &PendingScript, reason: &str) {
+        self.stats.failed += 1;
+        self.log.warn(&format!(
+            "[DUMP] {} not dumped: {}",
+            script.entry.url, reason
+        ));
+    }
+
+    /// Once nothing is outstanding, write the manifest, and prepare a commit
+    /// if this completes the target's dump. This happens once per batch of
+    /// scripts rather than per script.
+    fn finish_batch(&mut self) {
+        if self.is_idle() && !self.unsaved.is_empty() {
+            self.save_manifest();
+            self.uncommitted = true;
+            if !self.cycle_committed {
+                self.cycle_committed = true;
+                self.prepare_commit();
+            }
+            if let Some(ref origin) = self.origin {
+                self.log.event(ProxyEvent::DumpProgress {
+                    origin: origin.clone(),
+                    stats: self.stats,
+                });
+            }
+        }
+    }
+
+    /// Give up on everything outstanding, e.g. when the connection closes,
+    /// and save what was written. Returns the final statistics.
+    pub fn finish(&mut self) -> DumpStats {
+        let mut pending: Vec<PendingScript> =
+            self.pending.drain().map(|(_, script)| script).collect();
+        pending.append(&mut self.unanswered);
+        for script in pending {
+            self.fail(&script, "connection closed before the source arrived");
+        }
+        for (script, source) in std::mem::take(&mut self.unwritten) {
+            // One last attempt
+            let script = PendingScript {
+                attempts: MAX_ATTEMPTS,
+                ..script
+            };
+            self.write(script, source);
+        }
+        self.finish_batch();
+        self.prepare_commit();
+        self.stats
+    }
+
+    /// Merge the unsaved entries into the context's `manifest.json`. Other
+    /// connections of the context may have written it too; an entry for the
+    /// same file replaces the older one.
+    fn save_manifest(&mut self) {
+        let _guard = MANIFEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = Path::new(&self.dump_dir)
+            .join(&self.target_dir)
+            .join(MANIFEST_FILE);
+        let mut manifest = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| serde_json::from_str::<Manifest>(&text).ok())
+            .unwrap_or_default();
+        manifest.context = self.target_dir.clone();
+
+        let unsaved = std::mem::take(&mut self.unsaved);
+        manifest
+            .scripts
+            .retain(|e| !unsaved.iter().any(|u| u.file == e.file));
+        manifest.scripts.extend(unsaved);
+        manifest.scripts.sort_by(|a, b| a.file.cmp(&b.file));
+
+        match serde_json::to_string_pretty(&manifest) {
+            Ok(json) => {
+                if let Err(e) = std::fs::write(&path, json) {
+                    self.log
+                        .warn(&format!("Cannot write {}: {}", path.display(), e));
+                }
+            }
+            Err(e) => self
+                .log
+                .warn(&format!("Cannot serialize {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Queue a commit of what was saved since the last one, if enabled.
+    fn prepare_commit(&mut self) {
+        if !self.git || !self.uncommitted {
+            return;
+        }
+        self.uncommitted = false;
+        self.commit = Some(DumpCommit {
+            dump_dir: PathBuf::from(&self.dump_dir),
+            origin: DumpOrigin {
+                context: self.target_dir.clone(),
+                target: self.target.as_ref().map(|(path, _)| path.clone()),
+                vcs: self
+                    .target
+                    .as_ref()
+                    .map(|(_, vcs)| *vcs)
+                    .filter(|vcs| *vcs > 0),
+            },
+            log: self.log.clone(),
+        });
+    }
+
+    /// The commit due after the last message or `finish`, if any. The owner
+    /// runs it with `DumpCommit::spawn`.
+    pub fn take_commit(&mut self) -> Option<DumpCommit> {
+        self.commit.take()
+    }
+}
+
+/// A commit of a context's dumped scripts, prepared by `ScriptDumper`.
+#[derive(Debug)]
+pub struct DumpCommit {
+    dump_dir: PathBuf,
+    origin: DumpOrigin,
+    log: Logger,
+}
+
+impl DumpCommit {
+    /// Commit on the blocking thread pool, since hashing the dump takes a while.
+    pub fn spawn(self) -> tokio::task::JoinHandle<()> {
+        tokio::task::spawn_blocking(move || self.run())
+    }
+
+    fn run(&self) {
+        match commit_dump(&self.dump_dir, &self.origin) {
+            Ok(Some(changes)) => self.log.log(&format!(
+                "[GIT] Committed {} scripts: {}",
+                self.origin.context,
+                changes.summary()
+            )),
+            Ok(None) => {}
+            Err(e) => self.log.warn(&format!(
+                "Cannot commit {}: {:#}",
+                self.dump_dir.display(),
+                e
+            )),
+        }
+    }
+}
+
+// ============================================================================
+// Dump History
+// ============================================================================
+
+/// Directory below a dump directory that keeps the scripts of earlier targets
+/// when `dump_history` is on, as `.history/<context>/<snapshot>/`.
+pub const HISTORY_DIR: &str = ".history";
+
+/// What happened to a context's previous scripts when it got a new target.
+#[derive(Debug, PartialEq)]
+pub enum Retired {
+    /// There were no dumped scripts
+    Nothing,
+    /// The scripts were deleted
+    Removed,
+    /// The scripts were moved to this snapshot directory
+    Archived(PathBuf),
+}
+
+/// Name of a history snapshot: the time it was taken, plus the VCS number of
+/// the target the scripts came from if it is known.
+fn snapshot_name(time: chrono::DateTime<chrono::Local>, vcs: Option<u32>) -> String {
+    let stamp = time.format("%Y%m%d-%H%M%S").to_string();
+    match vcs {
+        Some(vcs) if vcs > 0 => format!("{}_vcs{}", stamp, vcs),
+        _ => stamp,
+    }
+}
+
+/// Move a context's dumped scripts into a new history snapshot. Returns the
+/// snapshot directory, or `None` if there was nothing to move.
+fn archive_context_dump(
+    dump_dir: &str,
+    kind: &ContextKind,
+    vcs: Option<u32>,
+) -> std::io::Result<Option<PathBuf>> {
+    let live = Path::new(dump_dir).join(kind.name());
+    if !live.exists() {
+        return Ok(None);
+    }
+    let history = Path::new(dump_dir).join(HISTORY_DIR).join(kind.name());
+    std::fs::create_dir_all(&history)?;
+
+    // Two changes within a second get numbered snapshots
+    let name = snapshot_name(chrono::Local::now(), vcs);
+    let mut snapshot = history.join(&name);
+    let mut n = 2;
+    while snapshot.exists() {
+        snapshot = history.join(format!("{}-{}", name, n));
+        n += 1;
+    }
+    std::fs::rename(&live, &snapshot)?;
+    Ok(Some(snapshot))
+}
+
+/// Clear a context's dumped scripts before it is dumped from a new target.
+/// With `history` the scripts are archived instead of deleted; `vcs` is the
+/// VCS number of the target they came from, if known.
+pub fn retire_context_dump(
+    dump_dir: &str,
+    kind: &ContextKind,
+    vcs: Option<u32>,
+    history: bool,
+    log: &Logger,
+) -> Retired {
+    if history {
+        match archive_context_dump(dump_dir, kind, vcs) {
+            Ok(Some(snapshot)) => return Retired::Archived(snapshot),
+            Ok(None) => return Retired::Nothing,
+            Err(e) => log.warn(&format!(
+                "Cannot archive {}/{}/, removing it instead: {}",
+                dump_dir,
+                kind.name(),
+                e
+            )),
+        }
+    }
+    let live = Path::new(dump_dir).join(kind.name());
+    if live.exists() && std::fs::remove_dir_all(&live).is_ok() {
+        Retired::Removed
+    } else {
+        Retired::Nothing
+    }
+}
+
+/// Log what `retire_context_dump` did, if anything.
+pub fn log_retired(
+    log: &Logger,
+    prefix: &str,
+    dump_dir: &str,
+    kind: &ContextKind,
+    retired: &Retired,
+) {
+    match retired {
+        Retired::Nothing => {}
+        Retired::Removed => log.log(&format!(
+            "{}   Cleaned {}/{}/",
+            prefix,
+            dump_dir,
+            kind.name()
+        )),
+        Retired::Archived(snapshot) => log.log(&format!(
+            "{}   Archived {}/{}/ to {}",
+            prefix,
+            dump_dir,
+            kind.name(),
+            snapshot.display()
+        )),
+    }
+}
+
+/// Retire the scripts of every context of a station, e.g. left over from a
+/// previous run.
+pub fn clean_dump_scripts(station: &Station, history: bool, log: &Logger) {
+    let Some(ref dump_dir) = station.dump_output else {
+        return;
+    };
+    for context in &station.contexts {
+        retire_context_dump(dump_dir, &context.kind, None, history, log);
+    }
+}
+
+// ============================================================================
+// Headless Dump
+// ============================================================================
+
+/// Message id of the `Debugger.enable` request of a headless dump.
+const ENABLE_ID: u64 = 1;
+
+/// Connect straight to a context's target, enable the debugger and write out
+/// every script it reports.
+///
+/// With `retire`, the context's previous dump is retired once the target was
+/// reached, so an unreachable target leaves it in place; the value is the VCS
+/// number of the target it came from, if known.
+///
+/// Without `watch`, returns once every script that existed when the debugger
+/// was enabled has been written. With `watch`, keeps writing newly loaded
+/// scripts until the connection closes or the context moves to another target.
+async fn dump_target(
+    cfg: &Configuration,
+    log: &Logger,
+    station: &Station,
+    spec: &ContextSpec,
+    (path, vcs): (&str, u32),
+    retire: Option<Option<u32>>,
+    watch: bool,
+) -> Result<DumpStats> {
+    let dump_dir = station
+        .dump_output
+        .clone()
+        .context("No dump directory configured")?;
+    let url = format!(
+        "ws://{}:{}/{}",
+        station.target_host, station.target_port, path
+    );
+    let (stream, _) = tokio_tungstenite::connect_async(&url)
+        .await
+        .with_context(|| format!("Failed to connect to the {} target", spec.kind))?;
+    let (mut target_tx, mut target_rx) = stream.split();
+
+    if let Some(previous_vcs) = retire {
+        let retired =
+            retire_context_dump(&dump_dir, &spec.kind, previous_vcs, cfg.dump_history, log);
+        log_retired(log, &station.prefix(), &dump_dir, &spec.kind, &retired);
+    }
+
+    let enable = serde_json::json!({ "id": ENABLE_ID, "method": "Debugger.enable" });
+    target_tx.send(Message::Text(enable.to_string())).await?;
+
+    let mut dumper = ScriptDumper::new(dump_dir, spec.kind.name())
+        .with_format(cfg.dump_format)
+        .with_split(cfg.dump_split)
+        .with_git(cfg.dump_git)
+        .with_logger(log.clone())
+        .with_origin(station.origin(&spec.kind));
+    dumper.set_target(path, vcs);
+    let mut enabled = false;
+    let mut commits = Vec::new();
+    let mut poll = tokio::time::interval(Duration::from_secs(cfg.poll_interval));
+    poll.tick().await;
+    let mut retry = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        tokio::select! {
+            msg = target_rx.next() => {
+                let Some(Ok(msg)) = msg else { break };
+                let Message::Text(text) = msg else { continue };
+                let Ok(parsed) = serde_json::from_str::<serde_json::Value>(&text) else { continue };
+
+                if let Some(request) = dumper.handle_script_parsed(&parsed) {
+                    target_tx.send(Message::Text(request)).await?;
+                } else if parsed.get("id").and_then(|id| id.as_u64()) == Some(ENABLE_ID) {
+                    // The target reports all existing scripts before answering
+                    enabled = true;
+                } else {
+                    dumper.handle_response(&parsed);
+                }
+                if let Some(commit) = dumper.take_commit() {
+                    commits.push(commit.spawn());
+                }
+
+                if !watch && enabled && dumper.is_idle() {
+                    break;
+                }
+            }
+            _ = retry.tick() => {
+                for request in dumper.poll() {
+                    target_tx.send(Message::Text(request)).await?;
+                }
+                if let Some(commit) = dumper.take_commit() {
+                    commits.push(commit.spawn());
+                }
+                if !watch && enabled && dumper.is_idle() {
+                    break;
+                }
+            }
+            _ = poll.tick(), if watch => {
+                let moved = current_targets(station, log).await.is_ok_and(|targets| {
+                    targets
+                        .iter()
+                        .find(|(s, _)| s.kind == spec.kind)
+                        .and_then(|(_, target)| target.as_ref())
+                        .is_none_or(|(current, _)| current != path)
+                });
+                if moved {
+                    break;
+                }
+            }
+        }
+    }
+
+    let _ = target_tx.close().await;
+    let stats = dumper.finish();
+    commits.extend(dumper.take_commit().map(DumpCommit::spawn));
+    for commit in commits {
+        let _ = commit.await;
+    }
+    Ok(stats)
+}
+
+/// Keep one context's dump up to date until the process exits, following it
+/// to new targets.
+async fn watch_context(cfg: Arc<Configuration>, log: Logger, station: Station, spec: ContextSpec) {
+    let prefix = station.prefix();
+    let mut last: Option<(String, u32)> = None;
+    loop {
+        let target = match current_targets(&station, &log).await {
+            Ok(targets) => targets
+                .into_iter()
+                .find(|(s, _)| s.kind == spec.kind)
+                .and_then(|(_, target)| target),
+            Err(e) => {
+                log.verbose(&format!("{}Cannot fetch targets: {}", prefix, e));
+                None
+            }
+        };
+
+        if let Some((path, vcs)) = target {
+            // The first target replaces what a previous run left, a new one the last dump
+            let retire = match &last {
+                None => Some(None),
+                Some((last_path, last_vcs)) if *last_path != path => {
+                    log.log(&format!("{}{} target changed", prefix, spec.kind));
+                    Some(Some(*last_vcs))
+                }
+                Some(_) => None,
+            };
+            log.log(&format!(
+                "{}{}: watching target for scripts",
+                prefix, spec.kind
+            ));
+            match dump_target(&cfg, &log, &station, &spec, (&path, vcs), retire, true).await {
+                Ok(stats) => log.log(&format!("{}{}: {}", prefix, spec.kind, stats.summary())),
+                Err(e) => log.error(&format!("{}{}: {:#}", prefix, spec.kind, e)),
+            }
+            last = Some((path, vcs));
+        }
+
+        tokio::time::sleep(Duration::from_secs(cfg.poll_interval)).await;
+    }
+}
+
+/// The `dump` command: write every runtime script of every context to the
+/// dump directory without a debugger attached. With `watch`, keeps running and
+/// follows target changes until Ctrl+C.
+pub async fn run_dump(cfg: Configuration, log: Logger, watch: bool, timeout: u64) -> Result<()> {
+    if watch {
+        log.tagged(
+            Tag::Start,
+            "Watching WinCC targets, dumping scripts as they load. Press Ctrl+C to stop",
+        );
+        let cfg = Arc::new(cfg);
+        for station in &cfg.stations {
+            for spec in &station.contexts {
+                tokio::spawn(watch_context(
+                    cfg.clone(),
+                    log.clone(),
+                    station.clone(),
+                    spec.clone(),
+                ));
+            }
+        }
+        tokio::signal::ctrl_c().await?;
+        return Ok(());
+    }
+
+    let mut total = 0;
+    let mut failed = Vec::new();
+    for station in &cfg.stations {
+        let prefix = station.prefix();
+        let targets = current_targets(station, &log).await.with_context(|| {
+            format!(
+                "{}Cannot connect to WinCC at {}:{}",
+                prefix, station.target_host, station.target_port
+            )
+        })?;
+        let dump_dir = station.dump_output.as_deref().unwrap_or_default();
+
+        for (spec, target) in targets {
+            let Some((path, vcs)) = target else {
+                log.warn(&format!(
+                    "{}No {} target found, skipping",
+                    prefix, spec.kind
+                ));
+                continue;
+            };
+            let result = tokio::time::timeout(
+                Duration::from_secs(timeout),
+                dump_target(&cfg, &log, station, &spec, (&path, vcs), Some(None), false),
+            )
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("timed out after {}s", timeout)));
+
+            match result {
+                Ok(stats) if stats.failed > 0 => {
+                    log.warn(&format!(
+                        "{}{}: {} in {}/{}/",
+                        prefix,
+                        spec.kind,
+                        stats.summary(),
+                        dump_dir,
+                        spec.kind.name()
+                    ));
+                    total += stats.written;
+                    failed.push(format!("{}{}", prefix, spec.kind));
+                }
+                Ok(stats) => {
+                    log.success(&format!(
+                        "{}{}: {} in {}/{}/",
+                        prefix,
+                        spec.kind,
+                        stats.summary(),
+                        dump_dir,
+                        spec.kind.name()
+                    ));
+                    total += stats.written;
+                }
+                Err(e) => {
+                    log.error(&format!("{}{}: {:#}", prefix, spec.kind, e));
+                    failed.push(format!("{}{}", prefix, spec.kind));
+                }
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        anyhow::bail!("Dump incomplete for {}", failed.join(", "));
+    }
+    if total == 0 {
+        anyhow::bail!("No scripts dumped: no matching WinCC targets found");
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ================================================================
+    // ScriptDumper::handle_script_parsed
+    // ================================================================
+
+    #[test]
+    fn attach_source_map_points_at_dumped_file() {
+        let dumper = make_dumper();
+        let mut event = serde_json::json!({
+            "method": "Debugger.scriptParsed",
+            "params": {
+                "scriptId": "42",
+                "url": "/screen_modules/Screen_Content/HMI_RT_1::HMI_Screen/Dynamics.js",
+                "endLine": 9,
+                "sourceMapURL": ""
+            }
+        });
+        assert!(dumper.attach_source_map(&mut event, "127.0.0.1:9230"));
+        assert_eq!(
+            event["params"]["sourceMapURL"],
+            "http://127.0.0.1:9230/sourcemap/Dynamics/screen_modules/Screen_Content/HMI_RT_1__HMI_Screen/Dynamics.js?lines=10"
+        );
+
+        // An existing source map is left alone
+        assert!(!dumper.attach_source_map(&mut event, "127.0.0.1:9230"));
+    }
+
+    fn make_dumper() -> ScriptDumper {
+        ScriptDumper::new("_test_dump".to_string(), "Dynamics")
+    }
+
+    #[test]
+    fn handle_script_parsed_valid_event() {
+        let mut dumper = make_dumper();
+        let event = serde_json::json!({
+            "method": "Debugger.scriptParsed",
+            "params": {
+                "scriptId": "42",
+                "url": "/screen_modules/Screen_Content/HMI_RT_1::HMI_Screen/Dynamics.js"
+            }
+        });
+        let result = dumper.handle_script_parsed(&event);
+        assert!(result.is_some());
+        let req: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+        assert_eq!(req["method"], "Debugger.getScriptSource");
+        assert_eq!(req["params"]["scriptId"], "42");
+        assert_eq!(req["id"], 900_000);
+    }
+
+    #[test]
+    fn colliding_urls_are_dumped_where_file_urls_point() {
+        let mut dumper = make_dumper();
+        for url in [
+            "/Screen/HMI_RT_1_Main/Events.js",
+            "/Screen/HMI_RT_1:Main/Events.js",
+        ] {
+            let event = serde_json::json!({
+                "method": "Debugger.scriptParsed",
+                "params": { "scriptId": "1", "url": url }
+            });
+            dumper.handle_script_parsed(&event).unwrap();
+        }
+        let mut files: Vec<String> = dumper
+            .pending
+            .values()
+            .map(|s| s.file_path.clone())
+            .collect();
+        files.sort();
+        let mut expected: Vec<String> = [
+            "/Screen/HMI_RT_1:Main/Events.js",
+            "/Screen/HMI_RT_1_Main/Events.js",
+        ]
+        .iter()
+        .map(|url| {
+            format!(
+                "_test_dump/{}",
+                crate::urlmap::dump_relative_path("Dynamics", url)
+            )
+        })
+        .collect();
+        expected.sort();
+        assert_eq!(files, expected);
+        assert_ne!(files[0], files[1]);
+    }
+
+    #[test]
+    fn handle_script_parsed_eval_cdp_skipped() {
+        let mut dumper = make_dumper();
+        let event = serde_json::json!({
+            "method": "Debugger.scriptParsed",
+            "params": { "scriptId": "99", "url": "eval-abc.cdp" }
+        });
+        assert!(dumper.handle_script_parsed(&event).is_none());
+    }
+
+    #[test]
+    fn handle_script_parsed_empty_url_skipped() {
+        let mut dumper = make_dumper();
+        let event = serde_json::json!({
+            "method": "Debugger.scriptParsed",
+            "params": { "scriptId": "99", "url": "" }
+        });
+        assert!(dumper.handle_script_parsed(&event).is_none());
+    }
+
+    #[test]
+    fn handle_script_parsed_wrong_method() {
+        let mut dumper = make_dumper();
+        let event = serde_json::json!({
+            "method": "Debugger.paused",
+            "params": { "scriptId": "1", "url": "foo.js" }
+        });
+        assert!(dumper.handle_script_parsed(&event).is_none());
+    }
+
+    // ================================================================
+    // ScriptDumper::handle_response
+    // ================================================================
+
+    #[test]
+    fn handle_response_matching_id_consumed() {
+        let scratch = tempfile::tempdir().unwrap();
+        let tmp = scratch.path();
+        let mut dumper = ScriptDumper::new(tmp.to_string_lossy().to_string(), "Dynamics");
+
+        let event = serde_json::json!({
+            "method": "Debugger.scriptParsed",
+            "params": { "scriptId": "1", "url": "test.js" }
+        });
+        dumper.handle_script_parsed(&event).unwrap();
+
+        let response = serde_json::json!({
+            "id": 900_000,
+            "result": { "scriptSource": "console.log('hello');" }
+        });
+        assert!(dumper.handle_response(&response));
+        assert!(dumper.pending.is_empty());
+    }
+
+    #[test]
+    fn split_dumps_write_a_file_per_function() {
+        let scratch = tempfile::tempdir().unwrap();
+        let tmp = scratch.path();
+        let mut dumper =
+            ScriptDumper::new(tmp.to_string_lossy().to_string(), "Events").with_split(true);
+
+        let event = serde_json::json!({
+            "method": "Debugger.scriptParsed",
+            "params": { "scriptId": "1", "url": "/Screen/Events.js" }
+        });
+        dumper.handle_script_parsed(&event).unwrap();
+        let source = "export function Button_1_OnTapped(item){}\nexport function Button_1_OnPressed(item){}\n";
+        dumper.handle_response(
+            &serde_json::json!({ "id": 900_000, "result": { "scriptSource": source } }),
+        );
+
+        let dir = tmp.join("Events").join("Screen").join("Events.split");
+        assert_eq!(
+            std::fs::read_to_string(dir.join("Button_1").join("OnTapped.js")).unwrap(),
+            "export function Button_1_OnTapped(item){}\n"
+        );
+        let index: SplitIndex =
+            serde_json::from_str(&std::fs::read_to_string(dir.join(INDEX_FILE)).unwrap()).unwrap();
+        assert_eq!(index.script, "Events.js");
+        assert_eq!(index.functions[1].file, "Button_1/OnPressed.js");
+        assert_eq!(index.functions[1].start_line, 2);
+
+        // A new dump of the script replaces the functions of the old one
+        dumper.handle_script_parsed(&event).unwrap();
+        let source = "export function Button_2_OnTapped(item){}\n";
+        dumper.handle_response(
+            &serde_json::json!({ "id": 900_001, "result": { "scriptSource": source } }),
+        );
+        assert!(!dir.join("Button_1").exists());
+        assert!(dir.join("Button_2").join("OnTapped.js").exists());
+    }
+
+    #[test]
+    fn pretty_dumps_keep_the_raw_source() {
+        let scratch = tempfile::tempdir().unwrap();
+        let tmp = scratch.path();
+        let mut dumper = ScriptDumper::new(tmp.to_string_lossy().to_string(), "Dynamics")
+            .with_format(DumpFormat::Pretty);
+
+        let mut event = serde_json::json!({
+            "method": "Debugger.scriptParsed",
+            "params": { "scriptId": "1", "url": "/Screen/Dynamics.js", "endLine": 0 }
+        });
+        dumper.handle_script_parsed(&event).unwrap();
+        let source = "export function f(item){if(item){return 1;}return 2;}";
+        dumper.handle_response(
+            &serde_json::json!({ "id": 900_000, "result": { "scriptSource": source } }),
+        );
+
+        let dir = tmp.join("Dynamics").join("Screen");
+        assert_eq!(
+            std::fs::read_to_string(dir.join("Dynamics.raw.js")).unwrap(),
+            source
+        );
+        let pretty = std::fs::read_to_string(dir.join("Dynamics.js")).unwrap();
+        assert!(pretty.lines().count() > 3, "{}", pretty);
+
+        // Source maps point at the copy with the runtime's lines
+        assert!(dumper.attach_source_map(&mut event, "127.0.0.1:9230"));
+        assert!(
+            event["params"]["sourceMapURL"]
+                .as_str()
+                .unwrap()
+                .contains("/Screen/Dynamics.raw.js?")
+        );
+
+        let manifest = std::fs::read_to_string(tmp.join("Dynamics").join(MANIFEST_FILE)).unwrap();
+        assert!(
+            manifest.contains("\"rawFile\": \"Screen/Dynamics.raw.js\""),
+            "{}",
+            manifest
+        );
+    }
+
+    #[test]
+    fn handle_response_unknown_id_not_consumed() {
+        let mut dumper = make_dumper();
+        let response = serde_json::json!({ "id": 999_999, "result": {} });
+        assert!(!dumper.handle_response(&response));
+    }
+
+    #[test]
+    fn handle_response_no_id_field() {
+        let mut dumper = make_dumper();
+        let msg = serde_json::json!({ "method": "Debugger.paused" });
+        assert!(!dumper.handle_response(&msg));
+    }
+
+    #[test]
+    fn handle_response_records_script_in_manifest() {
+        let scratch = tempfile::tempdir().unwrap();
+        let tmp = scratch.path();
+        let mut dumper = ScriptDumper::new(tmp.to_string_lossy().to_string(), "Events");
+        dumper.set_target("abc123", 8);
+
+        let event = serde_json::json!({
+            "method": "Debugger.scriptParsed",
+            "params": {
+                "scriptId": "42",
+                "url": "/screen_modules/Screen_Content/HMI_RT_1::HMI_Screen/Events.js",
+                "executionContextId": 3,
+                "startLine": 0,
+                "endLine": 12
+            }
+        });
+        dumper.handle_script_parsed(&event).unwrap();
+        let response = serde_json::json!({ "id": 900_000, "result": { "scriptSource": "abc" } });
+        assert!(dumper.handle_response(&response));
+
+        let text = std::fs::read_to_string(tmp.join("Events").join(MANIFEST_FILE)).unwrap();
+        let manifest: Manifest = serde_json::from_str(&text).unwrap();
+        assert_eq!(manifest.context, "Events");
+        let entry = &manifest.scripts[0];
+        assert_eq!(
+            entry.file,
+            "screen_modules/Screen_Content/HMI_RT_1__HMI_Screen/Events.js"
+        );
+        assert_eq!(entry.script_id, "42");
+        assert_eq!(entry.short_url.as_deref(), Some("HMI_Screen/Events.js"));
+        assert_eq!(
+            entry.hash,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(entry.length, 3);
+        assert_eq!(entry.execution_context_id, Some(3));
+        assert_eq!(entry.end_line, 12);
+        assert_eq!(entry.vcs, Some(8));
+        assert_eq!(entry.target.as_deref(), Some("abc123"));
+        assert!(text.contains("\"executionContextId\": 3"));
+
+        // A new dump of the same script replaces its entry
+        dumper.handle_script_parsed(&event).unwrap();
+        let response = serde_json::json!({ "id": 900_001, "result": { "scriptSource": "abcd" } });
+        dumper.handle_response(&response);
+        let text = std::fs::read_to_string(tmp.join("Events").join(MANIFEST_FILE)).unwrap();
+        let manifest: Manifest = serde_json::from_str(&text).unwrap();
+        assert_eq!(manifest.scripts.len(), 1);
+        assert_eq!(manifest.scripts[0].length, 4);
+    }
+
+    #[test]
+    fn commits_once_per_target_and_on_finish() {
+        let scratch = tempfile::tempdir().unwrap();
+        let mut dumper = ScriptDumper::new(scratch.path().to_string_lossy().to_string(), "Events")
+            .with_git(true);
+        dumper.set_target("abc123", 8);
+        let dump = |dumper: &mut ScriptDumper, id: &str| {
+            let request = dumper.handle_script_parsed(&parsed_event(id)).unwrap();
+            let id = serde_json::from_str::<serde_json::Value>(&request).unwrap()["id"].clone();
+            dumper.handle_response(
+                &serde_json::json!({ "id": id, "result": { "scriptSource": "x" } }),
+            );
+        };
+
+        dump(&mut dumper, "1");
+        let commit = dumper.take_commit().unwrap();
+        assert_eq!(commit.origin.context, "Events");
+        assert_eq!(commit.origin.vcs, Some(8));
+        commit.run();
+        assert!(scratch.path().join(".git").exists());
+
+        // Scripts loaded later wait for the next target or the end
+        dump(&mut dumper, "2");
+        assert!(dumper.take_commit().is_none());
+        dumper.set_target("def456", 9);
+        dump(&mut dumper, "3");
+        assert_eq!(dumper.take_commit().unwrap().origin.vcs, Some(9));
+        dump(&mut dumper, "4");
+        dumper.finish();
+        assert!(dumper.take_commit().is_some());
+        dumper.finish();
+        assert!(dumper.take_commit().is_none());
+    }
+
+    #[test]
+    fn concurrent_connections_keep_every_manifest_entry() {
+        let scratch = tempfile::tempdir().unwrap();
+        let dump_dir = scratch.path().to_string_lossy().to_string();
+        let threads: Vec<_> = (0..8)
+            .map(|n| {
+                let dump_dir = dump_dir.clone();
+                std::thread::spawn(move || {
+                    let mut dumper = ScriptDumper::new(dump_dir, "Events");
+                    for round in 0..5 {
+                        let url = format!(
+                            "/screen_modules/Screen_Content/HMI_RT_1::Screen_{}/Events_{}.js",
+                            n, round
+                        );
+                        let event = serde_json::json!({
+                            "method": "Debugger.scriptParsed",
+                            "params": { "scriptId": format!("{}", round), "url": url }
+                        });
+                        let request = dumper.handle_script_parsed(&event).unwrap();
+                        let id = serde_json::from_str::<serde_json::Value>(&request).unwrap()["id"]
+                            .clone();
+                        dumper.handle_response(
+                            &serde_json::json!({ "id": id, "result": { "scriptSource": "x" } }),
+                        );
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let text =
+            std::fs::read_to_string(scratch.path().join("Events").join(MANIFEST_FILE)).unwrap();
+        let manifest: Manifest = serde_json::from_str(&text).unwrap();
+        assert_eq!(manifest.scripts.len(), 40);
+    }
+
+    fn parsed_event(script_id: &str) -> serde_json::Value {
+        serde_json::json!({
+            "method": "Debugger.scriptParsed",
+            "params": { "scriptId": script_id, "url": "/screen_modules/Screen_Content/HMI_RT_1::HMI_Screen/Events.js" }
+        })
+    }
+
+    #[test]
+    fn error_responses_are_retried_then_failed() {
+        let mut dumper = make_dumper();
+        dumper.handle_script_parsed(&parsed_event("1")).unwrap();
+        dumper.handle_script_parsed(&parsed_event("2")).unwrap();
+        dumper.handle_script_parsed(&serde_json::json!({
+            "method": "Debugger.scriptParsed",
+            "params": { "scriptId": "3", "url": "eval-1.cdp" }
+        }));
+
+        let error = serde_json::json!({ "id": 900_000, "error": { "code": -32000, "message": "No script for id: 1" } });
+        assert!(dumper.handle_response(&error));
+        let empty = serde_json::json!({ "id": 900_001, "result": {} });
+        assert!(dumper.handle_response(&empty));
+        assert_eq!(dumper.stats.failed, 1);
+        assert!(!dumper.is_idle());
+
+        // The script with the error is asked for again on the next poll
+        for _ in 2..=MAX_ATTEMPTS {
+            let requests = dumper.poll_at(Instant::now());
+            assert_eq!(requests.len(), 1);
+            let request: serde_json::Value = serde_json::from_str(&requests[0]).unwrap();
+            assert_eq!(request["params"]["scriptId"], "1");
+            assert!(dumper.handle_response(
+                &serde_json::json!({ "id": request["id"], "error": { "message": "busy" } })
+            ));
+        }
+        assert!(dumper.poll_at(Instant::now()).is_empty());
+        assert!(dumper.is_idle());
+        assert_eq!(
+            dumper.stats,
+            DumpStats {
+                written: 0,
+                failed: 2,
+                skipped: 1,
+                dropped: 0
+            }
+        );
+    }
+
+    #[test]
+    fn target_change_counts_dropped_requests() {
+        let mut dumper = make_dumper();
+        dumper.handle_script_parsed(&parsed_event("1")).unwrap();
+        dumper.handle_script_parsed(&parsed_event("2")).unwrap();
+        dumper.set_target("def456", 9);
+        assert!(dumper.is_idle());
+        assert_eq!(dumper.stats.dropped, 2);
+        assert_eq!(
+            dumper.finish().summary(),
+            "0 written, 0 failed, 0 skipped, 2 dropped"
+        );
+    }
+
+    #[test]
+    fn given_up_requests_are_forgotten() {
+        let mut dumper = make_dumper();
+        dumper.handle_script_parsed(&parsed_event("1")).unwrap();
+        let later = Instant::now() + REQUEST_TIMEOUT;
+        dumper.poll_at(later);
+        assert!(dumper.abandoned.contains_key(&900_000));
+        dumper.poll_at(later + REQUEST_TIMEOUT);
+        assert!(!dumper.abandoned.contains_key(&900_000));
+
+        dumper.set_target("def456", 9);
+        assert!(dumper.abandoned.is_empty());
+    }
+
+    #[test]
+    fn unanswered_requests_are_retried_then_failed() {
+        let mut dumper = make_dumper();
+        dumper.handle_script_parsed(&parsed_event("7")).unwrap();
+        assert!(dumper.poll_at(Instant::now()).is_empty());
+
+        let mut later = Instant::now();
+        for attempt in 2..=MAX_ATTEMPTS {
+            later += REQUEST_TIMEOUT;
+            let requests = dumper.poll_at(later);
+            assert_eq!(requests.len(), 1, "attempt {}", attempt);
+            let request: serde_json::Value = serde_json::from_str(&requests[0]).unwrap();
+            assert_eq!(request["params"]["scriptId"], "7");
+        }
+        later += REQUEST_TIMEOUT;
+        assert!(dumper.poll_at(later).is_empty());
+        assert!(dumper.is_idle());
+        assert_eq!(dumper.stats.failed, 1);
+
+        // A late answer to an abandoned request is still kept from the client
+        let late = serde_json::json!({ "id": 900_002, "result": { "scriptSource": "x" } });
+        assert!(dumper.handle_response(&late));
+        assert!(!dumper.handle_response(&late));
+        assert_eq!(dumper.stats.written, 0);
+    }
+
+    #[test]
+    fn finish_fails_outstanding_requests() {
+        let mut dumper = make_dumper();
+        dumper.handle_script_parsed(&parsed_event("1")).unwrap();
+        let stats = dumper.finish();
+        assert_eq!(stats.failed, 1);
+        assert_eq!(stats.summary(), "0 written, 1 failed, 0 skipped");
+    }
+
+    // ================================================================
+    // Headless dump
+    // ================================================================
+
+    #[tokio::test]
+    async fn dump_target_writes_every_script_of_a_mock_target() {
+        use crate::mock::{MockTarget, MockWinCC};
+
+        let scratch = tempfile::tempdir().unwrap();
+        let tmp = scratch.path();
+        let mock = MockWinCC::start(vec![
+            MockTarget::new("ev1", " @localhost VCS_2 Events")
+                .with_script(
+                    "1",
+                    "/screen_modules/Screen_Content/HMI_RT_1::Main/Events.js",
+                    "export function Button_1_OnTapped(item) {}\n",
+                )
+                .with_script(
+                    "2",
+                    "/screen_modules/Screen_Content/HMI_RT_1::Popup/Events.js",
+                    "export function Button_2_OnTapped(item) {}\n",
+                )
+                .with_script("3", "eval-17.cdp", "1 + 1"),
+        ])
+        .await;
+        let station = mock.station(Some(tmp.to_string_lossy().into_owned()));
+        let spec = station.contexts[1].clone();
+        let cfg = Configuration::default();
+
+        // Scripts left by a previous run are replaced once the target is reached
+        std::fs::create_dir_all(tmp.join("Events")).unwrap();
+        std::fs::write(tmp.join("Events/Old.js"), "old").unwrap();
+        let stats = dump_target(
+            &cfg,
+            &Logger::default(),
+            &station,
+            &spec,
+            ("ev1", 2),
+            Some(None),
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            stats,
+            DumpStats {
+                written: 2,
+                failed: 0,
+                skipped: 1,
+                dropped: 0
+            }
+        );
+        let dir = tmp.join("Events/screen_modules/Screen_Content");
+        assert!(dir.join("HMI_RT_1__Main/Events.js").exists());
+        assert!(dir.join("HMI_RT_1__Popup/Events.js").exists());
+        assert!(!tmp.join("Events/Old.js").exists());
+
+        // A target that went away is an error, not an empty dump, and the last dump stays
+        mock.set_targets(Vec::new());
+        assert!(
+            dump_target(
+                &cfg,
+                &Logger::default(),
+                &station,
+                &spec,
+                ("ev1", 2),
+                Some(None),
+                false
+            )
+            .await
+            .is_err()
+        );
+        assert!(dir.join("HMI_RT_1__Main/Events.js").exists());
+    }
+
+    // ================================================================
+    // Dump history
+    // ================================================================
+
+    #[test]
+    fn snapshot_name_includes_known_vcs() {
+        use chrono::TimeZone;
+        let time = chrono::Local
+            .with_ymd_and_hms(2024, 3, 5, 14, 7, 9)
+            .unwrap();
+        assert_eq!(snapshot_name(time, Some(8)), "20240305-140709_vcs8");
+        assert_eq!(snapshot_name(time, Some(0)), "20240305-140709");
+        assert_eq!(snapshot_name(time, None), "20240305-140709");
+    }
+
+    #[test]
+    fn archive_moves_scripts_to_numbered_snapshots() {
+        let scratch = tempfile::tempdir().unwrap();
+        let tmp = scratch.path();
+        let dump_dir = tmp.to_string_lossy().to_string();
+        let live = tmp.join("Events");
+
+        std::fs::create_dir_all(live.join("HMI_Screen")).unwrap();
+        std::fs::write(live.join("HMI_Screen/Events.js"), "v1").unwrap();
+        let first = archive_context_dump(&dump_dir, &ContextKind::Events, Some(3))
+            .unwrap()
+            .unwrap();
+        assert!(!live.exists());
+        assert!(first.starts_with(tmp.join(HISTORY_DIR).join("Events")));
+        assert_eq!(
+            std::fs::read_to_string(first.join("HMI_Screen/Events.js")).unwrap(),
+            "v1"
+        );
+
+        // A second snapshot in the same second must not overwrite the first
+        std::fs::create_dir_all(&live).unwrap();
+        std::fs::write(live.join("Events.js"), "v2").unwrap();
+        let second = archive_context_dump(&dump_dir, &ContextKind::Events, Some(3))
+            .unwrap()
+            .unwrap();
+        assert_ne!(first, second);
+        assert!(first.join("HMI_Screen/Events.js").exists());
+
+        assert_eq!(
+            archive_context_dump(&dump_dir, &ContextKind::Events, None).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,444 @@
+use crate::config::ContextKind;
+use crate::dump::DumpStats;
+use crate::logging::{Level, LogRecord, Tag};
+
+// ============================================================================
+// Proxy Events
+// ============================================================================
+
+/// The station and script context an event belongs to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventOrigin {
+    /// Profile name of the station, `None` for an unnamed station
+    pub station: Option<String>,
+    pub context: ContextKind,
+}
+
+impl EventOrigin {
+    /// The station's log prefix, e.g. `"[line3] "`.
+    fn prefix(&self) -> String {
+        station_prefix(&self.station)
+    }
+
+    /// `Dynamics`, or `[line3] Dynamics` for a named station.
+    fn label(&self) -> String {
+        format!("{}{}", self.prefix(), self.context)
+    }
+
+    /// `[Dynamics] Client #7`, or `[line3] [Dynamics] Client #7`.
+    fn client_label(&self, client_id: u32) -> String {
+        format!("{}[{}] Client #{}", self.prefix(), self.context, client_id)
+    }
+}
+
+/// Why a client's connection through the proxy ended.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DisconnectReason {
+    /// VS Code closed the connection
+    ClientClosed,
+    /// The target closed the connection
+    TargetClosed,
+    /// The context's server restarts for a new target
+    TargetChanged,
+    /// The proxy shuts down
+    ProxyStopped,
+    /// The client connected before a target was discovered
+    NoTarget,
+    /// The target refused the connection
+    TargetUnreachable(String),
+}
+
+impl DisconnectReason {
+    /// Machine-readable name, e.g. `"target_closed"`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DisconnectReason::ClientClosed => "client_closed",
+            DisconnectReason::TargetClosed => "target_closed",
+            DisconnectReason::TargetChanged => "target_changed",
+            DisconnectReason::ProxyStopped => "proxy_stopped",
+            DisconnectReason::NoTarget => "no_target",
+            DisconnectReason::TargetUnreachable(_) => "target_unreachable",
+        }
+    }
+}
+
+/// What a running proxy reports to its subscribers, in order.
+#[derive(Debug, Clone)]
+pub enum ProxyEvent {
+    /// Every context's server is listening; target polling starts next
+    Ready,
+    /// A station's debug server answered, for the first time or after
+    /// `after_failures` failed polls
+    TargetServerConnected {
+        station: Option<String>,
+        host: String,
+        port: u16,
+        after_failures: u32,
+    },
+    /// Polling a station's debug server failed `attempts` times in a row
+    TargetServerUnreachable {
+        station: Option<String>,
+        host: String,
+        port: u16,
+        reason: String,
+        attempts: u32,
+        /// Seconds until the next attempt
+        retry_secs: u64,
+    },
+    /// A context got its first target
+    TargetDiscovered { origin: EventOrigin, path: String },
+    /// A context moved to a new target, e.g. after a download
+    TargetChanged {
+        origin: EventOrigin,
+        old_path: String,
+        new_path: String,
+    },
+    /// A client connected to a context's server
+    ClientConnected { origin: EventOrigin, client_id: u32 },
+    /// The proxy connected a client to a target
+    ClientAttached {
+        origin: EventOrigin,
+        client_id: u32,
+        path: String,
+    },
+    /// A client with a kept session was moved to the context's new target
+    ClientRetargeted {
+        origin: EventOrigin,
+        client_id: u32,
+        path: String,
+    },
+    ClientDisconnected {
+        origin: EventOrigin,
+        client_id: u32,
+        reason: DisconnectReason,
+    },
+    /// A batch of scripts was dumped; `stats` counts every script so far
+    DumpProgress {
+        origin: EventOrigin,
+        stats: DumpStats,
+    },
+    /// A client's connection ended with scripts dumped through it
+    DumpFinished {
+        origin: EventOrigin,
+        client_id: u32,
+        stats: DumpStats,
+    },
+    /// A line of log output
+    Log(LogRecord),
+    /// The proxy shut down; nothing follows
+    Stopped,
+}
+
+fn still_unreachable(station: &Option<String>, attempts: u32, retry_secs: u64) -> LogRecord {
+    LogRecord::now(
+        Level::Info,
+        None,
+        format!(
+            "{}Still cannot connect to WinCC ({} failed attempts, retrying every {}s)",
+            station_prefix(station),
+            attempts,
+            retry_secs
+        ),
+    )
+}
+
+fn station_prefix(station: &Option<String>) -> String {
+    match station {
+        Some(name) => format!("[{}] ", name),
+        None => String::new(),
+    }
+}
+
+pub(crate) fn decode(path: &str) -> String {
+    urlencoding::decode(path).map_or_else(|_| path.to_string(), |p| p.into_owned())
+}
+
+impl ProxyEvent {
+    /// Machine-readable name of the event, e.g. `"client_connected"`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProxyEvent::Ready => "ready",
+            ProxyEvent::TargetServerConnected { .. } => "target_server_connected",
+            ProxyEvent::TargetServerUnreachable { .. } => "target_server_unreachable",
+            ProxyEvent::TargetDiscovered { .. } => "target_discovered",
+            ProxyEvent::TargetChanged { .. } => "target_changed",
+            ProxyEvent::ClientConnected { .. } => "client_connected",
+            ProxyEvent::ClientAttached { .. } => "client_attached",
+            ProxyEvent::ClientRetargeted { .. } => "client_retargeted",
+            ProxyEvent::ClientDisconnected { .. } => "client_disconnected",
+            ProxyEvent::DumpProgress { .. } => "dump_progress",
+            ProxyEvent::DumpFinished { .. } => "dump_finished",
+            ProxyEvent::Log(_) => "log",
+            ProxyEvent::Stopped => "stopped",
+        }
+    }
+
+    /// The record an event's JSON line takes its time, level, tag and
+    /// message from. Unlike `records`, every event has one, so machine
+    /// consumers see the events text output throttles or leaves out.
+    pub fn headline(&self) -> LogRecord {
+        match self {
+            ProxyEvent::Ready => {
+                LogRecord::now(Level::Info, Some(Tag::Ready), "Proxy ready".to_string())
+            }
+            ProxyEvent::Stopped => {
+                LogRecord::now(Level::Info, Some(Tag::Stop), "Proxy stopped".to_string())
+            }
+            ProxyEvent::TargetServerUnreachable {
+                station,
+                attempts,
+                retry_secs,
+                ..
+            } if *attempts > 1 => still_unreachable(station, *attempts, *retry_secs),
+            _ => self.records().remove(0),
+        }
+    }
+
+    /// The event as console lines. `Ready` and `Stopped` have none; what to
+    /// show for them is up to the subscriber.
+    pub fn records(&self) -> Vec<LogRecord> {
+        use LogRecord as R;
+        match self {
+            ProxyEvent::Ready | ProxyEvent::Stopped => Vec::new(),
+            ProxyEvent::Log(record) => vec![record.clone()],
+            ProxyEvent::TargetServerConnected {
+                station,
+                host,
+                port,
+                after_failures,
+            } => {
+                let prefix = station_prefix(station);
+                let mut records = Vec::new();
+                if *after_failures > 0 {
+                    records.push(R::now(
+                        Level::Success,
+                        None,
+                        format!("{}Target server is back online!", prefix),
+                    ));
+                }
+                records.push(R::now(
+                    Level::Info,
+                    Some(Tag::Conn),
+                    format!(
+                        "{}WinCC target server connected at {}:{}",
+                        prefix, host, port
+                    ),
+                ));
+                records
+            }
+            ProxyEvent::TargetServerUnreachable {
+                station,
+                host,
+                port,
+                reason,
+                attempts,
+                retry_secs,
+            } => {
+                let prefix = station_prefix(station);
+                if *attempts == 1 {
+                    vec![
+                        R::now(
+                            Level::Error,
+                            None,
+                            format!("{}Cannot connect to WinCC at {}:{}", prefix, host, port),
+                        ),
+                        R::now(
+                            Level::Error,
+                            None,
+                            format!("{}   Reason: {}", prefix, reason),
+                        ),
+                        R::now(
+                            Level::Info,
+                            None,
+                            format!("{}Will retry every {} seconds...", prefix, retry_secs),
+                        ),
+                    ]
+                } else if attempts % 5 == 0 {
+                    vec![still_unreachable(station, *attempts, *retry_secs)]
+                } else {
+                    Vec::new()
+                }
+            }
+            ProxyEvent::TargetDiscovered { origin, path } => vec![R::now(
+                Level::Info,
+                Some(Tag::Conn),
+                format!("{} target discovered: {}", origin.label(), decode(path)),
+            )],
+            ProxyEvent::TargetChanged {
+                origin,
+                old_path,
+                new_path,
+            } => {
+                let prefix = origin.prefix();
+                vec![
+                    R::now(
+                        Level::Info,
+                        Some(Tag::Change),
+                        format!("{} target changed:", origin.label()),
+                    ),
+                    R::now(
+                        Level::Info,
+                        None,
+                        format!("{}   Old: {}", prefix, decode(old_path)),
+                    ),
+                    R::now(
+                        Level::Info,
+                        None,
+                        format!("{}   New: {}", prefix, decode(new_path)),
+                    ),
+                ]
+            }
+            ProxyEvent::ClientConnected { origin, client_id } => vec![R::now(
+                Level::Success,
+                None,
+                format!("{} connected", origin.client_label(*client_id)),
+            )],
+            ProxyEvent::ClientAttached {
+                origin, client_id, ..
+            } => vec![R::now(
+                Level::Info,
+                Some(Tag::Conn),
+                format!("{}: Connected to target", origin.client_label(*client_id)),
+            )],
+            ProxyEvent::ClientRetargeted {
+                origin, client_id, ..
+            } => vec![R::now(
+                Level::Info,
+                Some(Tag::Change),
+                format!(
+                    "{}: Switching to new target, keeping session",
+                    origin.client_label(*client_id)
+                ),
+            )],
+            ProxyEvent::ClientDisconnected {
+                origin,
+                client_id,
+                reason,
+            } => {
+                let client = origin.client_label(*client_id);
+                let (level, tag, message) = match reason {
+                    DisconnectReason::ClientClosed => (
+                        Level::Info,
+                        Some(Tag::Disc),
+                        format!("{} disconnected (client closed)", client),
+                    ),
+                    DisconnectReason::TargetClosed => (
+                        Level::Info,
+                        Some(Tag::Disc),
+                        format!("{} disconnected (target closed)", client),
+                    ),
+                    DisconnectReason::TargetChanged => (
+                        Level::Info,
+                        Some(Tag::Stop),
+                        format!("{}: Closing due to target change", client),
+                    ),
+                    DisconnectReason::ProxyStopped => (
+                        Level::Info,
+                        Some(Tag::Stop),
+                        format!("{}: Closing, the proxy stops", client),
+                    ),
+                    DisconnectReason::NoTarget => (
+                        Level::Error,
+                        None,
+                        format!("{}: No target path available yet", client),
+                    ),
+                    DisconnectReason::TargetUnreachable(e) => (
+                        Level::Error,
+                        None,
+                        format!("{}: Failed to connect to target: {}", client, e),
+                    ),
+                };
+                vec![R::now(level, tag, message)]
+            }
+            ProxyEvent::DumpProgress { origin, stats } => vec![R::now(
+                Level::Verbose,
+                None,
+                format!("[DUMP] {}: {}", origin.label(), stats.summary()),
+            )],
+            ProxyEvent::DumpFinished {
+                origin,
+                client_id,
+                stats,
+            } => vec![R::now(
+                Level::Info,
+                None,
+                format!(
+                    "{}: Dumped scripts: {}",
+                    origin.client_label(*client_id),
+                    stats.summary()
+                ),
+            )],
+        }
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(event: &ProxyEvent) -> Vec<String> {
+        event.records().into_iter().map(|r| r.message).collect()
+    }
+
+    #[test]
+    fn client_lines_name_station_context_and_client() {
+        let origin = EventOrigin {
+            station: Some("line3".to_string()),
+            context: ContextKind::Events,
+        };
+        let event = ProxyEvent::ClientDisconnected {
+            origin,
+            client_id: 7,
+            reason: DisconnectReason::TargetClosed,
+        };
+        assert_eq!(
+            messages(&event),
+            vec!["[line3] [Events] Client #7 disconnected (target closed)"]
+        );
+        assert_eq!(event.records()[0].tag, Some(Tag::Disc));
+    }
+
+    #[test]
+    fn unreachable_servers_are_reported_on_the_first_and_every_fifth_attempt() {
+        let unreachable = |attempts| ProxyEvent::TargetServerUnreachable {
+            station: None,
+            host: "plc".to_string(),
+            port: 9222,
+            reason: "refused".to_string(),
+            attempts,
+            retry_secs: 1,
+        };
+        assert_eq!(
+            messages(&unreachable(1)),
+            vec![
+                "Cannot connect to WinCC at plc:9222",
+                "   Reason: refused",
+                "Will retry every 1 seconds..."
+            ]
+        );
+        assert!(messages(&unreachable(2)).is_empty());
+        assert_eq!(
+            messages(&unreachable(10)),
+            vec!["Still cannot connect to WinCC (10 failed attempts, retrying every 1s)"]
+        );
+    }
+
+    #[test]
+    fn target_paths_are_shown_decoded() {
+        let event = ProxyEvent::TargetDiscovered {
+            origin: EventOrigin {
+                station: None,
+                context: ContextKind::Dynamics,
+            },
+            path: "VCS_8%20Dynamics".to_string(),
+        };
+        assert_eq!(
+            messages(&event),
+            vec!["Dynamics target discovered: VCS_8 Dynamics"]
+        );
+    }
+}
@@ -0,0 +1,310 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+use gix::ObjectId;
+use gix::object::tree::EntryKind;
+
+// ============================================================================
+// Dump Repository
+// ============================================================================
+
+/// Written to a dump directory when it becomes a repository. The history
+/// snapshots and the styleguide's npm packages are not worth versioning.
+const GITIGNORE: &str = ".history/\nnode_modules/\n";
+
+/// Author and committer of the dump commits.
+const SIGNATURE_NAME: &str = "WinCC Unified Debug Proxy";
+const SIGNATURE_EMAIL: &str = "debug-proxy@localhost";
+
+/// Serializes commits, since every connection of every context commits to
+/// the dump directory on its own.
+static COMMIT_LOCK: Mutex<()> = Mutex::new(());
+
+/// What a dump commit is about: the context whose dump cycle completed and
+/// the target its scripts came from.
+#[derive(Debug, Clone)]
+pub struct DumpOrigin {
+    pub context: String,
+    pub target: Option<String>,
+    pub vcs: Option<u32>,
+}
+
+/// Files that differ between the last commit and the dump directory.
+#[derive(Debug, Default, PartialEq)]
+pub struct Changes {
+    pub added: Vec<String>,
+    pub modified: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl Changes {
+    fn between(old: &BTreeMap<String, ObjectId>, new: &BTreeMap<String, ObjectId>) -> Self {
+        let mut changes = Self::default();
+        for (path, id) in new {
+            match old.get(path) {
+                None => changes.added.push(path.clone()),
+                Some(old_id) if old_id != id => changes.modified.push(path.clone()),
+                Some(_) => {}
+            }
+        }
+        changes.removed = old
+            .keys()
+            .filter(|p| !new.contains_key(*p))
+            .cloned()
+            .collect();
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.modified.is_empty() && self.removed.is_empty()
+    }
+
+    /// `3 added, 1 modified`, leaving out empty categories.
+    pub fn summary(&self) -> String {
+        let parts: Vec<String> = [
+            (self.added.len(), "added"),
+            (self.modified.len(), "modified"),
+            (self.removed.len(), "removed"),
+        ]
+        .iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, what)| format!("{} {}", n, what))
+        .collect();
+        parts.join(", ")
+    }
+}
+
+/// Commit message of a dump commit: a subject with the context, VCS number
+/// and change summary, then the target and one line per changed file.
+fn commit_message(origin: &DumpOrigin, changes: &Changes) -> String {
+    let mut message = match origin.vcs {
+        Some(vcs) => format!("{} VCS_{}: {}\n", origin.context, vcs, changes.summary()),
+        None => format!("{}: {}\n", origin.context, changes.summary()),
+    };
+    if let Some(ref target) = origin.target {
+        let decoded = urlencoding::decode(target).unwrap_or_else(|_| target.into());
+        message.push_str(&format!("\nTarget: {}\n", decoded));
+    }
+    message.push('\n');
+    for (marker, paths) in [
+        ("A", &changes.added),
+        ("M", &changes.modified),
+        ("D", &changes.removed),
+    ] {
+        for path in paths {
+            message.push_str(&format!("{} {}\n", marker, path));
+        }
+    }
+    message
+}
+
+/// Open the repository of a dump directory, creating it on first use. The
+/// `.gitignore` is written whenever it is missing.
+fn open_or_init(dir: &Path) -> Result<gix::Repository> {
+    let repo = if dir.join(".git").exists() {
+        gix::open(dir).with_context(|| format!("Cannot open repository {}", dir.display()))?
+    } else {
+        std::fs::create_dir_all(dir)?;
+        gix::init(dir).with_context(|| format!("Cannot create repository {}", dir.display()))?
+    };
+    let gitignore = dir.join(".gitignore");
+    if !gitignore.exists() {
+        std::fs::write(gitignore, GITIGNORE)?;
+    }
+    Ok(repo)
+}
+
+/// Store every file below `root` as a blob, by path relative to it with
+/// `prefix` in front. Hidden directories and `node_modules` are left out.
+fn write_blobs(
+    repo: &gix::Repository,
+    root: &Path,
+    prefix: &str,
+) -> Result<BTreeMap<String, ObjectId>> {
+    fn walk(
+        repo: &gix::Repository,
+        dir: &Path,
+        prefix: &str,
+        files: &mut BTreeMap<String, ObjectId>,
+    ) -> Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            let relative = format!("{}{}", prefix, name);
+            if path.is_dir() {
+                if !name.starts_with('.') && name != "node_modules" {
+                    walk(repo, &path, &format!("{}/", relative), files)?;
+                }
+            } else {
+                let id = repo.write_blob(std::fs::read(&path)?)?;
+                files.insert(relative, id.detach());
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = BTreeMap::new();
+    if root.is_dir() {
+        walk(repo, root, prefix, &mut files)?;
+    }
+    Ok(files)
+}
+
+/// The files of the `HEAD` commit, empty for a new repository.
+fn head_files(repo: &gix::Repository) -> Result<BTreeMap<String, ObjectId>> {
+    let Ok(commit) = repo.head_commit() else {
+        return Ok(BTreeMap::new());
+    };
+    let mut recorder = gix::traverse::tree::Recorder::default();
+    commit.tree()?.traverse().breadthfirst(&mut recorder)?;
+    Ok(recorder
+        .records
+        .into_iter()
+        .filter(|entry| !entry.mode.is_tree())
+        .map(|entry| (entry.filepath.to_string(), entry.oid))
+        .collect())
+}
+
+/// Commit the current state of the origin's context directory and the
+/// `.gitignore`, creating the repository on first use. The other contexts stay
+/// as they were in the last commit. Returns `None` if nothing changed since.
+/// Walks and hashes the whole context, so async code should call it through
+/// `spawn_blocking`.
+pub fn commit_dump(dump_dir: &Path, origin: &DumpOrigin) -> Result<Option<Changes>> {
+    let _guard = COMMIT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let repo = open_or_init(dump_dir)?;
+
+    let head = head_files(&repo)?;
+    let context_prefix = format!("{}/", origin.context);
+    let mut files: BTreeMap<String, ObjectId> = head
+        .iter()
+        .filter(|(path, _)| !path.starts_with(&context_prefix) && *path != ".gitignore")
+        .map(|(path, id)| (path.clone(), *id))
+        .collect();
+    files.extend(write_blobs(
+        &repo,
+        &dump_dir.join(&origin.context),
+        &context_prefix,
+    )?);
+    let gitignore = repo.write_blob(std::fs::read(dump_dir.join(".gitignore"))?)?;
+    files.insert(".gitignore".to_string(), gitignore.detach());
+    let changes = Changes::between(&head, &files);
+    if changes.is_empty() {
+        return Ok(None);
+    }
+
+    let mut editor = repo.empty_tree().edit()?;
+    for (path, id) in &files {
+        editor.upsert(path.as_str(), EntryKind::Blob, *id)?;
+    }
+    let tree = editor.write()?.detach();
+
+    let signature = gix::actor::Signature {
+        name: SIGNATURE_NAME.into(),
+        email: SIGNATURE_EMAIL.into(),
+        time: gix::date::Time::now_local_or_utc(),
+    };
+    let mut time = gix::date::parse::TimeBuf::default();
+    let signature = signature.to_ref(&mut time);
+    let parents: Vec<ObjectId> = repo
+        .head_id()
+        .ok()
+        .map(|id| id.detach())
+        .into_iter()
+        .collect();
+    repo.commit_as(
+        signature,
+        signature,
+        "HEAD",
+        commit_message(origin, &changes),
+        tree,
+        parents,
+    )?;
+
+    // Keep the index in step so `git status` in the dump directory is clean
+    let mut index = repo.index_from_tree(&tree)?;
+    index.write(Default::default())?;
+
+    Ok(Some(changes))
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commits_only_when_the_dump_changed() {
+        let scratch = tempfile::tempdir().unwrap();
+        let tmp = scratch.path();
+        let origin = DumpOrigin {
+            context: "Events".to_string(),
+            target: Some("abc%20123".to_string()),
+            vcs: Some(8),
+        };
+
+        std::fs::create_dir_all(tmp.join("Events/HMI_Screen")).unwrap();
+        std::fs::write(tmp.join("Events/HMI_Screen/Events.js"), "v1").unwrap();
+        std::fs::create_dir_all(tmp.join(".history/Events/old")).unwrap();
+        std::fs::write(tmp.join(".history/Events/old/Events.js"), "v0").unwrap();
+        let changes = commit_dump(tmp, &origin).unwrap().unwrap();
+        assert_eq!(
+            changes.added,
+            vec![".gitignore", "Events/HMI_Screen/Events.js"]
+        );
+
+        assert_eq!(commit_dump(tmp, &origin).unwrap(), None);
+
+        std::fs::write(tmp.join("Events/HMI_Screen/Events.js"), "v2").unwrap();
+        std::fs::write(tmp.join("Events/Other.js"), "new").unwrap();
+        let changes = commit_dump(tmp, &origin).unwrap().unwrap();
+        assert_eq!(changes.summary(), "1 added, 1 modified");
+
+        let repo = gix::open(tmp).unwrap();
+        let head = repo.head_commit().unwrap();
+        let message = head.message_raw().unwrap().to_string();
+        assert!(message.starts_with("Events VCS_8: 1 added, 1 modified\n"));
+        assert!(message.contains("Target: abc 123"));
+        assert!(message.contains("M Events/HMI_Screen/Events.js"));
+        assert_eq!(head.parent_ids().count(), 1);
+    }
+
+    #[test]
+    fn commits_stage_only_the_origin_context() {
+        let scratch = tempfile::tempdir().unwrap();
+        let tmp = scratch.path();
+        let origin = |context: &str| DumpOrigin {
+            context: context.to_string(),
+            target: None,
+            vcs: None,
+        };
+        std::fs::create_dir_all(tmp.join("Events")).unwrap();
+        std::fs::create_dir_all(tmp.join("Dynamics")).unwrap();
+        std::fs::write(tmp.join("Events/Events.js"), "v1").unwrap();
+        std::fs::write(tmp.join("Dynamics/Dynamics.js"), "v1").unwrap();
+
+        let changes = commit_dump(tmp, &origin("Events")).unwrap().unwrap();
+        assert_eq!(changes.added, vec![".gitignore", "Events/Events.js"]);
+
+        // Another context's change waits for its own commit
+        std::fs::write(tmp.join("Events/Events.js"), "v2").unwrap();
+        let changes = commit_dump(tmp, &origin("Dynamics")).unwrap().unwrap();
+        assert_eq!(changes.added, vec!["Dynamics/Dynamics.js"]);
+        assert!(changes.modified.is_empty());
+
+        // A deleted .gitignore comes back
+        std::fs::remove_file(tmp.join(".gitignore")).unwrap();
+        let changes = commit_dump(tmp, &origin("Events")).unwrap().unwrap();
+        assert_eq!(changes.modified, vec!["Events/Events.js"]);
+        assert!(tmp.join(".gitignore").exists());
+    }
+}
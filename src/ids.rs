@@ -0,0 +1,141 @@
+use serde_json::Value;
+use std::collections::HashMap;
+
+// ============================================================================
+// Message Ids
+// ============================================================================
+
+// Every request a connection sends to the target has an id in one of these
+// disjoint ranges. The proxy's own commands use fixed bases far below the
+// range client requests are moved into, so a response always tells whether
+// the proxy or the client asked. The target parses ids as 32-bit integers,
+// which bounds the client range.
+
+/// `BreakpointSync` restoring and replacing breakpoints.
+pub const BREAKPOINT_ID_BASE: u64 = 700_000;
+/// `Session` replaying commands on a new target.
+pub const REPLAY_ID_BASE: u64 = 800_000;
+/// `ScriptDumper` fetching script sources.
+pub const DUMP_ID_BASE: u64 = 900_000;
+/// Client requests, renumbered by `IdMap`.
+pub const CLIENT_ID_BASE: u64 = 1 << 30;
+
+/// Renumbers one client's requests into the client range and restores the
+/// client's own ids in the responses.
+///
+/// Client messages go through `renumber_request` before anything else looks at
+/// them, so the rest of the proxy only ever sees ids it can tell apart.
+/// Everything sent to the client goes through `restore_response` last.
+#[derive(Debug)]
+pub struct IdMap {
+    next_id: u64,
+    /// The client's id of every outstanding request, by the id sent to the target
+    originals: HashMap<u64, Value>,
+}
+
+impl IdMap {
+    pub fn new() -> Self {
+        Self {
+            next_id: CLIENT_ID_BASE,
+            originals: HashMap::new(),
+        }
+    }
+
+    /// Give a client request an id from the client range. Returns true if the
+    /// message changed.
+    pub fn renumber_request(&mut self, parsed: &mut Value) -> bool {
+        let Some(id) = parsed.get_mut("id") else {
+            return false;
+        };
+        let wire_id = self.next_id;
+        self.next_id += 1;
+        self.originals
+            .insert(wire_id, std::mem::replace(id, Value::from(wire_id)));
+        true
+    }
+
+    /// Put the client's id back into a response. Returns false if the message
+    /// answers a request the client never sent and must not reach it.
+    pub fn restore_response(&mut self, parsed: &mut Value) -> bool {
+        let Some(id) = parsed.get_mut("id") else {
+            return true; // an event
+        };
+        match id
+            .as_u64()
+            .and_then(|wire_id| self.originals.remove(&wire_id))
+        {
+            Some(original) => {
+                *id = original;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forget every outstanding request, for a target that will not answer
+    /// them any more. Returns the client's ids, oldest request first.
+    pub fn take_pending(&mut self) -> Vec<Value> {
+        let mut pending: Vec<(u64, Value)> = self.originals.drain().collect();
+        pending.sort_by_key(|(wire_id, _)| *wire_id);
+        pending.into_iter().map(|(_, id)| id).collect()
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn client_ids_round_trip_through_the_client_range() {
+        let mut ids = IdMap::new();
+        let mut first = json!({"id": DUMP_ID_BASE, "method": "Runtime.enable"});
+        let mut second = json!({"id": "abc", "method": "Debugger.enable"});
+        assert!(ids.renumber_request(&mut first));
+        assert!(ids.renumber_request(&mut second));
+        let first_wire = first["id"].as_u64().unwrap();
+        let second_wire = second["id"].as_u64().unwrap();
+        assert!(first_wire >= CLIENT_ID_BASE && first_wire <= i32::MAX as u64);
+        assert_ne!(first_wire, second_wire);
+
+        let mut response = json!({"id": second_wire, "result": {}});
+        assert!(ids.restore_response(&mut response));
+        assert_eq!(response["id"], "abc");
+        let mut response = json!({"id": first_wire, "result": {}});
+        assert!(ids.restore_response(&mut response));
+        assert_eq!(response["id"], DUMP_ID_BASE);
+
+        // Answered once only
+        let mut again = json!({"id": first_wire, "result": {}});
+        assert!(!ids.restore_response(&mut again));
+    }
+
+    #[test]
+    fn events_pass_and_foreign_responses_are_held_back() {
+        let mut ids = IdMap::new();
+        let mut event = json!({"method": "Debugger.resumed", "params": {}});
+        assert!(!ids.renumber_request(&mut event));
+        assert!(ids.restore_response(&mut event));
+
+        let mut internal = json!({"id": DUMP_ID_BASE, "result": {}});
+        assert!(!ids.restore_response(&mut internal));
+    }
+
+    #[test]
+    fn pending_requests_are_handed_back_once() {
+        let mut ids = IdMap::new();
+        let mut first = json!({"id": 1, "method": "Runtime.evaluate"});
+        let mut second = json!({"id": 2, "method": "Runtime.evaluate"});
+        ids.renumber_request(&mut first);
+        ids.renumber_request(&mut second);
+
+        assert_eq!(ids.take_pending(), vec![json!(1), json!(2)]);
+        assert!(ids.take_pending().is_empty());
+        let mut late = json!({"id": first["id"], "result": {}});
+        assert!(!ids.restore_response(&mut late));
+    }
+}
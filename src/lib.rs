@@ -0,0 +1,29 @@
+//! Proxies Chrome DevTools Protocol connections to the WinCC Unified runtime,
+//! following its debug targets as screens are downloaded again.
+//!
+//! The command line tool is a thin wrapper around [`Proxy`]: build a
+//! [`Configuration`], subscribe to the [`ProxyEvent`]s, start the proxy and
+//! call [`ProxyHandle::shutdown`] when done.
+
+mod breakpoints;
+pub mod commands;
+pub mod config;
+pub mod diff;
+pub mod dump;
+pub mod events;
+mod git;
+mod ids;
+pub mod logging;
+#[cfg(test)]
+mod mock;
+mod pathmap;
+mod pretty;
+pub mod proxy;
+mod session;
+mod split;
+pub mod styleguide;
+mod urlmap;
+
+pub use config::Configuration;
+pub use events::ProxyEvent;
+pub use proxy::{Proxy, ProxyHandle};
@@ -1,65 +1,1090 @@
-use chrono::Local;
-use colored::Colorize;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDate};
+use colored::{ColoredString, Colorize};
+use serde_json::{Map, Value, json};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, mpsc};
+use tokio::sync::broadcast;
 
-use crate::config::config;
+use crate::config::{LogFormat, LogLevel};
+use crate::events::{EventOrigin, ProxyEvent, decode};
 
-pub fn timestamp() -> String {
-    Local::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+// ============================================================================
+// Log Records
+// ============================================================================
+
+/// Severity of a log record. `Verbose` and `VeryVerbose` records are always
+/// emitted; whoever shows them decides whether they are wanted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Success,
+    Warn,
+    Error,
+    Verbose,
+    VeryVerbose,
+}
+
+impl Level {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Info => "info",
+            Level::Success => "success",
+            Level::Warn => "warn",
+            Level::Error => "error",
+            Level::Verbose => "verbose",
+            Level::VeryVerbose => "very_verbose",
+        }
+    }
 }
 
-pub fn log(message: &str) {
-    println!(
-        "{} {}",
-        format!("[{}]", timestamp()).dimmed(),
-        message
-    );
+/// Highlighted category of an info line, e.g. `[CONN]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tag {
+    Start,
+    Ready,
+    Conn,
+    Change,
+    Disc,
+    Stop,
 }
 
-pub fn log_success(message: &str) {
-    println!(
-        "{} {} {}",
-        format!("[{}]", timestamp()).dimmed(),
-        "[OK]".green().bold(),
-        message
-    );
+impl Tag {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tag::Start => "start",
+            Tag::Ready => "ready",
+            Tag::Conn => "conn",
+            Tag::Change => "change",
+            Tag::Disc => "disc",
+            Tag::Stop => "stop",
+        }
+    }
 }
 
-pub fn log_warn(message: &str) {
-    println!(
-        "{} {} {}",
-        format!("[{}]", timestamp()).dimmed(),
-        "[WARN]".yellow().bold(),
-        message
-    );
+/// What a log line is about. Text output has it in the message already;
+/// JSON output adds it as fields. Set through `Logger::scoped`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LogFields {
+    pub origin: Option<EventOrigin>,
+    pub client_id: Option<u32>,
+    /// Websocket path of the target, URL-encoded
+    pub target: Option<String>,
 }
 
-pub fn log_error(message: &str) {
-    eprintln!(
-        "{} {} {}",
-        format!("[{}]", timestamp()).dimmed(),
-        "[ERROR]".red().bold(),
-        message
-    );
+/// One line of proxy output.
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub time: DateTime<Local>,
+    pub level: Level,
+    pub tag: Option<Tag>,
+    pub message: String,
+    pub fields: LogFields,
 }
 
-pub fn log_verbose(message: &str) {
-    if config().verbose {
-        println!(
-            "{} {} {}",
-            format!("[{}]", timestamp()).dimmed(),
-            "[VERBOSE]".dimmed(),
-            message.dimmed()
-        );
+impl LogRecord {
+    pub fn now(level: Level, tag: Option<Tag>, message: String) -> Self {
+        Self {
+            time: Local::now(),
+            level,
+            tag,
+            message,
+            fields: LogFields::default(),
+        }
+    }
+}
+
+/// Which verbose records are shown.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Verbosity {
+    pub verbose: bool,
+    pub very_verbose: bool,
+}
+
+impl Verbosity {
+    pub fn shows(&self, level: Level) -> bool {
+        match level {
+            Level::Verbose => self.verbose,
+            Level::VeryVerbose => self.very_verbose,
+            _ => true,
+        }
+    }
+}
+
+/// RFC 3339 with milliseconds and the local UTC offset.
+fn format_time(time: &DateTime<Local>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%S%.3f%:z").to_string()
+}
+
+impl From<LogLevel> for Verbosity {
+    fn from(level: LogLevel) -> Self {
+        Self {
+            verbose: level != LogLevel::Info,
+            very_verbose: level == LogLevel::VeryVerbose,
+        }
+    }
+}
+
+fn badge(record: &LogRecord) -> Option<ColoredString> {
+    match (record.level, record.tag) {
+        (_, Some(Tag::Start)) => Some("[START]".cyan().bold()),
+        (_, Some(Tag::Ready)) => Some("[READY]".green().bold()),
+        (_, Some(Tag::Conn)) => Some("[CONN]".cyan().bold()),
+        (_, Some(Tag::Change)) => Some("[CHANGE]".blue().bold()),
+        (_, Some(Tag::Disc)) => Some("[DISC]".magenta().bold()),
+        (_, Some(Tag::Stop)) => Some("[STOP]".magenta().bold()),
+        (Level::Success, None) => Some("[OK]".green().bold()),
+        (Level::Warn, None) => Some("[WARN]".yellow().bold()),
+        (Level::Error, None) => Some("[ERROR]".red().bold()),
+        (Level::Verbose | Level::VeryVerbose, None) => Some("[VERBOSE]".dimmed()),
+        (Level::Info, None) => None,
+    }
+}
+
+/// A record as a colored console line.
+pub fn render(record: &LogRecord) -> String {
+    let time = format!("[{}]", format_time(&record.time)).dimmed();
+    let badge = badge(record);
+    let message = match record.level {
+        Level::Verbose | Level::VeryVerbose => record.message.dimmed().to_string(),
+        _ => record.message.clone(),
+    };
+    match badge {
+        Some(badge) => format!("{} {} {}", time, badge, message),
+        None => format!("{} {}", time, message),
+    }
+}
+
+/// `text` without its ANSI color codes.
+fn strip_colors(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip up to the code's final letter, e.g. the `m` of `\x1b[1;36m`
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+/// A record as an uncolored line, for log files.
+pub fn render_plain(record: &LogRecord) -> String {
+    match badge(record) {
+        Some(badge) => format!(
+            "[{}] {} {}",
+            format_time(&record.time),
+            badge.input,
+            record.message
+        ),
+        None => format!("[{}] {}", format_time(&record.time), record.message),
+    }
+}
+
+/// Print a record to the console if `verbosity` shows it. Errors go to stderr.
+pub fn print_record(record: &LogRecord, verbosity: Verbosity) {
+    if !verbosity.shows(record.level) {
+        return;
+    }
+    if record.level == Level::Error {
+        eprintln!("{}", render(record));
+    } else {
+        println!("{}", render(record));
+    }
+}
+
+fn insert_origin(line: &mut Map<String, Value>, origin: &EventOrigin) {
+    insert_station(line, &origin.station);
+    line.insert("context".into(), json!(origin.context.name()));
+}
+
+fn insert_station(line: &mut Map<String, Value>, station: &Option<String>) {
+    if let Some(name) = station {
+        line.insert("station".into(), json!(name));
+    }
+}
+
+/// The record to render an event's JSON line from, for output showing
+/// `verbosity`. Typed events always have one, whatever their level and even
+/// if text output leaves them out; log lines only if `verbosity` shows them.
+pub fn json_record(event: &ProxyEvent, verbosity: Verbosity) -> Option<LogRecord> {
+    let record = event.headline();
+    let wanted = !matches!(event, ProxyEvent::Log(_)) || verbosity.shows(record.level);
+    wanted.then_some(record)
+}
+
+/// An event as a single JSON line. Time, level, tag and message are taken
+/// from `record`, usually the event's `headline`.
+pub fn render_json(event: &ProxyEvent, record: &LogRecord) -> String {
+    let mut line = Map::new();
+    line.insert("time".into(), json!(format_time(&record.time)));
+    line.insert("level".into(), json!(record.level.name()));
+    line.insert("event".into(), json!(event.kind()));
+    if let Some(tag) = record.tag {
+        line.insert("tag".into(), json!(tag.name()));
+    }
+    match event {
+        ProxyEvent::Ready | ProxyEvent::Stopped => {}
+        ProxyEvent::Log(record) => {
+            let fields = &record.fields;
+            if let Some(ref origin) = fields.origin {
+                insert_origin(&mut line, origin);
+            }
+            if let Some(client_id) = fields.client_id {
+                line.insert("clientId".into(), json!(client_id));
+            }
+            if let Some(ref target) = fields.target {
+                line.insert("target".into(), json!(decode(target)));
+            }
+        }
+        ProxyEvent::TargetServerConnected {
+            station,
+            host,
+            port,
+            after_failures,
+        } => {
+            insert_station(&mut line, station);
+            line.insert("host".into(), json!(host));
+            line.insert("port".into(), json!(port));
+            line.insert("afterFailures".into(), json!(after_failures));
+        }
+        ProxyEvent::TargetServerUnreachable {
+            station,
+            host,
+            port,
+            reason,
+            attempts,
+            retry_secs,
+        } => {
+            insert_station(&mut line, station);
+            line.insert("host".into(), json!(host));
+            line.insert("port".into(), json!(port));
+            line.insert("reason".into(), json!(reason));
+            line.insert("attempts".into(), json!(attempts));
+            line.insert("retrySecs".into(), json!(retry_secs));
+        }
+        ProxyEvent::TargetDiscovered { origin, path } => {
+            insert_origin(&mut line, origin);
+            line.insert("target".into(), json!(decode(path)));
+        }
+        ProxyEvent::TargetChanged {
+            origin,
+            old_path,
+            new_path,
+        } => {
+            insert_origin(&mut line, origin);
+            line.insert("target".into(), json!(decode(new_path)));
+            line.insert("previousTarget".into(), json!(decode(old_path)));
+        }
+        ProxyEvent::ClientConnected { origin, client_id } => {
+            insert_origin(&mut line, origin);
+            line.insert("clientId".into(), json!(client_id));
+        }
+        ProxyEvent::ClientAttached {
+            origin,
+            client_id,
+            path,
+        }
+        | ProxyEvent::ClientRetargeted {
+            origin,
+            client_id,
+            path,
+        } => {
+            insert_origin(&mut line, origin);
+            line.insert("clientId".into(), json!(client_id));
+            line.insert("target".into(), json!(decode(path)));
+        }
+        ProxyEvent::ClientDisconnected {
+            origin,
+            client_id,
+            reason,
+        } => {
+            insert_origin(&mut line, origin);
+            line.insert("clientId".into(), json!(client_id));
+            line.insert("reason".into(), json!(reason.name()));
+        }
+        ProxyEvent::DumpProgress { origin, stats } => {
+            insert_origin(&mut line, origin);
+            line.insert(
+                "stats".into(),
+                json!({ "written": stats.written, "failed": stats.failed, "skipped": stats.skipped, "dropped": stats.dropped }),
+            );
+        }
+        ProxyEvent::DumpFinished {
+            origin,
+            client_id,
+            stats,
+        } => {
+            insert_origin(&mut line, origin);
+            line.insert("clientId".into(), json!(client_id));
+            line.insert(
+                "stats".into(),
+                json!({ "written": stats.written, "failed": stats.failed, "skipped": stats.skipped, "dropped": stats.dropped }),
+            );
+        }
+    }
+    line.insert("message".into(), json!(record.message));
+    Value::Object(line).to_string()
+}
+
+/// How the command line tools print to the console.
+#[derive(Debug, Clone, Default)]
+pub struct Console {
+    pub verbosity: Verbosity,
+    pub format: LogFormat,
+    /// Gets every event as well, at its own verbosity
+    pub file: Option<Arc<LogFile>>,
+}
+
+impl Console {
+    /// Print preformatted lines, like the banner, as they are. The log file
+    /// gets them without colors, as info records.
+    pub fn print_lines(&self, lines: &[String]) {
+        for line in lines {
+            println!("{}", line);
+            if let Some(ref file) = self.file
+                && !line.is_empty()
+            {
+                let record = LogRecord::now(Level::Info, None, strip_colors(line));
+                file.write(&ProxyEvent::Log(record));
+            }
+        }
+    }
+
+    /// Wait until the log file has everything printed so far.
+    pub fn flush(&self) {
+        if let Some(ref file) = self.file {
+            file.flush();
+        }
+    }
+
+    /// Whether a record at `level` would reach the console or the log file.
+    pub fn shows(&self, level: Level) -> bool {
+        self.verbosity.shows(level)
+            || self
+                .file
+                .as_ref()
+                .is_some_and(|file| file.verbosity.shows(level))
+    }
+
+    /// Print an event's records, or the event as one JSON line. In text
+    /// output, events without records, like `Ready`, print nothing.
+    pub fn print(&self, event: &ProxyEvent) {
+        if let Some(ref file) = self.file {
+            file.write(event);
+        }
+        match self.format {
+            LogFormat::Text => {
+                for record in &event.records() {
+                    print_record(record, self.verbosity);
+                }
+            }
+            LogFormat::Json => {
+                // All on stdout, so a collector reads one stream
+                if let Some(record) = json_record(event, self.verbosity) {
+                    println!("{}", render_json(event, &record));
+                }
+            }
+        }
+    }
+}
+
+// ============================================================================
+// Log File
+// ============================================================================
+
+/// When a log file is rotated and how many rotated files are kept.
+#[derive(Debug, Clone, Copy)]
+pub struct Rotation {
+    /// Rotate before a line would make the file larger than this
+    pub max_bytes: u64,
+    /// Rotated files kept as `<path>.1` (newest) to `<path>.<keep>`
+    pub keep: usize,
+}
+
+/// A line for the writer thread, or a request to report back once every
+/// earlier line is written.
+#[derive(Debug)]
+enum FileCommand {
+    Line(DateTime<Local>, String),
+    Flush(mpsc::Sender<()>),
+}
+
+/// A log file for unattended runs: uncolored, with records up to its own
+/// verbosity whatever the console shows. Appends to an existing file, and
+/// rotates at the first record of a new day and before a line would make it
+/// larger than `Rotation::max_bytes`.
+///
+/// Lines are written and rotated on a thread of their own, so a slow disk
+/// does not hold up whoever logs. `flush` waits for them.
+#[derive(Debug)]
+pub struct LogFile {
+    verbosity: Verbosity,
+    format: LogFormat,
+    lines: mpsc::Sender<FileCommand>,
+}
+
+impl LogFile {
+    pub fn open(
+        path: impl Into<PathBuf>,
+        rotation: Rotation,
+        verbosity: Verbosity,
+        format: LogFormat,
+    ) -> Result<Self> {
+        let path = path.into();
+        let file = FileWriter::append(&path)
+            .with_context(|| format!("Cannot open log file {}", path.display()))?;
+        let metadata = file.metadata()?;
+        let date = metadata
+            .modified()
+            .map_or_else(|_| Local::now(), DateTime::<Local>::from)
+            .date_naive();
+        let writer = FileWriter {
+            path,
+            rotation,
+            file: Some(file),
+            size: metadata.len(),
+            date,
+            failed: false,
+        };
+        let (lines, commands) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("log-file".to_string())
+            .spawn(move || writer.run(commands))
+            .context("Cannot start the log file writer")?;
+        Ok(Self {
+            verbosity,
+            format,
+            lines,
+        })
+    }
+
+    /// Write an event the way the console shows it, without colors.
+    pub fn write(&self, event: &ProxyEvent) {
+        match self.format {
+            LogFormat::Text => {
+                let records = event.records();
+                for record in records.iter().filter(|r| self.verbosity.shows(r.level)) {
+                    self.write_line(record.time, render_plain(record));
+                }
+            }
+            LogFormat::Json => {
+                if let Some(record) = json_record(event, self.verbosity) {
+                    self.write_line(record.time, render_json(event, &record));
+                }
+            }
+        }
+    }
+
+    fn write_line(&self, time: DateTime<Local>, line: String) {
+        // Only fails once the writer is gone, and then there is nobody to tell
+        let _ = self.lines.send(FileCommand::Line(time, line));
+    }
+
+    /// Wait until every line written so far is in the file.
+    pub fn flush(&self) {
+        let (done, flushed) = mpsc::channel();
+        if self.lines.send(FileCommand::Flush(done)).is_ok() {
+            let _ = flushed.recv();
+        }
+    }
+}
+
+/// The writer thread's side of a `LogFile`.
+#[derive(Debug)]
+struct FileWriter {
+    path: PathBuf,
+    rotation: Rotation,
+    /// `None` only while rotating, or after reopening failed
+    file: Option<File>,
+    size: u64,
+    /// Local day of the lines in the file
+    date: NaiveDate,
+    /// A write failed and was reported; later failures are not
+    failed: bool,
+}
+
+impl FileWriter {
+    fn append(path: &PathBuf) -> std::io::Result<File> {
+        if let Some(dir) = path.parent()
+            && !dir.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(dir)?;
+        }
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    /// Write lines until every `LogFile` sender is gone.
+    fn run(mut self, commands: mpsc::Receiver<FileCommand>) {
+        for command in commands {
+            match command {
+                FileCommand::Line(time, line) => self.write_line(&time, &line),
+                FileCommand::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    }
+
+    fn write_line(&mut self, time: &DateTime<Local>, line: &str) {
+        let line = format!("{}\n", line);
+        let result = self
+            .rotate_if_due(time.date_naive(), line.len() as u64)
+            .and_then(|_| {
+                let file = self
+                    .file
+                    .as_mut()
+                    .ok_or_else(|| std::io::Error::other("not open"))?;
+                file.write_all(line.as_bytes())
+            });
+        match result {
+            Ok(()) => {
+                self.size += line.len() as u64;
+                self.failed = false;
+            }
+            Err(e) if !self.failed => {
+                self.failed = true;
+                eprintln!(
+                    "Error: cannot write log file {}: {}",
+                    self.path.display(),
+                    e
+                );
+            }
+            Err(_) => {}
+        }
+    }
+
+    fn rotate_if_due(&mut self, date: NaiveDate, len: u64) -> std::io::Result<()> {
+        // Records of different tasks can arrive slightly out of order around midnight
+        let new_day = date > self.date;
+        let full = self.size > 0 && self.size + len > self.rotation.max_bytes;
+        if self.file.is_some() && !new_day && !full {
+            return Ok(());
+        }
+        // Close before renaming; Windows cannot rename open files
+        self.file = None;
+        if new_day || full {
+            self.shift_rotated()?;
+            self.size = 0;
+        }
+        self.date = self.date.max(date);
+        self.file = Some(Self::append(&self.path)?);
+        Ok(())
+    }
+
+    /// Move `<path>` to `<path>.1`, `<path>.1` to `<path>.2` and so on,
+    /// dropping whatever falls beyond `Rotation::keep`.
+    fn shift_rotated(&self) -> std::io::Result<()> {
+        let keep = self.rotation.keep;
+        if keep == 0 {
+            return remove_if_exists(&self.path);
+        }
+        remove_if_exists(&self.rotated(keep))?;
+        for n in (1..keep).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                std::fs::rename(&from, self.rotated(n + 1))?;
+            }
+        }
+        if self.path.exists() {
+            std::fs::rename(&self.path, self.rotated(1))?;
+        }
+        Ok(())
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        path.into()
+    }
+}
+
+fn remove_if_exists(path: &PathBuf) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// ============================================================================
+// Logger
+// ============================================================================
+
+#[derive(Debug, Clone)]
+enum Sink {
+    /// Print straight to the console
+    Console(Console),
+    /// Broadcast, log lines as `ProxyEvent::Log` if `verbosity` shows them.
+    /// `file` gets everything first, at its own verbosity.
+    Events {
+        events: broadcast::Sender<ProxyEvent>,
+        verbosity: Verbosity,
+        file: Option<Arc<LogFile>>,
+    },
+    /// Drop everything
+    Silent,
+}
+
+/// Where the proxy's and the dumper's output goes, log lines and typed
+/// events alike. Cheap to clone; every clone writes to the same place.
+#[derive(Debug, Clone)]
+pub struct Logger {
+    sink: Sink,
+    fields: LogFields,
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self::with_sink(Sink::Silent)
+    }
+}
+
+impl Logger {
+    fn with_sink(sink: Sink) -> Self {
+        Self {
+            sink,
+            fields: LogFields::default(),
+        }
+    }
+
+    /// A logger that prints to the console, for command line tools.
+    pub fn console(console: Console) -> Self {
+        Self::with_sink(Sink::Console(console))
+    }
+
+    /// A logger that sends every typed event, and the records `verbosity`
+    /// shows, to `events`. `verbosity` is the most any subscriber shows;
+    /// records below it and below `file`'s verbosity are not even built.
+    ///
+    /// `file` is written here rather than by a subscriber, so it misses
+    /// nothing when a subscriber falls behind and events are dropped.
+    pub fn events(
+        events: broadcast::Sender<ProxyEvent>,
+        verbosity: Verbosity,
+        file: Option<Arc<LogFile>>,
+    ) -> Self {
+        Self::with_sink(Sink::Events {
+            events,
+            verbosity,
+            file,
+        })
+    }
+
+    /// A logger writing to the same place that attaches `fields` to its
+    /// records. Typed events are passed on as they are.
+    pub fn scoped(&self, fields: LogFields) -> Self {
+        Self {
+            sink: self.sink.clone(),
+            fields,
+        }
+    }
+
+    /// Report an event. The console shows it as `ProxyEvent::records`, or
+    /// as a JSON line.
+    pub fn event(&self, event: ProxyEvent) {
+        match &self.sink {
+            Sink::Console(console) => console.print(&event),
+            Sink::Events {
+                events,
+                verbosity,
+                file,
+            } => {
+                if let Some(file) = file {
+                    file.write(&event);
+                }
+                let wanted = match event {
+                    ProxyEvent::Log(ref record) => verbosity.shows(record.level),
+                    _ => true,
+                };
+                if wanted {
+                    let _ = events.send(event);
+                }
+            }
+            Sink::Silent => {}
+        }
+    }
+
+    fn emit(&self, level: Level, tag: Option<Tag>, message: &str) {
+        let wanted = match &self.sink {
+            Sink::Console(console) => console.shows(level),
+            Sink::Events {
+                verbosity, file, ..
+            } => {
+                verbosity.shows(level)
+                    || file
+                        .as_ref()
+                        .is_some_and(|file| file.verbosity.shows(level))
+            }
+            Sink::Silent => false,
+        };
+        if wanted {
+            let mut record = LogRecord::now(level, tag, message.to_string());
+            record.fields = self.fields.clone();
+            self.event(ProxyEvent::Log(record));
+        }
+    }
+
+    pub fn log(&self, message: &str) {
+        self.emit(Level::Info, None, message);
+    }
+
+    pub fn tagged(&self, tag: Tag, message: &str) {
+        self.emit(Level::Info, Some(tag), message);
+    }
+
+    pub fn success(&self, message: &str) {
+        self.emit(Level::Success, None, message);
+    }
+
+    pub fn warn(&self, message: &str) {
+        self.emit(Level::Warn, None, message);
+    }
+
+    pub fn error(&self, message: &str) {
+        self.emit(Level::Error, None, message);
+    }
+
+    pub fn verbose(&self, message: &str) {
+        self.emit(Level::Verbose, None, message);
+    }
+
+    pub fn very_verbose(&self, message: &str) {
+        self.emit(Level::VeryVerbose, None, message);
     }
 }
 
-pub fn log_very_verbose(message: &str) {
-    if config().very_verbose {
-        println!(
-            "{} {} {}",
-            format!("[{}]", timestamp()).dimmed(),
-            "[VERBOSE]".dimmed(),
-            message.dimmed()
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ContextKind;
+    use crate::events::DisconnectReason;
+
+    fn parse(line: &str) -> Value {
+        serde_json::from_str(line).unwrap()
+    }
+
+    #[test]
+    fn times_are_rfc3339_with_the_local_offset() {
+        let record = LogRecord::now(Level::Info, None, "hello".to_string());
+        let time = format_time(&record.time);
+        let parsed = DateTime::parse_from_rfc3339(&time).unwrap();
+        assert_eq!(parsed.timestamp_millis(), record.time.timestamp_millis());
+        assert_eq!(parsed.offset(), record.time.offset());
+    }
+
+    #[test]
+    fn client_events_carry_context_client_and_target() {
+        let event = ProxyEvent::ClientAttached {
+            origin: EventOrigin {
+                station: Some("line3".to_string()),
+                context: ContextKind::Events,
+            },
+            client_id: 7,
+            path: "VCS_8%20Events".to_string(),
+        };
+        let record = &event.records()[0];
+        let line = render_json(&event, record);
+        assert!(!line.contains('\n'));
+
+        let line = parse(&line);
+        assert_eq!(line["event"], "client_attached");
+        assert_eq!(line["level"], "info");
+        assert_eq!(line["tag"], "conn");
+        assert_eq!(line["station"], "line3");
+        assert_eq!(line["context"], "Events");
+        assert_eq!(line["clientId"], 7);
+        assert_eq!(line["target"], "VCS_8 Events");
+        assert_eq!(
+            line["message"],
+            "[line3] [Events] Client #7: Connected to target"
+        );
+        assert!(DateTime::parse_from_rfc3339(line["time"].as_str().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn unnamed_stations_and_log_lines_leave_fields_out() {
+        let event = ProxyEvent::ClientDisconnected {
+            origin: EventOrigin {
+                station: None,
+                context: ContextKind::Dynamics,
+            },
+            client_id: 2,
+            reason: DisconnectReason::TargetUnreachable("refused".to_string()),
+        };
+        let line = parse(&render_json(&event, &event.records()[0]));
+        assert_eq!(line["level"], "error");
+        assert_eq!(line["reason"], "target_unreachable");
+        assert!(line.get("station").is_none());
+        assert!(line.get("tag").is_none());
+
+        let event = ProxyEvent::Log(LogRecord::now(Level::Warn, None, "careful".to_string()));
+        let line = parse(&render_json(&event, &event.records()[0]));
+        assert_eq!(line["event"], "log");
+        assert_eq!(line["level"], "warn");
+        assert_eq!(line["message"], "careful");
+        assert!(line.get("context").is_none());
+    }
+
+    #[test]
+    fn json_output_has_every_typed_event_whatever_text_shows() {
+        let quiet = Verbosity::default();
+        let unreachable = ProxyEvent::TargetServerUnreachable {
+            station: None,
+            host: "plc".to_string(),
+            port: 9222,
+            reason: "refused".to_string(),
+            attempts: 3,
+            retry_secs: 1,
+        };
+        assert!(unreachable.records().is_empty());
+        let record = json_record(&unreachable, quiet).unwrap();
+        let line = parse(&render_json(&unreachable, &record));
+        assert_eq!(line["attempts"], 3);
+        assert_eq!(
+            line["message"],
+            "Still cannot connect to WinCC (3 failed attempts, retrying every 1s)"
+        );
+
+        let progress = ProxyEvent::DumpProgress {
+            origin: EventOrigin {
+                station: None,
+                context: ContextKind::Events,
+            },
+            stats: Default::default(),
+        };
+        assert_eq!(json_record(&progress, quiet).unwrap().level, Level::Verbose);
+        assert_eq!(
+            json_record(&ProxyEvent::Ready, quiet).unwrap().tag,
+            Some(Tag::Ready)
+        );
+
+        let verbose = ProxyEvent::Log(LogRecord::now(Level::Verbose, None, "detail".to_string()));
+        assert!(json_record(&verbose, quiet).is_none());
+    }
+
+    #[test]
+    fn scoped_log_lines_carry_context_client_and_target() {
+        let (tx, mut rx) = broadcast::channel(4);
+        let origin = EventOrigin {
+            station: Some("line3".to_string()),
+            context: ContextKind::Dynamics,
+        };
+        let verbosity = LogLevel::Verbose.into();
+        let log = Logger::events(tx, verbosity, None).scoped(LogFields {
+            origin: Some(origin),
+            client_id: Some(7),
+            target: Some("VCS_8%20Dynamics".to_string()),
+        });
+        log.very_verbose("Sent Runtime.enable");
+        log.verbose("Replaying 2 commands");
+
+        // The very verbose record was never sent
+        let event = rx.try_recv().unwrap();
+        let line = parse(&render_json(&event, &event.headline()));
+        assert_eq!(line["event"], "log");
+        assert_eq!(line["station"], "line3");
+        assert_eq!(line["context"], "Dynamics");
+        assert_eq!(line["clientId"], 7);
+        assert_eq!(line["target"], "VCS_8 Dynamics");
+    }
+
+    // ========================================================================
+    // Log File
+    // ========================================================================
+
+    fn log_event(time: DateTime<Local>, level: Level, message: &str) -> ProxyEvent {
+        ProxyEvent::Log(LogRecord {
+            time,
+            level,
+            tag: None,
+            message: message.to_string(),
+            fields: LogFields::default(),
+        })
+    }
+
+    fn read(path: PathBuf) -> String {
+        std::fs::read_to_string(path).unwrap_or_default()
+    }
+
+    #[test]
+    fn log_files_are_uncolored_and_keep_their_own_verbosity() {
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        let rotation = Rotation {
+            max_bytes: 1 << 20,
+            keep: 2,
+        };
+        let file = LogFile::open(
+            dir.join("proxy.log"),
+            rotation,
+            LogLevel::Verbose.into(),
+            LogFormat::Text,
+        )
+        .unwrap();
+        let console = Console {
+            file: Some(Arc::new(file)),
+            ..Default::default()
+        };
+
+        let now = Local::now();
+        console.print(&log_event(now, Level::Error, "target gone"));
+        console.print(&log_event(now, Level::Verbose, "dump progress"));
+        console.print(&log_event(now, Level::VeryVerbose, "every message"));
+        console.flush();
+
+        let text = read(dir.join("proxy.log"));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("] [ERROR] target gone"), "{}", lines[0]);
+        assert!(
+            lines[1].ends_with("] [VERBOSE] dump progress"),
+            "{}",
+            lines[1]
         );
+        assert!(!text.contains('\x1b'));
+    }
+
+    #[test]
+    fn printed_lines_reach_the_log_file_uncolored() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("proxy.log");
+        let rotation = Rotation {
+            max_bytes: 1 << 20,
+            keep: 1,
+        };
+        let file = LogFile::open(&path, rotation, Verbosity::default(), LogFormat::Text).unwrap();
+        let console = Console {
+            file: Some(Arc::new(file)),
+            ..Default::default()
+        };
+
+        console.print_lines(&[
+            "\x1b[1;36mConfiguration:\x1b[0m".to_string(),
+            String::new(),
+            "   Poll interval: 1s".to_string(),
+        ]);
+        console.flush();
+
+        let text = read(path);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("] Configuration:"), "{}", lines[0]);
+        assert!(lines[1].ends_with("]    Poll interval: 1s"), "{}", lines[1]);
+    }
+
+    #[test]
+    fn log_files_fed_by_the_logger_keep_what_subscribers_drop() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("proxy.log");
+        let rotation = Rotation {
+            max_bytes: 1 << 20,
+            keep: 1,
+        };
+        let file = LogFile::open(&path, rotation, Verbosity::default(), LogFormat::Json).unwrap();
+        let file = Arc::new(file);
+        let (tx, mut rx) = broadcast::channel(16);
+        let log = Logger::events(tx, LogLevel::VeryVerbose.into(), Some(file.clone()));
+
+        // Far more than the channel holds, with nobody reading
+        for client_id in 0..100 {
+            for _ in 0..20 {
+                log.very_verbose("Forwarded a message");
+            }
+            log.event(ProxyEvent::ClientDisconnected {
+                origin: EventOrigin {
+                    station: None,
+                    context: ContextKind::Events,
+                },
+                client_id,
+                reason: DisconnectReason::TargetChanged,
+            });
+        }
+        file.flush();
+
+        assert!(matches!(
+            rx.try_recv(),
+            Err(broadcast::error::TryRecvError::Lagged(_))
+        ));
+        let text = read(path);
+        let lines: Vec<Value> = text.lines().map(parse).collect();
+        // The file's verbosity leaves the very verbose records out
+        assert_eq!(lines.len(), 100);
+        for (client_id, line) in lines.iter().enumerate() {
+            assert_eq!(line["event"], "client_disconnected");
+            assert_eq!(line["clientId"], client_id);
+        }
+    }
+
+    #[test]
+    fn log_files_rotate_by_size_and_keep_the_newest() {
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        let path = dir.join("proxy.log");
+        let rotation = Rotation {
+            max_bytes: 100,
+            keep: 2,
+        };
+        let file = LogFile::open(&path, rotation, Verbosity::default(), LogFormat::Text).unwrap();
+
+        let now = Local::now();
+        for n in 1..=4 {
+            // Each line is over half the limit, so every line starts a new file
+            file.write(&log_event(
+                now,
+                Level::Info,
+                &format!("line {} {}", n, "x".repeat(40)),
+            ));
+        }
+        file.flush();
+
+        assert!(read(path.clone()).contains("line 4"));
+        assert!(read(dir.join("proxy.log.1")).contains("line 3"));
+        assert!(read(dir.join("proxy.log.2")).contains("line 2"));
+        assert!(!dir.join("proxy.log.3").exists());
+    }
+
+    #[test]
+    fn log_files_rotate_on_a_new_day_and_append_otherwise() {
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        let path = dir.join("proxy.log");
+        std::fs::write(&path, "earlier run\n").unwrap();
+
+        let rotation = Rotation {
+            max_bytes: 1 << 20,
+            keep: 1,
+        };
+        let file = LogFile::open(&path, rotation, Verbosity::default(), LogFormat::Json).unwrap();
+        let now = Local::now();
+        file.write(&log_event(now, Level::Info, "today"));
+        file.write(&log_event(
+            now - chrono::Duration::milliseconds(5),
+            Level::Info,
+            "a bit late",
+        ));
+        file.flush();
+        assert_eq!(read(path.clone()).lines().count(), 3);
+        assert!(!dir.join("proxy.log.1").exists());
+
+        file.write(&log_event(
+            now + chrono::Duration::days(1),
+            Level::Info,
+            "tomorrow",
+        ));
+        file.flush();
+        let rotated = read(dir.join("proxy.log.1"));
+        assert!(rotated.starts_with("earlier run\n"));
+        assert!(rotated.contains("a bit late"));
+        let current = read(path);
+        assert_eq!(current.lines().count(), 1);
+        assert_eq!(parse(current.trim())["message"], "tomorrow");
     }
 }
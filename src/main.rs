@@ -1,11 +1,13 @@
-mod commands;
-mod config;
-mod logging;
-mod proxy;
-mod styleguide;
-
 use clap::Parser;
-use config::{Cli, Commands, Configuration, CONFIG};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use wincc_unified_debug_proxy::config::{
+    CONFIG_FILE_NAME, Cli, Commands, ConfigCommand, ConfigFiles, Configuration, ContextSpec,
+    DEFAULT_DYNAMICS_PORT, DEFAULT_EVENTS_PORT, DEFAULT_TASKS_PORT, LogFormat, ProfileCommand,
+    RunArgs, Station,
+};
+use wincc_unified_debug_proxy::logging::{Console, LogFile, Logger, Rotation, Tag, Verbosity};
+use wincc_unified_debug_proxy::{Proxy, ProxyEvent, commands, diff, dump};
 
 fn has_node() -> bool {
     std::process::Command::new("cmd")
@@ -24,10 +26,10 @@ fn detect_styleguide_version(dump_dir: &str) -> Option<String> {
     for entry in entries.flatten() {
         let name = entry.file_name();
         let name = name.to_string_lossy();
-        if let Some(rest) = name.strip_prefix("ua_rt_device_V") {
-            if let Some(num) = rest.strip_suffix(".d.ts") {
-                return Some(format!("v{}", num));
-            }
+        if let Some(rest) = name.strip_prefix("ua_rt_device_V")
+            && let Some(num) = rest.strip_suffix(".d.ts")
+        {
+            return Some(format!("v{}", num));
         }
         // v17 has no version suffix
         if name == "ua_rt_device.d.ts" {
@@ -81,13 +83,41 @@ fn prompt_styleguide_version() -> Option<String> {
 async fn main() {
     let cli = Cli::parse();
 
+    let files = match ConfigFiles::load(cli.config.as_deref()) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
     match cli.command {
         Some(Commands::Init {
             output,
             dynamics_port,
             events_port,
+            tasks_port,
         }) => {
-            if let Err(e) = commands::init_vscode(&output, dynamics_port, events_port) {
+            let dynamics_port = dynamics_port
+                .or_else(|| files.value(|f| f.dynamics_port))
+                .unwrap_or(DEFAULT_DYNAMICS_PORT);
+            let events_port = events_port
+                .or_else(|| files.value(|f| f.events_port))
+                .unwrap_or(DEFAULT_EVENTS_PORT);
+            let tasks_port = tasks_port
+                .or_else(|| files.value(|f| f.tasks_port))
+                .unwrap_or(DEFAULT_TASKS_PORT);
+            let mut contexts = vec![
+                ContextSpec::dynamics(dynamics_port),
+                ContextSpec::events(events_port),
+                ContextSpec::tasks(tasks_port),
+            ];
+            if let Some(custom) =
+                files.value(|f| (!f.contexts.is_empty()).then(|| f.contexts.clone()))
+            {
+                contexts.extend(custom.iter().map(ContextSpec::from));
+            }
+            if let Err(e) = commands::init_vscode(&output, &contexts) {
                 eprintln!("Error: {}", e);
                 std::process::exit(1);
             }
@@ -98,50 +128,222 @@ async fn main() {
             port,
             output,
         }) => {
+            let Some(address) = address.or_else(|| files.value(|f| f.target_host.clone())) else {
+                eprintln!(
+                    "Error: no address given. Pass --address or set target_host in {}",
+                    CONFIG_FILE_NAME
+                );
+                std::process::exit(1);
+            };
+            let port = port
+                .or_else(|| files.value(|f| f.target_port))
+                .unwrap_or(Station::default().target_port);
             if let Err(e) = commands::generate_netsh_scripts(&address, port, &output) {
                 eprintln!("Error: {}", e);
                 std::process::exit(1);
             }
             return;
         }
-        Some(Commands::Run {
-            target_host,
-            target_port,
-            dynamics_port,
-            events_port,
-            poll_interval,
-            verbose,
-            very_verbose,
-            long_paths,
-            dump,
+        Some(Commands::Dump(args)) => {
+            let cfg = match Configuration::resolve(&args.to_run_args(), &files) {
+                Ok((cfg, _)) => cfg,
+                Err(e) => {
+                    eprintln!("Error: {:#}", e);
+                    std::process::exit(1);
+                }
+            };
+            if let Some(station) = cfg.stations.iter().find(|s| s.dump_output.is_none()) {
+                eprintln!(
+                    "Error: no dump directory{}. Pass an output directory or set 'dump' in {}",
+                    station
+                        .name
+                        .as_ref()
+                        .map(|n| format!(" for profile '{}'", n))
+                        .unwrap_or_default(),
+                    CONFIG_FILE_NAME
+                );
+                std::process::exit(1);
+            }
+            let console = match console(&cfg) {
+                Ok(console) => console,
+                Err(e) => {
+                    eprintln!("Error: {:#}", e);
+                    std::process::exit(1);
+                }
+            };
+            let log = Logger::console(console.clone());
+            if let Err(e) = dump::run_dump(cfg, log.clone(), args.watch, args.timeout).await {
+                log.error(&format!("{:#}", e));
+                console.flush();
+                std::process::exit(1);
+            }
+            console.flush();
+            return;
+        }
+        Some(Commands::Diff { old, new, summary }) => {
+            if let Err(e) = diff::diff_dumps(&old, &new, summary) {
+                eprintln!("Error: {:#}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Commands::Config {
+            command: ConfigCommand::Show(args),
         }) => {
-            let styleguide_version = if let Some(ref dump_dir) = dump {
-                detect_styleguide_version(dump_dir)
-                    .or_else(|| prompt_styleguide_version())
-            } else {
-                None
+            match Configuration::resolve_with_defaults(Configuration::run_defaults(), &args, &files)
+            {
+                Ok((_, entries)) => commands::show_config(&files, &entries),
+                Err(e) => {
+                    eprintln!("Error: {:#}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Commands::Profile { command }) => {
+            let result = match command {
+                ProfileCommand::Add(args) => commands::add_profile(&files, &args),
+                ProfileCommand::List => {
+                    commands::list_profiles(&files);
+                    Ok(())
+                }
+                ProfileCommand::Remove { name } => commands::remove_profile(&files, &name),
             };
+            if let Err(e) = result {
+                eprintln!("Error: {:#}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Commands::Run(args)) => run(Configuration::run_defaults(), args, &files).await,
+        None => run(Configuration::default(), RunArgs::default(), &files).await,
+    }
+}
 
-            let cfg = Configuration::from_run_command(
-                target_host,
-                target_port,
-                dynamics_port,
-                events_port,
-                poll_interval,
-                verbose,
-                very_verbose,
-                long_paths,
-                dump,
-                styleguide_version,
-            );
-            CONFIG.set(cfg).expect("Failed to set configuration");
+fn console(cfg: &Configuration) -> anyhow::Result<Console> {
+    let file = match cfg.log_file {
+        Some(ref path) => {
+            let rotation = Rotation {
+                max_bytes: cfg.log_file_size.saturating_mul(1024 * 1024),
+                keep: cfg.log_file_keep,
+            };
+            Some(Arc::new(LogFile::open(
+                path,
+                rotation,
+                cfg.log_file_level.into(),
+                cfg.log_format,
+            )?))
+        }
+        None => None,
+    };
+    Ok(Console {
+        verbosity: Verbosity {
+            verbose: cfg.verbose,
+            very_verbose: cfg.very_verbose,
+        },
+        format: cfg.log_format,
+        file,
+    })
+}
+
+async fn run(defaults: Configuration, args: RunArgs, files: &ConfigFiles) {
+    let (mut cfg, _) = match Configuration::resolve_with_defaults(defaults, &args, files) {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
         }
-        None => {
-            CONFIG
-                .set(Configuration::default())
-                .expect("Failed to set configuration");
+    };
+
+    if cfg.styleguide_version.is_none() {
+        let dump_dirs = cfg.dump_dirs();
+        if !dump_dirs.is_empty() {
+            let version = dump_dirs
+                .iter()
+                .find_map(|dir| detect_styleguide_version(dir))
+                // Keep JSON output free of the interactive prompt
+                .or_else(|| {
+                    (cfg.log_format == LogFormat::Text)
+                        .then(prompt_styleguide_version)
+                        .flatten()
+                });
+            cfg.styleguide_version = version;
         }
     }
 
-    proxy::run_proxy().await;
+    let console = match console(&cfg) {
+        Ok(console) => console,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    let log = Logger::console(console.clone());
+    let mut proxy = Proxy::new(cfg.clone());
+    if let Some(ref file) = console.file {
+        proxy = proxy.with_log_file(file.clone());
+    }
+    let renderer = tokio::spawn(render_events(proxy.subscribe(), cfg, console.clone()));
+    let handle = match proxy.start().await {
+        Ok(handle) => handle,
+        Err(e) => {
+            let _ = renderer.await;
+            log.error(&format!("{:#}", e));
+            console.flush();
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        log.error(&format!("Cannot wait for Ctrl+C: {}", e));
+    }
+    handle.shutdown().await;
+    let _ = renderer.await;
+    console.flush();
+}
+
+/// Print the proxy's events until it stops. Once it is ready, print the
+/// banner (text output only) and install the styleguide's ESLint setup.
+async fn render_events(
+    mut events: broadcast::Receiver<ProxyEvent>,
+    cfg: Configuration,
+    console: Console,
+) {
+    let log = Logger::console(console.clone());
+    // The proxy writes its events to the log file itself
+    let screen = Console {
+        file: None,
+        ..console.clone()
+    };
+    loop {
+        match events.recv().await {
+            Ok(event @ ProxyEvent::Ready) => {
+                // Shows as a `ready` line in JSON output
+                screen.print(&event);
+                let text = cfg.log_format == LogFormat::Text;
+                if text {
+                    log.tagged(Tag::Ready, "WinCC Debug Proxy is running!");
+                }
+                let styleguide_dirs = commands::write_styleguides(&cfg, &log);
+                if text {
+                    console.print_lines(&commands::banner(&cfg, &styleguide_dirs));
+                }
+                for dump_dir in styleguide_dirs {
+                    tokio::spawn(commands::npm_install(dump_dir, log.clone()));
+                }
+            }
+            Ok(event @ ProxyEvent::Stopped) => {
+                screen.print(&event);
+                break;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+            Ok(event) => screen.print(&event),
+            Err(broadcast::error::RecvError::Lagged(dropped)) => {
+                log.warn(&format!(
+                    "{} events not shown, the console fell behind",
+                    dropped
+                ));
+            }
+        }
+    }
 }
@@ -0,0 +1,340 @@
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{Value, json};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tokio::sync::{oneshot, watch};
+use tokio::time::Duration;
+use warp::Filter;
+
+use crate::config::{ContextSpec, Station};
+
+// ============================================================================
+// Mock WinCC Debug Server
+// ============================================================================
+
+/// A method mock targets never answer, like a request the runtime is still
+/// working on when the target goes away.
+pub const MOCK_NO_REPLY: &str = "Mock.noReply";
+
+/// A script a mock target reports after `Debugger.enable`.
+#[derive(Debug, Clone)]
+pub struct MockScript {
+    pub id: String,
+    pub url: String,
+    pub source: String,
+}
+
+/// A debug target of the mock server, as listed on `/json`.
+#[derive(Debug, Clone)]
+pub struct MockTarget {
+    /// Websocket path, the last segment of `webSocketDebuggerUrl`
+    pub path: String,
+    /// Title as WinCC reports it, e.g. `" @localhost VCS_8 Dynamics"`
+    pub title: String,
+    pub scripts: Vec<MockScript>,
+}
+
+impl MockTarget {
+    pub fn new(path: &str, title: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            title: title.to_string(),
+            scripts: Vec::new(),
+        }
+    }
+
+    pub fn with_script(mut self, id: &str, url: &str, source: &str) -> Self {
+        self.scripts.push(MockScript {
+            id: id.to_string(),
+            url: url.to_string(),
+            source: source.to_string(),
+        });
+        self
+    }
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    /// `None` while the server pretends to be down: `/json` fails
+    targets: Option<Vec<MockTarget>>,
+    /// Websocket path of every connection, in order
+    connections: Vec<String>,
+    /// Every CDP message received, with the path it was sent to
+    received: Vec<(String, Value)>,
+}
+
+/// An in-process stand-in for the WinCC Unified debug server: `/json`,
+/// `/json/version` and a CDP websocket per target that answers
+/// `Debugger.enable` with its scripts and serves their sources.
+///
+/// Replacing the targets closes the connections of targets that are gone,
+/// the way the runtime does when a screen is downloaded again.
+pub struct MockWinCC {
+    addr: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+    changed: watch::Sender<()>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl MockWinCC {
+    pub async fn start(targets: Vec<MockTarget>) -> Self {
+        let state = Arc::new(Mutex::new(MockState {
+            targets: Some(targets),
+            ..Default::default()
+        }));
+        let (changed, _) = watch::channel(());
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+
+        // Keep the listener bound, so no other test can take the port
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("no free local port");
+        let addr = listener.local_addr().expect("listener has no address");
+        let incoming = futures_util::stream::unfold(listener, |listener| async move {
+            let stream = listener.accept().await.map(|(stream, _)| stream);
+            Some((stream, listener))
+        });
+        let server = warp::serve(routes(state.clone(), changed.clone(), addr.port()))
+            .serve_incoming_with_graceful_shutdown(incoming, async move {
+                shutdown_rx.await.ok();
+            });
+        tokio::spawn(server);
+
+        Self {
+            addr,
+            state,
+            changed,
+            shutdown: Some(shutdown_tx),
+        }
+    }
+
+    /// The address the mock server listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Replace the targets, closing connections to targets that are gone.
+    pub fn set_targets(&self, targets: Vec<MockTarget>) {
+        self.state.lock().unwrap().targets = Some(targets);
+        self.changed.send_replace(());
+    }
+
+    /// Make `/json` fail and close every connection, as if the runtime stopped.
+    pub fn go_offline(&self) {
+        self.state.lock().unwrap().targets = None;
+        self.changed.send_replace(());
+    }
+
+    /// Websocket paths of all connections so far.
+    pub fn connections(&self) -> Vec<String> {
+        self.state.lock().unwrap().connections.clone()
+    }
+
+    /// Every message with `method` that was sent to the target at `path`.
+    pub fn received(&self, path: &str, method: &str) -> Vec<Value> {
+        self.state
+            .lock()
+            .unwrap()
+            .received
+            .iter()
+            .filter(|(p, msg)| p == path && msg["method"] == method)
+            .map(|(_, msg)| msg.clone())
+            .collect()
+    }
+
+    /// A station pointed at the mock, with the standard contexts on free ports.
+    pub fn station(&self, dump_output: Option<String>) -> Station {
+        Station {
+            name: None,
+            target_host: "127.0.0.1".to_string(),
+            target_port: self.local_addr().port(),
+            contexts: vec![
+                ContextSpec::dynamics(free_port()),
+                ContextSpec::events(free_port()),
+                ContextSpec::tasks(free_port()),
+            ],
+            dump_output,
+        }
+    }
+}
+
+impl Drop for MockWinCC {
+    fn drop(&mut self) {
+        self.go_offline();
+        if let Some(tx) = self.shutdown.take() {
+            let _ = tx.send(());
+        }
+    }
+}
+
+/// A local port nothing listens on right now, for a proxy under test to bind.
+/// Servers the tests own bind port 0 and keep their listener instead.
+pub fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .expect("no free local port")
+}
+
+/// Wait until `condition` holds, checking every 20 ms. Panics after 10 seconds.
+pub async fn wait_for(what: &str, mut condition: impl FnMut() -> bool) {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    while !condition() {
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "timed out waiting for {}",
+            what
+        );
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+fn target_json(target: &MockTarget, port: u16) -> Value {
+    json!({
+        "description": "",
+        "devtoolsFrontendUrl": "",
+        "id": target.path,
+        "title": target.title,
+        "type": "node",
+        "url": "file://",
+        "webSocketDebuggerUrl": format!("ws://127.0.0.1:{}/{}", port, target.path),
+    })
+}
+
+fn routes(
+    state: Arc<Mutex<MockState>>,
+    changed: watch::Sender<()>,
+    port: u16,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let list_state = state.clone();
+    let json_route = warp::path("json")
+        .and(warp::path::end().or(warp::path!("list")).unify())
+        .and_then(move || {
+            let targets = list_state.lock().unwrap().targets.clone();
+            async move {
+                match targets {
+                    Some(targets) => {
+                        let list: Vec<Value> =
+                            targets.iter().map(|t| target_json(t, port)).collect();
+                        Ok(warp::reply::json(&list))
+                    }
+                    None => Err(warp::reject::not_found()),
+                }
+            }
+        });
+
+    let version_route = warp::path!("json" / "version").map(|| {
+        warp::reply::json(&json!({ "Browser": "node.js/v18.20.4", "Protocol-Version": "1.1" }))
+    });
+
+    let ws_route = warp::path::param::<String>()
+        .and(warp::path::end())
+        .and(warp::ws())
+        .and_then(move |path: String, ws: warp::ws::Ws| {
+            let state = state.clone();
+            let changed = changed.subscribe();
+            async move {
+                // Like the runtime, refuse connections to targets that are gone
+                if current_target(&state, &path).is_none() {
+                    return Err(warp::reject::not_found());
+                }
+                Ok(ws.on_upgrade(move |socket| serve_target(socket, path, state, changed)))
+            }
+        });
+
+    json_route.or(version_route).or(ws_route)
+}
+
+fn current_target(state: &Mutex<MockState>, path: &str) -> Option<MockTarget> {
+    state
+        .lock()
+        .unwrap()
+        .targets
+        .as_ref()?
+        .iter()
+        .find(|t| t.path == path)
+        .cloned()
+}
+
+/// Answer CDP requests on one target connection until the client leaves or
+/// the target disappears.
+async fn serve_target(
+    socket: warp::ws::WebSocket,
+    path: String,
+    state: Arc<Mutex<MockState>>,
+    mut changed: watch::Receiver<()>,
+) {
+    let (mut tx, mut rx) = socket.split();
+    state.lock().unwrap().connections.push(path.clone());
+
+    loop {
+        tokio::select! {
+            msg = rx.next() => {
+                let Some(Ok(msg)) = msg else { break };
+                let Ok(text) = msg.to_str() else { continue };
+                let Ok(request) = serde_json::from_str::<Value>(text) else { continue };
+                state.lock().unwrap().received.push((path.clone(), request.clone()));
+
+                let Some(target) = current_target(&state, &path) else { break };
+                for reply in respond(&target, &request) {
+                    if tx.send(warp::ws::Message::text(reply.to_string())).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            result = changed.changed() => {
+                if result.is_err() || current_target(&state, &path).is_none() {
+                    break;
+                }
+            }
+        }
+    }
+    let _ = tx.close().await;
+}
+
+/// The events and response a target sends for a request.
+fn respond(target: &MockTarget, request: &Value) -> Vec<Value> {
+    let id = request["id"].clone();
+    match request["method"].as_str().unwrap_or_default() {
+        "Debugger.enable" => {
+            let mut replies: Vec<Value> = target
+                .scripts
+                .iter()
+                .map(|script| {
+                    json!({
+                        "method": "Debugger.scriptParsed",
+                        "params": {
+                            "scriptId": script.id,
+                            "url": script.url,
+                            "startLine": 0,
+                            "startColumn": 0,
+                            "endLine": script.source.lines().count().saturating_sub(1),
+                            "endColumn": 0,
+                            "executionContextId": 1,
+                            "hash": "",
+                            "sourceMapURL": ""
+                        }
+                    })
+                })
+                .collect();
+            replies.push(
+                json!({ "id": id, "result": { "debuggerId": format!("mock-{}", target.path) } }),
+            );
+            replies
+        }
+        "Debugger.getScriptSource" => {
+            let script_id = request["params"]["scriptId"].as_str().unwrap_or_default();
+            match target.scripts.iter().find(|s| s.id == script_id) {
+                Some(script) => {
+                    vec![json!({ "id": id, "result": { "scriptSource": script.source } })]
+                }
+                None => vec![
+                    json!({ "id": id, "error": { "code": -32000, "message": "No script for id" } }),
+                ],
+            }
+        }
+        MOCK_NO_REPLY => Vec::new(),
+        _ => vec![json!({ "id": id, "result": {} })],
+    }
+}
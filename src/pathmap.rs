@@ -0,0 +1,381 @@
+use sha2::{Digest, Sha256};
+
+// ============================================================================
+// Script URL to File Path
+// ============================================================================
+
+/// Longest file or directory name written, in bytes. Well below the 255 most
+/// file systems allow, so a disambiguating suffix still fits.
+const MAX_SEGMENT_LEN: usize = 96;
+
+/// Longest path written below a context directory, in bytes. Leaves room for
+/// the dump directory within the 260 characters Windows tools still assume.
+const MAX_PATH_LEN: usize = 180;
+
+/// Characters Windows does not allow in file names.
+const FORBIDDEN: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Device names Windows reserves, with or without an extension.
+const RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Short hash of a name, appended where a name had to be changed beyond
+/// replacing forbidden characters, so different originals stay different.
+fn short_hash(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))[..8].to_string()
+}
+
+/// Insert `~hash` before a name's extension.
+fn with_hash(name: &str, hash: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() && !ext.is_empty() => {
+            format!("{}~{}.{}", stem, hash, ext)
+        }
+        _ => format!("{}~{}", name, hash),
+    }
+}
+
+/// Cut a name to at most `max` bytes on a character boundary, keeping its
+/// extension.
+fn truncate_name(name: &str, max: usize) -> String {
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() && ext.len() < 16 => (stem, format!(".{}", ext)),
+        _ => (name, String::new()),
+    };
+    let mut end = max.saturating_sub(ext.len()).min(stem.len());
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &stem[..end], ext)
+}
+
+/// Undo the one replacement that keeps a name recognizable: the colons after
+/// a runtime prefix, `HMI_RT_1::` or `HMI_RT_1:`, which became underscores.
+fn restore_runtime_colons(name: &str) -> String {
+    let Some(after) = name.strip_prefix("HMI_RT_") else {
+        return name.to_string();
+    };
+    let digits = after.len() - after.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits == 0 {
+        return name.to_string();
+    }
+    let (number, rest) = after.split_at(digits);
+    match rest.strip_prefix("__") {
+        Some(rest) => format!("HMI_RT_{}::{}", number, rest),
+        None => match rest.strip_prefix('_') {
+            Some(rest) => format!("HMI_RT_{}:{}", number, rest),
+            None => name.to_string(),
+        },
+    }
+}
+
+/// Whether a name has the form of a file the dumper writes next to a script:
+/// the `Events.raw.js` copy of a pretty-printed script (see `pretty::raw_path`)
+/// or the `Events.split` directory of a split one (see `split::split_dir`).
+fn is_companion_name(name: &str) -> bool {
+    let lower = name.to_lowercase();
+    let stem = lower
+        .rsplit_once('.')
+        .map_or(lower.as_str(), |(stem, _)| stem);
+    lower.ends_with(".split") || lower.ends_with(".raw") || stem.ends_with(".raw")
+}
+
+fn is_reserved(name: &str) -> bool {
+    let base = name.split('.').next().unwrap_or(name).trim_end();
+    RESERVED.iter().any(|r| r.eq_ignore_ascii_case(base))
+}
+
+/// A single path segment that is safe on Windows, macOS and Linux, or `None`
+/// for segments that only navigate (`.` and empty ones).
+///
+/// Forbidden and control characters become `_`, which is how the colons of
+/// WinCC's `HMI_RT_1::` prefix have always been written. Any name that can't
+/// be told back from the result that way gets a hash of the original
+/// appended: other replaced characters, names that look like a replaced
+/// prefix or like the dumper's companion files, `..`, names with trailing
+/// dots or spaces (Windows drops those), reserved device names and over-long
+/// names. So different names never end
+/// up as the same segment, whatever order they are seen in. Unicode is kept
+/// as is.
+fn safe_segment(segment: &str) -> Option<String> {
+    if segment.is_empty() || segment == "." {
+        return None;
+    }
+    let replaced: String = segment
+        .chars()
+        .map(|c| {
+            if FORBIDDEN.contains(&c) || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+
+    let only_dots = replaced.chars().all(|c| c == '.');
+    let trailing = replaced.ends_with('.') || replaced.ends_with(' ');
+    if is_reserved(&replaced) && !trailing {
+        // Windows ignores everything after the first dot here, so the hash
+        // has to go before it
+        let (base, rest) = replaced.split_once('.').unwrap_or((&replaced, ""));
+        let renamed = format!("{}~{}", base.trim_end(), short_hash(segment));
+        let renamed = if rest.is_empty() {
+            renamed
+        } else {
+            format!("{}.{}", renamed, rest)
+        };
+        return Some(truncate_name(&renamed, MAX_SEGMENT_LEN + 9));
+    }
+    if only_dots || trailing || is_reserved(&replaced) {
+        let trimmed = replaced.trim_end_matches(['.', ' ']);
+        let base = if trimmed.is_empty() { "_" } else { trimmed };
+        return Some(with_hash(
+            &truncate_name(base, MAX_SEGMENT_LEN),
+            &short_hash(segment),
+        ));
+    }
+    if replaced.len() > MAX_SEGMENT_LEN
+        || restore_runtime_colons(&replaced) != segment
+        || is_companion_name(&replaced)
+    {
+        let truncated = truncate_name(&replaced, MAX_SEGMENT_LEN);
+        let hashed = with_hash(&truncated, &short_hash(segment));
+        if is_companion_name(&hashed) {
+            // `Events~hash.split` would still look like one
+            return Some(format!("{}~{}", truncated, short_hash(segment)));
+        }
+        return Some(hashed);
+    }
+    Some(replaced)
+}
+
+/// Where a script is dumped, relative to its context directory.
+///
+/// The URL's path segments become directories, so the dump mirrors the
+/// runtime's structure: `/screen_modules/Screen_Content/HMI_RT_1::HMI_Screen/Events.js`
+/// is written to `screen_modules/Screen_Content/HMI_RT_1__HMI_Screen/Events.js`.
+/// A scheme is dropped and the host kept as the first directory. The result
+/// never leaves the context directory and depends on the URL alone, so the
+/// dumper and `--file-urls` agree on it. URLs that differ only in case still
+/// share a file on case-insensitive file systems; WinCC does not allow names
+/// that differ only in case.
+/// Paths longer than `MAX_PATH_LEN` keep their leading directories and file
+/// name, with the directories in between replaced by a hash.
+pub fn script_path(url: &str) -> String {
+    let without_scheme = match url.split_once("://") {
+        Some((scheme, rest))
+            if !scheme.is_empty()
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)) =>
+        {
+            rest
+        }
+        _ => url,
+    };
+    let mut segments: Vec<String> = without_scheme
+        .split(['/', '\\'])
+        .filter_map(safe_segment)
+        .collect();
+    if segments.is_empty() {
+        segments.push(with_hash("_", &short_hash(url)));
+    }
+
+    let path = segments.join("/");
+    if path.len() <= MAX_PATH_LEN {
+        return path;
+    }
+
+    // Keep as many leading directories as fit next to a hash of the rest
+    let file = segments.pop().unwrap_or_default();
+    let hash = format!("~{}", short_hash(url));
+    let mut kept = Vec::new();
+    let mut len = file.len() + hash.len() + 1;
+    for segment in &segments {
+        if len + segment.len() + 1 > MAX_PATH_LEN {
+            break;
+        }
+        len += segment.len() + 1;
+        kept.push(segment.as_str());
+    }
+    kept.push(&hash);
+    kept.push(&file);
+    kept.join("/")
+}
+
+/// `path` with a hash of `name` added to its file name, for a file whose path
+/// is already taken by something else.
+pub fn disambiguate(path: &str, name: &str) -> String {
+    let hash = short_hash(name);
+    match path.rsplit_once('/') {
+        Some((dir, file)) => format!("{}/{}", dir, with_hash(file, &hash)),
+        None => with_hash(path, &hash),
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wincc_urls_keep_their_structure() {
+        assert_eq!(
+            script_path(
+                "/screen_modules/Screen_Content/HMI_RT_1::HMI_Screen/faceplate_modules/CM_Freq/Events.js"
+            ),
+            "screen_modules/Screen_Content/HMI_RT_1__HMI_Screen/faceplate_modules/CM_Freq/Events.js"
+        );
+        assert_eq!(
+            script_path("screen_modules/Screen_Content/HMI_RT_1:Main/Dynamics.js"),
+            "screen_modules/Screen_Content/HMI_RT_1_Main/Dynamics.js"
+        );
+        assert_eq!(
+            script_path("http://localhost/global/Scripts.js"),
+            "localhost/global/Scripts.js"
+        );
+        let query = script_path("/Screen/Events.js?v=2");
+        assert!(
+            query.starts_with("Screen/Events~") && query.ends_with(".js_v=2"),
+            "{}",
+            query
+        );
+        assert_eq!(
+            script_path("/Bildschirm/Überdruck_Ventil/Events.js"),
+            "Bildschirm/Überdruck_Ventil/Events.js"
+        );
+    }
+
+    #[test]
+    fn traversal_stays_inside_the_context_directory() {
+        let path = script_path("/a/../../etc/./passwd");
+        assert!(path.starts_with("a/_~"));
+        assert!(path.ends_with("/etc/passwd"));
+        assert!(
+            path.split('/')
+                .all(|s| s != ".." && s != "." && !s.is_empty())
+        );
+        assert!(
+            !script_path("..\\..\\Windows\\win.ini")
+                .split('/')
+                .any(|s| s == "..")
+        );
+        assert_ne!(script_path("/a/../b.js"), script_path("/a/.../b.js"));
+    }
+
+    #[test]
+    fn windows_reserved_and_trailing_names_are_renamed() {
+        let con = script_path("/Screen/CON.js");
+        assert!(
+            con.starts_with("Screen/CON~") && con.ends_with(".js"),
+            "{}",
+            con
+        );
+        assert!(script_path("/Screen/nul").starts_with("Screen/nul~"));
+        assert!(script_path("/Screen/com1.txt.js").starts_with("Screen/com1~"));
+        assert_eq!(script_path("/Screen/Console.js"), "Screen/Console.js");
+
+        let dotted = script_path("/Screen./Events.js");
+        assert!(
+            dotted.starts_with("Screen~") && dotted.ends_with("/Events.js"),
+            "{}",
+            dotted
+        );
+        assert_ne!(
+            script_path("/Screen /Events.js"),
+            script_path("/Screen/Events.js")
+        );
+        assert!(script_path("/Scr\u{1}een/Events.js").starts_with("Scr_een~"));
+    }
+
+    #[test]
+    fn long_names_and_paths_are_cut_deterministically() {
+        let long_name = format!("/Screen/{}.js", "Ä".repeat(80));
+        let path = script_path(&long_name);
+        let file = path.rsplit('/').next().unwrap();
+        assert!(file.len() <= MAX_SEGMENT_LEN + 10);
+        assert!(file.ends_with(".js"));
+        assert_eq!(path, script_path(&long_name));
+
+        let deep = format!("/{}/Events.js", ["Faceplate_Container_Level"; 12].join("/"));
+        let path = script_path(&deep);
+        assert!(path.len() <= MAX_PATH_LEN, "{}", path);
+        assert!(path.starts_with("Faceplate_Container_Level/"));
+        assert!(path.ends_with("/Events.js"));
+        assert_ne!(
+            path,
+            script_path(&deep.replace("Events.js", "Other/Events.js"))
+        );
+    }
+
+    #[test]
+    fn names_that_collide_after_replacement_get_a_hash() {
+        let urls = [
+            "/Screen/HMI_RT_1::Main/Events.js",
+            "/Screen/HMI_RT_1:Main/Events.js",
+            "/Screen/HMI_RT_1_Main/Events.js",
+            "/Screen/HMI_RT_1__Main/Events.js",
+            "/Screen/HMI_RT_1:_Main/Events.js",
+            "/Screen/HMI_RT_1|Main/Events.js",
+        ];
+        let paths: Vec<String> = urls.iter().map(|url| script_path(url)).collect();
+        assert_eq!(paths[0], "Screen/HMI_RT_1__Main/Events.js");
+        assert_eq!(paths[1], "Screen/HMI_RT_1_Main/Events.js");
+        assert!(
+            paths[2].starts_with("Screen/HMI_RT_1_Main~"),
+            "{}",
+            paths[2]
+        );
+        for (i, path) in paths.iter().enumerate() {
+            for other in &paths[i + 1..] {
+                assert_ne!(path, other);
+            }
+        }
+        assert_eq!(
+            script_path("/screen_modules/Screen_Content/HMI_RT_1::_Hidden/Events.js"),
+            "screen_modules/Screen_Content/HMI_RT_1___Hidden/Events.js"
+        );
+    }
+
+    #[test]
+    fn names_of_companion_files_get_a_hash() {
+        let raw = script_path("/Screen/Events.raw.js");
+        assert!(
+            raw.starts_with("Screen/Events.raw~") && raw.ends_with(".js"),
+            "{}",
+            raw
+        );
+        assert_ne!(
+            raw,
+            crate::pretty::raw_path(&script_path("/Screen/Events.js"))
+        );
+        assert!(script_path("/Screen/Events.split/a.js").starts_with("Screen/Events.split~"));
+        assert!(script_path("/Screen/Events.RAW").starts_with("Screen/Events.RAW~"));
+        assert_eq!(script_path("/Screen/Rawdata.js"), "Screen/Rawdata.js");
+    }
+
+    #[test]
+    fn disambiguated_paths_differ_by_name() {
+        let taken = "Screen/Main/Main_Button_1_OnTapped.js";
+        let other = disambiguate(taken, "Main_Button_1:OnTapped");
+        assert!(
+            other.starts_with("Screen/Main/Main_Button_1_OnTapped~") && other.ends_with(".js"),
+            "{}",
+            other
+        );
+        assert_eq!(other, disambiguate(taken, "Main_Button_1:OnTapped"));
+        assert_ne!(other, disambiguate(taken, "Main_Button_1|OnTapped"));
+    }
+
+    #[test]
+    fn empty_paths_get_a_name() {
+        let path = script_path("///");
+        assert!(path.starts_with("_~"));
+        assert_ne!(path, script_path("/./"));
+    }
+}
@@ -0,0 +1,89 @@
+use oxc_allocator::Allocator;
+use oxc_codegen::{Codegen, CodegenOptions, IndentChar};
+use oxc_parser::Parser;
+use oxc_span::SourceType;
+
+// ============================================================================
+// JavaScript Pretty-Printing
+// ============================================================================
+
+/// Reformat a script with one statement per line and four-space indentation.
+/// Returns `None` if the script does not parse, so it can be kept as is.
+///
+/// The script is parsed and printed again, which normalises quotes and
+/// spacing. Comments inside expressions are lost, so the runtime's source
+/// should be kept next to the result.
+pub fn pretty_print(source: &str) -> Option<String> {
+    let allocator = Allocator::default();
+    let parsed = Parser::new(&allocator, source, SourceType::unambiguous()).parse();
+    if parsed.panicked || !parsed.errors.is_empty() {
+        return None;
+    }
+    let options = CodegenOptions {
+        indent_char: IndentChar::Space,
+        indent_width: 4,
+        ..CodegenOptions::default()
+    };
+    Some(
+        Codegen::new()
+            .with_options(options)
+            .build(&parsed.program)
+            .code,
+    )
+}
+
+/// Name of the unformatted copy of a dumped script: `Events.js` becomes
+/// `Events.raw.js`.
+pub fn raw_path(path: &str) -> String {
+    let (dir, file) = match path.rsplit_once('/') {
+        Some((dir, file)) => (format!("{}/", dir), file),
+        None => (String::new(), path),
+    };
+    match file.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{}{}.raw.{}", dir, stem, ext),
+        _ => format!("{}{}.raw", dir, file),
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_lines_are_split() {
+        let source = "export function HMI_Screen_Text_Dynamics(item){let v=Tags('Level').Read();if(v>10){item.BackColor=0xFFFF0000;}else{item.BackColor=0xFF00FF00;}return v;}";
+        let pretty = pretty_print(source).unwrap();
+        assert!(pretty.lines().count() > 5, "{}", pretty);
+        assert!(
+            pretty.contains("\n    let v = Tags(\"Level\").Read();\n"),
+            "{}",
+            pretty
+        );
+        assert_eq!(pretty_print(&pretty).as_deref(), Some(pretty.as_str()));
+    }
+
+    #[test]
+    fn scripts_without_modules_parse() {
+        let pretty = pretty_print("var x=1;with(obj){x=2}").unwrap();
+        assert!(pretty.contains("with(obj) {\n    x = 2;\n}"), "{}", pretty);
+    }
+
+    #[test]
+    fn invalid_scripts_are_not_formatted() {
+        assert_eq!(pretty_print("function (){"), None);
+    }
+
+    #[test]
+    fn raw_path_keeps_directory_and_extension() {
+        assert_eq!(
+            raw_path("Screen/HMI_RT_1__Main/Events.js"),
+            "Screen/HMI_RT_1__Main/Events.raw.js"
+        );
+        assert_eq!(raw_path("Screen.v2/Events"), "Screen.v2/Events.raw");
+        assert_eq!(raw_path("Dynamics.js"), "Dynamics.raw.js");
+    }
+}
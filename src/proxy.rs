@@ -1,15 +1,21 @@
-use anyhow::Result;
-use colored::Colorize;
+use anyhow::{Context, Result};
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use tokio::sync::{broadcast, RwLock};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{RwLock, broadcast, watch};
 use tokio::time::Duration;
 use tokio_tungstenite::tungstenite::Message;
 use warp::Filter;
 
-use crate::config::config;
-use crate::logging::*;
+use crate::breakpoints::{BreakpointStore, BreakpointSync};
+use crate::config::{Configuration, ContextKind, ContextSpec, DumpFormat, Station};
+use crate::dump::{ScriptDumper, clean_dump_scripts, log_retired, retire_context_dump};
+use crate::events::{DisconnectReason, ProxyEvent};
+use crate::ids::IdMap;
+use crate::logging::{LogFields, LogFile, Logger, Tag, Verbosity};
+use crate::session::Session;
+use crate::urlmap::{UrlMap, UrlStyle, dump_file_url};
 
 // ============================================================================
 // Types
@@ -28,226 +34,76 @@ struct DebugTarget {
     web_socket_debugger_url: String,
 }
 
+/// Per-context state: the current target, the running proxy server and what
+/// the context's clients set up that has to survive a target change.
+#[derive(Debug, Default)]
+struct ContextState {
+    path: Option<String>,
+    highest_vcs: u32,
+    breakpoints: Arc<Mutex<BreakpointStore>>,
+    urls: Arc<Mutex<UrlMap>>,
+    /// Disconnects every client, telling why
+    clients_shutdown_tx: Option<broadcast::Sender<DisconnectReason>>,
+    retarget_tx: Option<broadcast::Sender<String>>,
+    server_shutdown_tx: Option<tokio::sync::oneshot::Sender<()>>,
+    server_handle: Option<tokio::task::JoinHandle<()>>,
+}
+
 #[derive(Debug)]
 struct AppState {
-    dynamics_path: Option<String>,
-    events_path: Option<String>,
-    highest_dynamics_vcs: u32,
-    highest_events_vcs: u32,
+    contexts: HashMap<ContextKind, ContextState>,
     consecutive_failures: u32,
     target_available: bool,
-    dynamics_clients_shutdown_tx: Option<broadcast::Sender<()>>,
-    dynamics_server_shutdown_tx: Option<tokio::sync::oneshot::Sender<()>>,
-    dynamics_server_handle: Option<tokio::task::JoinHandle<()>>,
-    events_clients_shutdown_tx: Option<broadcast::Sender<()>>,
-    events_server_shutdown_tx: Option<tokio::sync::oneshot::Sender<()>>,
-    events_server_handle: Option<tokio::task::JoinHandle<()>>,
+    log: Logger,
 }
 
 impl AppState {
-    fn new() -> Self {
+    fn new(log: Logger) -> Self {
         Self {
-            dynamics_path: None,
-            events_path: None,
-            highest_dynamics_vcs: 0,
-            highest_events_vcs: 0,
+            contexts: HashMap::new(),
             consecutive_failures: 0,
             target_available: false,
-            dynamics_clients_shutdown_tx: None,
-            dynamics_server_shutdown_tx: None,
-            dynamics_server_handle: None,
-            events_clients_shutdown_tx: None,
-            events_server_shutdown_tx: None,
-            events_server_handle: None,
-        }
-    }
-}
-
-type SharedState = Arc<RwLock<AppState>>;
-
-// ============================================================================
-// CDP Message Rewriting
-// ============================================================================
-
-/// Shorten a WinCC script URL by stripping known prefixes and intermediate segments.
-///
-/// Transforms paths like:
-///   /screen_modules/Screen_Content/HMI_RT_1::HMI_Screen/faceplate_modules/CM_Freq/Events.js
-/// Into:
-///   HMI_Screen/CM_Freq/Events.js
-fn shorten_script_url(url: &str) -> Option<String> {
-    // Strip optional leading slash, then the known prefix
-    let rest = url.strip_prefix('/').unwrap_or(url);
-    let rest = rest.strip_prefix("screen_modules/Screen_Content/")?;
-
-    // Strip HMI_RT_\d+:: (double colon) or HMI_RT_\d+: (single colon) prefix
-    let rest = if let Some(colon_pos) = rest.find(':') {
-        let before_colon = &rest[..colon_pos];
-        if before_colon.starts_with("HMI_RT_")
-            && before_colon["HMI_RT_".len()..].chars().all(|c| c.is_ascii_digit())
-        {
-            // Skip past all consecutive colons (handles both : and ::)
-            let after_colon = &rest[colon_pos..];
-            after_colon.trim_start_matches(':')
-        } else {
-            rest
+            log,
         }
-    } else {
-        rest
-    };
-
-    // Strip faceplate_modules/ intermediate segment
-    let result = rest.replace("/faceplate_modules/", "/");
-
-    Some(result)
-}
-
-/// Inspect a CDP JSON message; if it is a `Debugger.scriptParsed` event,
-/// rewrite `params.url` to a shorter form. Returns the (possibly rewritten) text.
-fn maybe_rewrite_cdp_message(text: &str) -> String {
-    // Skip rewriting when long paths are requested
-    if config().long_paths {
-        return text.to_string();
-    }
-
-    // Quick bailout: avoid JSON parsing for the vast majority of messages
-    if !text.contains("scriptParsed") {
-        return text.to_string();
     }
 
-    let mut parsed: serde_json::Value = match serde_json::from_str(text) {
-        Ok(v) => v,
-        Err(_) => return text.to_string(),
-    };
-
-    // Check that this is a Debugger.scriptParsed event
-    let is_script_parsed = parsed
-        .get("method")
-        .and_then(|m| m.as_str())
-        .map_or(false, |m| m == "Debugger.scriptParsed");
-
-    if !is_script_parsed {
-        return text.to_string();
+    fn context(&mut self, kind: &ContextKind) -> &mut ContextState {
+        let log = &self.log;
+        self.contexts
+            .entry(kind.clone())
+            .or_insert_with(|| ContextState {
+                urls: Arc::new(Mutex::new(UrlMap::new(log.clone()))),
+                ..Default::default()
+            })
     }
-
-    // Try to rewrite params.url
-    let Some(params) = parsed.get_mut("params") else {
-        return text.to_string();
-    };
-    let Some(url_str) = params.get("url").and_then(|v| v.as_str()) else {
-        return text.to_string();
-    };
-    let Some(short) = shorten_script_url(url_str) else {
-        return text.to_string();
-    };
-
-    log_verbose(&format!("Rewrote script URL: {} -> {}", url_str, short));
-    params.as_object_mut().unwrap().insert(
-        "url".to_string(),
-        serde_json::Value::String(short),
-    );
-    serde_json::to_string(&parsed).unwrap_or_else(|_| text.to_string())
 }
 
-// ============================================================================
-// Script Dump
-// ============================================================================
+type SharedState = Arc<RwLock<AppState>>;
 
-struct ScriptDumper {
-    dump_dir: String,
-    target_dir: &'static str,
-    next_msg_id: u64,
-    pending: std::collections::HashMap<u64, String>,
-    count: u64,
+/// The configuration and output shared by every task of a running proxy.
+#[derive(Debug)]
+struct Env {
+    cfg: Configuration,
+    log: Logger,
 }
 
-impl ScriptDumper {
-    fn new(dump_dir: String, target_name: &str) -> Self {
-        Self {
-            dump_dir,
-            target_dir: if target_name.contains("Dynamics") { "Dynamics" } else { "Events" },
-            next_msg_id: 900_000,
-            pending: std::collections::HashMap::new(),
-            count: 0,
-        }
-    }
-
-    /// If this is a scriptParsed event, returns a getScriptSource CDP request to send.
-    fn handle_script_parsed(&mut self, parsed: &serde_json::Value) -> Option<String> {
-        if parsed.get("method").and_then(|m| m.as_str()) != Some("Debugger.scriptParsed") {
-            return None;
-        }
-        let params = parsed.get("params")?;
-        let script_id = params.get("scriptId").and_then(|s| s.as_str()).unwrap_or("");
-        let script_url = params.get("url").and_then(|s| s.as_str()).unwrap_or("");
-
-        if script_url.is_empty()
-            || (script_url.starts_with("eval-") && script_url.ends_with(".cdp"))
-        {
-            return None;
-        }
-
-        let safe_url = script_url
-            .replace(':', "_")
-            .replace('*', "_")
-            .replace('?', "_")
-            .replace('"', "_")
-            .replace('<', "_")
-            .replace('>', "_")
-            .replace('|', "_");
-        let file_path = format!("{}/{}/{}", self.dump_dir, self.target_dir, safe_url);
-
-        let request = serde_json::json!({
-            "id": self.next_msg_id,
-            "method": "Debugger.getScriptSource",
-            "params": { "scriptId": script_id }
-        });
-
-        self.pending.insert(self.next_msg_id, file_path);
-        self.next_msg_id += 1;
-
-        Some(request.to_string())
-    }
-
-    /// If this is a response to one of our getScriptSource requests, write to disk.
-    /// Returns true if the message was consumed (should not be forwarded to client).
-    fn handle_response(&mut self, parsed: &serde_json::Value) -> bool {
-        let Some(id) = parsed.get("id").and_then(|id| id.as_u64()) else {
-            return false;
-        };
-        let Some(file_path) = self.pending.remove(&id) else {
-            return false;
-        };
-
-        if let Some(source) = parsed
-            .get("result")
-            .and_then(|r| r.get("scriptSource"))
-            .and_then(|s| s.as_str())
-        {
-            let path = std::path::Path::new(&file_path);
-            if let Some(parent) = path.parent() {
-                let _ = std::fs::create_dir_all(parent);
-            }
-            let _ = std::fs::write(path, source);
-            self.count += 1;
-            log_verbose(&format!("[DUMP] {}", file_path));
-        }
-
-        true // consumed, don't forward
-    }
-}
+type SharedEnv = Arc<Env>;
 
 // ============================================================================
 // TCP Connectivity Check
 // ============================================================================
 
-async fn wait_for_target_connectivity() {
-    let cfg = config();
-    let addr = format!("{}:{}", cfg.target_host, cfg.target_port);
+async fn wait_for_target_connectivity(env: &Env, station: &Station) {
+    let (cfg, log) = (&env.cfg, &env.log);
+    let prefix = station.prefix();
+    let addr = format!("{}:{}", station.target_host, station.target_port);
     let mut shown_error = false;
 
     loop {
-        log_verbose(&format!("Checking TCP connectivity to {}...", addr));
+        log.verbose(&format!(
+            "{}Checking TCP connectivity to {}...",
+            prefix, addr
+        ));
 
         match tokio::time::timeout(
             Duration::from_secs(5),
@@ -256,34 +112,40 @@ async fn wait_for_target_connectivity() {
         .await
         {
             Ok(Ok(_)) => {
-                log_success(&format!("Target {} is reachable", addr));
+                log.success(&format!("{}Target {} is reachable", prefix, addr));
                 return;
             }
             Ok(Err(e)) => {
                 if !shown_error {
-                    log_warn(&format!("Cannot connect to {}: {}", addr, e));
-                    log_warn("Troubleshooting:");
-                    log_warn("  - Is WinCC Unified running with debugging enabled?");
-                    log_warn("  - Check firewall rules for port 9222 (in/out)");
-                    log_warn("  - If remote: verify netsh portproxy is configured");
-                    log_warn("  - After Windows restart: delete and re-add netsh rules");
-                    log_warn("  - Run with --help for detailed setup instructions");
+                    log.warn(&format!("{}Cannot connect to {}: {}", prefix, addr, e));
+                    log.warn("Troubleshooting:");
+                    log.warn("  - Is WinCC Unified running with debugging enabled?");
+                    log.warn("  - Check firewall rules for port 9222 (in/out)");
+                    log.warn("  - If remote: verify netsh portproxy is configured");
+                    log.warn("  - After Windows restart: delete and re-add netsh rules");
+                    log.warn("  - Run with --help for detailed setup instructions");
                     shown_error = true;
                 }
-                log(&format!("Retrying in {} seconds...", cfg.poll_interval));
+                log.log(&format!(
+                    "{}Retrying in {} seconds...",
+                    prefix, cfg.poll_interval
+                ));
             }
             Err(_) => {
                 if !shown_error {
-                    log_warn(&format!("Connection to {} timed out", addr));
-                    log_warn("Troubleshooting:");
-                    log_warn("  - Is WinCC Unified running with debugging enabled?");
-                    log_warn("  - Check firewall rules for port 9222 (in/out)");
-                    log_warn("  - If remote: verify netsh portproxy is configured");
-                    log_warn("  - After Windows restart: delete and re-add netsh rules");
-                    log_warn("  - Run with --help for detailed setup instructions");
+                    log.warn(&format!("{}Connection to {} timed out", prefix, addr));
+                    log.warn("Troubleshooting:");
+                    log.warn("  - Is WinCC Unified running with debugging enabled?");
+                    log.warn("  - Check firewall rules for port 9222 (in/out)");
+                    log.warn("  - If remote: verify netsh portproxy is configured");
+                    log.warn("  - After Windows restart: delete and re-add netsh rules");
+                    log.warn("  - Run with --help for detailed setup instructions");
                     shown_error = true;
                 }
-                log(&format!("Retrying in {} seconds...", cfg.poll_interval));
+                log.log(&format!(
+                    "{}Retrying in {} seconds...",
+                    prefix, cfg.poll_interval
+                ));
             }
         }
 
@@ -297,14 +159,15 @@ async fn wait_for_target_connectivity() {
 
 fn extract_vcs_number(title: &str) -> Option<u32> {
     // Parse "VCS_8" from titles like " @localhost VCS_8 Dynamics"
-    title.split("VCS_")
-        .nth(1)
-        .and_then(|s| s.split(|c: char| !c.is_numeric())
+    title.split("VCS_").nth(1).and_then(|s| {
+        s.split(|c: char| !c.is_numeric())
             .next()
-            .and_then(|n| n.parse().ok()))
+            .and_then(|n| n.parse().ok())
+    })
 }
 
 async fn select_best_target(
+    log: &Logger,
     candidates: Vec<DebugTarget>,
     target_type: &str,
     current_highest_vcs: u32,
@@ -313,17 +176,25 @@ async fn select_best_target(
         return None;
     }
 
-    log_verbose(&format!("Selecting best {} target from {} candidates", target_type, candidates.len()));
+    log.verbose(&format!(
+        "Selecting best {} target from {} candidates",
+        target_type,
+        candidates.len()
+    ));
 
     // Select target with highest VCS number
-    let best_target = candidates.into_iter()
+    let best_target = candidates
+        .into_iter()
         .max_by_key(|t| extract_vcs_number(&t.title).unwrap_or(0))?;
 
     let vcs_num = extract_vcs_number(&best_target.title).unwrap_or(0);
 
     // Only update highest VCS if new number is higher
     let new_highest = if vcs_num > current_highest_vcs {
-        log_verbose(&format!("  VCS number increased: {} -> {}", current_highest_vcs, vcs_num));
+        log.verbose(&format!(
+            "  VCS number increased: {} -> {}",
+            current_highest_vcs, vcs_num
+        ));
         vcs_num
     } else {
         current_highest_vcs
@@ -332,10 +203,37 @@ async fn select_best_target(
     Some((best_target, new_highest))
 }
 
-async fn fetch_targets() -> Result<Vec<DebugTarget>> {
-    let cfg = config();
-    let url = format!("http://{}:{}/json", cfg.target_host, cfg.target_port);
-    log_verbose(&format!("Fetching targets from {}", url));
+/// The target each of a station's contexts would be proxied to right now, by
+/// the same rules as the poller, as websocket path and VCS number.
+pub async fn current_targets(
+    station: &Station,
+    log: &Logger,
+) -> Result<Vec<(ContextSpec, Option<(String, u32)>)>> {
+    let targets = fetch_targets(station, log).await?;
+    let mut current = Vec::new();
+    for spec in &station.contexts {
+        let matching: Vec<DebugTarget> = targets
+            .iter()
+            .filter(|t| station.matches(spec, &t.title))
+            .cloned()
+            .collect();
+        let selected = select_best_target(log, matching, spec.kind.name(), 0)
+            .await
+            .and_then(|(target, vcs)| {
+                let path = target.web_socket_debugger_url.split('/').next_back()?;
+                (!path.is_empty()).then(|| (path.to_string(), vcs))
+            });
+        current.push((spec.clone(), selected));
+    }
+    Ok(current)
+}
+
+async fn fetch_targets(station: &Station, log: &Logger) -> Result<Vec<DebugTarget>> {
+    let url = format!(
+        "http://{}:{}/json",
+        station.target_host, station.target_port
+    );
+    log.verbose(&format!("Fetching targets from {}", url));
 
     let client = reqwest::Client::builder()
         .connect_timeout(Duration::from_secs(5))
@@ -345,53 +243,71 @@ async fn fetch_targets() -> Result<Vec<DebugTarget>> {
     let response = client.get(&url).send().await?;
     let targets: Vec<DebugTarget> = response.json().await?;
 
-    log_verbose(&format!("Received {} debug targets", targets.len()));
+    log.verbose(&format!("Received {} debug targets", targets.len()));
     Ok(targets)
 }
 
-async fn restart_server(state: SharedState, target_name: &str, old_path: String, new_path: String) {
-    let old_decoded = urlencoding::decode(&old_path).unwrap_or_else(|_| old_path.clone().into());
-    let new_decoded = urlencoding::decode(&new_path).unwrap_or_else(|_| new_path.clone().into());
-    println!(
-        "{} {} {} target changed:",
-        format!("[{}]", timestamp()).dimmed(),
-        "[CHANGE]".blue().bold(),
-        target_name
-    );
-    log(&format!("   Old: {}", old_decoded));
-    log(&format!("   New: {}", new_decoded));
-
-    // Clean dumped scripts for this target type
-    if let Some(ref dump_dir) = config().dump_output {
-        let subdir = std::path::Path::new(dump_dir).join(target_name);
-        if subdir.exists() {
-            let _ = std::fs::remove_dir_all(&subdir);
-            log(&format!("   Cleaned {}/", subdir.display()));
-        }
+/// Log a context's target change and retire its dumped scripts. `old_vcs` is
+/// the VCS number of the old target, used to name its history snapshot.
+fn announce_target_change(
+    env: &Env,
+    station: &Station,
+    kind: &ContextKind,
+    old_path: &str,
+    new_path: &str,
+    old_vcs: u32,
+) {
+    let log = &env.log;
+    let prefix = station.prefix();
+    log.event(ProxyEvent::TargetChanged {
+        origin: station.origin(kind),
+        old_path: old_path.to_string(),
+        new_path: new_path.to_string(),
+    });
+
+    // Clean or archive dumped scripts for this context
+    if let Some(ref dump_dir) = station.dump_output {
+        let retired = retire_context_dump(dump_dir, kind, Some(old_vcs), env.cfg.dump_history, log);
+        log_retired(log, &prefix, dump_dir, kind, &retired);
     }
+}
 
-    println!(
-        "{} {} Closing all {} client connections...",
-        format!("[{}]", timestamp()).dimmed(),
-        "[STOP]".magenta().bold(),
-        target_name
+async fn restart_server(
+    env: SharedEnv,
+    state: SharedState,
+    station: Arc<Station>,
+    spec: ContextSpec,
+    old_path: String,
+    new_path: String,
+    old_vcs: u32,
+) {
+    let prefix = station.prefix();
+    let kind = spec.kind.clone();
+    let log = &env.log.scoped(LogFields {
+        origin: Some(station.origin(&kind)),
+        target: Some(new_path.clone()),
+        ..Default::default()
+    });
+    announce_target_change(&env, &station, &kind, &old_path, &new_path, old_vcs);
+
+    log.tagged(
+        Tag::Stop,
+        &format!("{}Closing all {} client connections...", prefix, kind),
     );
 
     // Step 1: Send shutdown signal to all clients
-    let shutdown_tx = {
-        let mut state_guard = state.write().await;
-        match target_name {
-            "Dynamics" => state_guard.dynamics_clients_shutdown_tx.take(),
-            "Events" => state_guard.events_clients_shutdown_tx.take(),
-            _ => None,
-        }
-    };
+    let shutdown_tx = state
+        .write()
+        .await
+        .context(&kind)
+        .clients_shutdown_tx
+        .take();
 
     if let Some(tx) = shutdown_tx {
-        let _ = tx.send(());
-        log(&format!(
-            "   Sent disconnect signal to all {} clients",
-            target_name
+        let _ = tx.send(DisconnectReason::TargetChanged);
+        log.log(&format!(
+            "{}   Sent disconnect signal to all {} clients",
+            prefix, kind
         ));
     }
 
@@ -401,50 +317,76 @@ async fn restart_server(state: SharedState, target_name: &str, old_path: String,
     // Step 2: Send shutdown signal to server and take the handle
     let (server_handle, server_shutdown_tx) = {
         let mut state_guard = state.write().await;
-        let handle = match target_name {
-            "Dynamics" => state_guard.dynamics_server_handle.take(),
-            "Events" => state_guard.events_server_handle.take(),
-            _ => None,
-        };
-        let tx = match target_name {
-            "Dynamics" => state_guard.dynamics_server_shutdown_tx.take(),
-            "Events" => state_guard.events_server_shutdown_tx.take(),
-            _ => None,
-        };
+        let context = state_guard.context(&kind);
 
         // Store new path
-        match target_name {
-            "Dynamics" => state_guard.dynamics_path = Some(new_path.clone()),
-            "Events" => state_guard.events_path = Some(new_path.clone()),
-            _ => {}
-        }
+        context.path = Some(new_path.clone());
 
-        (handle, tx)
+        (
+            context.server_handle.take(),
+            context.server_shutdown_tx.take(),
+        )
     };
 
     if let Some(tx) = server_shutdown_tx {
         let _ = tx.send(());
-        log(&format!("   Stopping {} proxy server...", target_name));
+        log.log(&format!("{}   Stopping {} proxy server...", prefix, kind));
     }
 
     // Wait for server to actually stop
     if let Some(handle) = server_handle {
-        log(&format!(
-            "   Waiting for {} server shutdown...",
-            target_name
+        log.log(&format!(
+            "{}   Waiting for {} server shutdown...",
+            prefix, kind
         ));
         let _ = handle.await;
     }
 
     // Start new server (this waits until server is ready)
-    log(&format!("   Restarting {} proxy server...", target_name));
-    match target_name {
-        "Dynamics" => start_dynamics_server(state.clone()).await,
-        "Events" => start_events_server(state.clone()).await,
-        _ => {}
+    log.log(&format!("{}   Restarting {} proxy server...", prefix, kind));
+    if let Err(e) = start_server(env.clone(), state.clone(), station.clone(), spec).await {
+        log.error(&format!(
+            "{}Cannot restart the {} proxy server: {:#}",
+            prefix, kind, e
+        ));
     }
 }
 
+/// Session-preserving alternative to `restart_server`: the server keeps
+/// running and every connected client switches its upstream to the new target.
+async fn retarget_sessions(
+    env: SharedEnv,
+    state: SharedState,
+    station: Arc<Station>,
+    spec: ContextSpec,
+    old_path: String,
+    new_path: String,
+    old_vcs: u32,
+) {
+    let prefix = station.prefix();
+    let kind = spec.kind;
+    let log = env.log.scoped(LogFields {
+        origin: Some(station.origin(&kind)),
+        target: Some(new_path.clone()),
+        ..Default::default()
+    });
+    announce_target_change(&env, &station, &kind, &old_path, &new_path, old_vcs);
+
+    let mut state_guard = state.write().await;
+    let context = state_guard.context(&kind);
+    context.path = Some(new_path.clone());
+
+    let clients = context
+        .retarget_tx
+        .as_ref()
+        .and_then(|tx| tx.send(new_path).ok())
+        .unwrap_or(0);
+    log.log(&format!(
+        "{}   Moved {} {} client(s) to the new target",
+        prefix, clients, kind
+    ));
+}
+
 enum TargetChange {
     Initial { path: String, vcs: u32 },
     Changed { old: String, new: String, vcs: u32 },
@@ -452,6 +394,7 @@ enum TargetChange {
 }
 
 fn check_target_change(
+    log: &Logger,
     result: Option<(DebugTarget, u32)>,
     current_path: &Option<String>,
     target_name: &str,
@@ -462,7 +405,7 @@ fn check_target_change(
         None => return TargetChange::None { vcs: 0 },
     };
 
-    let path = match target.web_socket_debugger_url.split('/').last() {
+    let path = match target.web_socket_debugger_url.split('/').next_back() {
         Some(p) if !p.is_empty() => p.to_string(),
         _ => return TargetChange::None { vcs: new_vcs },
     };
@@ -472,7 +415,7 @@ fn check_target_change(
     }
 
     if candidate_count > 1 {
-        log_warn(&format!(
+        log.warn(&format!(
             "Multiple alive {} targets found ({}), selecting highest VCS number!",
             target_name, candidate_count
         ));
@@ -488,186 +431,143 @@ fn check_target_change(
     }
 }
 
-async fn update_targets(state: SharedState) {
-    log_verbose("--- Target Update Cycle ---");
+async fn update_targets(env: SharedEnv, state: SharedState, station: Arc<Station>) {
+    let (cfg, log) = (&env.cfg, &env.log);
+    let prefix = station.prefix();
+    log.verbose(&format!("{}--- Target Update Cycle ---", prefix));
 
-    match fetch_targets().await {
+    match fetch_targets(&station, log).await {
         Ok(targets) => {
             let mut state_guard = state.write().await;
 
             // Reset failure counter on success
-            if state_guard.consecutive_failures > 0 {
-                log_success("Target server is back online!");
-                state_guard.consecutive_failures = 0;
-            }
-
+            let after_failures = std::mem::take(&mut state_guard.consecutive_failures);
             if !state_guard.target_available {
                 state_guard.target_available = true;
-                let cfg = config();
-                println!(
-                    "{} {} WinCC target server connected at {}:{}",
-                    format!("[{}]", timestamp()).dimmed(),
-                    "[CONN]".cyan().bold(),
-                    cfg.target_host,
-                    cfg.target_port
-                );
+                log.event(ProxyEvent::TargetServerConnected {
+                    station: station.name.clone(),
+                    host: station.target_host.clone(),
+                    port: station.target_port,
+                    after_failures,
+                });
             }
 
-            // Separate Dynamics and Events targets
-            let dynamics_candidates: Vec<DebugTarget> = targets
+            // Classify targets per context by title
+            let candidates: Vec<(ContextSpec, Vec<DebugTarget>, u32)> = station
+                .contexts
                 .iter()
-                .filter(|t| t.title.to_lowercase().contains("dynamics"))
-                .cloned()
+                .map(|spec| {
+                    let matching: Vec<DebugTarget> = targets
+                        .iter()
+                        .filter(|t| station.matches(spec, &t.title))
+                        .cloned()
+                        .collect();
+                    let current_vcs = state_guard
+                        .contexts
+                        .get(&spec.kind)
+                        .map_or(0, |c| c.highest_vcs);
+                    (spec.clone(), matching, current_vcs)
+                })
                 .collect();
 
-            let events_candidates: Vec<DebugTarget> = targets
-                .iter()
-                .filter(|t| t.title.to_lowercase().contains("events"))
-                .cloned()
-                .collect();
-
-            let current_dynamics_vcs = state_guard.highest_dynamics_vcs;
-            let current_events_vcs = state_guard.highest_events_vcs;
-
-            let dynamics_count = dynamics_candidates.len();
-            let events_count = events_candidates.len();
-
             // Release lock during health checks
             drop(state_guard);
 
             // Select best targets with health checks
-            let dynamics_result = select_best_target(
-                dynamics_candidates,
-                "Dynamics",
-                current_dynamics_vcs,
-            ).await;
-
-            let events_result = select_best_target(
-                events_candidates,
-                "Events",
-                current_events_vcs,
-            ).await;
+            let mut results = Vec::new();
+            for (spec, matching, current_vcs) in candidates {
+                let count = matching.len();
+                let result = select_best_target(log, matching, spec.kind.name(), current_vcs).await;
+                results.push((spec, result, count));
+            }
 
             // Reacquire lock for updates
             let mut state_guard = state.write().await;
-
-            let dynamics_change = check_target_change(
-                dynamics_result,
-                &state_guard.dynamics_path,
-                "Dynamics",
-                dynamics_count,
-            );
-            let events_change = check_target_change(
-                events_result,
-                &state_guard.events_path,
-                "Events",
-                events_count,
-            );
-
-            // Apply Dynamics change
-            let dynamics_restart = match dynamics_change {
-                TargetChange::Initial { path, vcs } => {
-                    state_guard.highest_dynamics_vcs = vcs;
-                    let decoded = urlencoding::decode(&path)
-                        .unwrap_or_else(|_| path.clone().into());
-                    println!(
-                        "{} {} Dynamics target discovered: {}",
-                        format!("[{}]", timestamp()).dimmed(),
-                        "[CONN]".cyan().bold(),
-                        decoded
-                    );
-                    state_guard.dynamics_path = Some(path);
-                    None
-                }
-                TargetChange::Changed { old, new, vcs } => {
-                    state_guard.highest_dynamics_vcs = vcs;
-                    Some((old, new))
-                }
-                TargetChange::None { vcs } => {
-                    if vcs > 0 { state_guard.highest_dynamics_vcs = vcs; }
-                    None
-                }
-            };
-
-            // Apply Events change
-            let events_restart = match events_change {
-                TargetChange::Initial { path, vcs } => {
-                    state_guard.highest_events_vcs = vcs;
-                    let decoded = urlencoding::decode(&path)
-                        .unwrap_or_else(|_| path.clone().into());
-                    println!(
-                        "{} {} Events target discovered: {}",
-                        format!("[{}]", timestamp()).dimmed(),
-                        "[CONN]".cyan().bold(),
-                        decoded
-                    );
-                    state_guard.events_path = Some(path);
-                    None
-                }
-                TargetChange::Changed { old, new, vcs } => {
-                    state_guard.highest_events_vcs = vcs;
-                    Some((old, new))
-                }
-                TargetChange::None { vcs } => {
-                    if vcs > 0 { state_guard.highest_events_vcs = vcs; }
-                    None
+            let mut restarts = Vec::new();
+
+            for (spec, result, count) in results {
+                let context = state_guard.context(&spec.kind);
+                let label = format!("{}{}", prefix, spec.kind);
+                match check_target_change(log, result, &context.path, &label, count) {
+                    TargetChange::Initial { path, vcs } => {
+                        context.highest_vcs = vcs;
+                        log.event(ProxyEvent::TargetDiscovered {
+                            origin: station.origin(&spec.kind),
+                            path: path.clone(),
+                        });
+                        context.path = Some(path);
+                    }
+                    TargetChange::Changed { old, new, vcs } => {
+                        let old_vcs = std::mem::replace(&mut context.highest_vcs, vcs);
+                        restarts.push((spec, old, new, old_vcs));
+                    }
+                    TargetChange::None { vcs } => {
+                        if vcs > 0 {
+                            context.highest_vcs = vcs;
+                        }
+                    }
                 }
-            };
+            }
 
             // Release lock before restarting
             drop(state_guard);
 
-            // Restart servers sequentially
-            match (dynamics_restart, events_restart) {
-                (Some((old_dyn, new_dyn)), Some((old_evt, new_evt))) => {
-                    // Both changed - restart sequentially
-                    println!(
-                        "{} {} Both targets changed - restarting sequentially",
-                        format!("[{}]", timestamp()).dimmed(),
-                        "[CHANGE]".blue().bold()
-                    );
-                    restart_server(state.clone(), "Dynamics", old_dyn, new_dyn).await;
-                    restart_server(state.clone(), "Events", old_evt, new_evt).await;
+            if cfg.keep_sessions {
+                // Keep clients attached and move them to the new targets
+                for (spec, old, new, old_vcs) in restarts {
+                    retarget_sessions(
+                        env.clone(),
+                        state.clone(),
+                        station.clone(),
+                        spec,
+                        old,
+                        new,
+                        old_vcs,
+                    )
+                    .await;
                 }
-                (Some((old, new)), None) => {
-                    // Only Dynamics changed
-                    restart_server(state.clone(), "Dynamics", old, new).await;
-                }
-                (None, Some((old, new))) => {
-                    // Only Events changed
-                    restart_server(state.clone(), "Events", old, new).await;
+            } else {
+                // Restart servers sequentially
+                if restarts.len() > 1 {
+                    log.tagged(
+                        Tag::Change,
+                        &format!(
+                            "{}{} targets changed - restarting sequentially",
+                            prefix,
+                            restarts.len()
+                        ),
+                    );
                 }
-                (None, None) => {
-                    // No changes
+                for (spec, old, new, old_vcs) in restarts {
+                    restart_server(
+                        env.clone(),
+                        state.clone(),
+                        station.clone(),
+                        spec,
+                        old,
+                        new,
+                        old_vcs,
+                    )
+                    .await;
                 }
             }
 
-            log_verbose("--- End Target Update ---\n");
+            log.verbose(&format!("{}--- End Target Update ---\n", prefix));
         }
         Err(e) => {
             let mut state_guard = state.write().await;
             state_guard.consecutive_failures += 1;
-            let cfg = config();
-
-            if state_guard.consecutive_failures == 1 {
-                log_error(&format!(
-                    "Cannot connect to WinCC at {}:{}",
-                    cfg.target_host, cfg.target_port
-                ));
-                log_error(&format!("   Reason: {}", e));
-                log(&format!(
-                    "Will retry every {} seconds...",
-                    cfg.poll_interval
-                ));
-                state_guard.target_available = false;
-            } else if state_guard.consecutive_failures % 5 == 0 {
-                log(&format!(
-                    "Still cannot connect to WinCC ({} failed attempts, retrying every {}s)",
-                    state_guard.consecutive_failures, cfg.poll_interval
-                ));
-            }
-
-            log_verbose("--- End Target Update (failed) ---\n");
+            state_guard.target_available = false;
+            log.event(ProxyEvent::TargetServerUnreachable {
+                station: station.name.clone(),
+                host: station.target_host.clone(),
+                port: station.target_port,
+                reason: e.to_string(),
+                attempts: state_guard.consecutive_failures,
+                retry_secs: cfg.poll_interval,
+            });
+
+            log.verbose(&format!("{}--- End Target Update (failed) ---\n", prefix));
         }
     }
 }
@@ -676,22 +576,44 @@ async fn update_targets(state: SharedState) {
 // HTTP Proxy
 // ============================================================================
 
-async fn handle_json_request(
-    _state: SharedState,
+/// The host and port clients reach a context's server under. A proxy bound
+/// to loopback uses `loopback_host`; otherwise it answers under the Host
+/// header the client sent, since a loopback name would point other machines
+/// at themselves.
+fn client_authority(
+    cfg: &Configuration,
+    host: Option<String>,
+    loopback_host: &str,
     port: u16,
-    filter_title: String,
+) -> String {
+    if cfg.bind_address.is_loopback() {
+        return format!("{}:{}", loopback_host, port);
+    }
+    host.unwrap_or_else(|| std::net::SocketAddr::new(cfg.bind_address, port).to_string())
+}
+
+/// The targets of a context as the client sees them: only the context's
+/// targets, with websocket URLs pointing back at the proxy.
+async fn handle_json_request(
+    host: Option<String>,
+    env: SharedEnv,
+    station: Arc<Station>,
+    spec: ContextSpec,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let cfg = config();
-    let url = format!("http://{}:{}/json", cfg.target_host, cfg.target_port);
+    let url = format!(
+        "http://{}:{}/json",
+        station.target_host, station.target_port
+    );
+    let host = client_authority(&env.cfg, host, "localhost", spec.port);
 
     match reqwest::get(&url).await {
         Ok(response) => {
             if let Ok(targets) = response.json::<Vec<DebugTarget>>().await {
                 let filtered: Vec<DebugTarget> = targets
                     .into_iter()
-                    .filter(|t| t.title.contains(&filter_title))
+                    .filter(|t| station.matches(&spec, &t.title))
                     .map(|mut t| {
-                        t.web_socket_debugger_url = format!("ws://localhost:{}", port);
+                        t.web_socket_debugger_url = format!("ws://{}", host);
                         t
                     })
                     .collect();
@@ -702,17 +624,22 @@ async fn handle_json_request(
             }
         }
         Err(_) => {
-            log_verbose(&format!("[HTTP Proxy] Target unavailable for /json"));
+            env.log.verbose(&format!(
+                "{}[HTTP Proxy] Target unavailable for /json",
+                station.prefix()
+            ));
             Ok(warp::reply::json(&Vec::<DebugTarget>::new()))
         }
     }
 }
 
-async fn handle_version_request() -> Result<impl warp::Reply, warp::Rejection> {
-    let cfg = config();
+async fn handle_version_request(
+    env: SharedEnv,
+    station: Arc<Station>,
+) -> Result<impl warp::Reply, warp::Rejection> {
     let url = format!(
         "http://{}:{}/json/version",
-        cfg.target_host, cfg.target_port
+        station.target_host, station.target_port
     );
 
     match reqwest::get(&url).await {
@@ -725,247 +652,698 @@ async fn handle_version_request() -> Result<impl warp::Reply, warp::Rejection> {
             }
         }
         Err(_) => {
-            log_verbose("[HTTP Proxy] Target unavailable for /json/version");
+            env.log.verbose(&format!(
+                "{}[HTTP Proxy] Target unavailable for /json/version",
+                station.prefix()
+            ));
             let fallback = r#"{"Browser":"WinCC-Proxy/1.0","Protocol-Version":"1.3"}"#;
             Ok(warp::reply::html(fallback.to_string()))
         }
     }
 }
 
-// ============================================================================
-// WebSocket Proxy
-// ============================================================================
+/// Append a source map field as a base64 VLQ.
+fn push_vlq(out: &mut String, value: i64) {
+    const DIGITS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut rest = if value < 0 {
+        ((-value) << 1) | 1
+    } else {
+        value << 1
+    };
+    loop {
+        let mut digit = rest & 0x1f;
+        rest >>= 5;
+        if rest > 0 {
+            digit |= 0x20;
+        }
+        out.push(DIGITS[digit as usize] as char);
+        if rest == 0 {
+            break;
+        }
+    }
+}
 
-async fn handle_websocket(ws: warp::ws::WebSocket, state: SharedState, target_name: String) {
-    let client_id = rand::random::<u32>();
-    let target_name_log = target_name.clone();
-    log_success(&format!(
-        "[{}] Client #{} connected",
-        target_name_log, client_id
-    ));
+/// Longest fallback source map served. `lines` comes from the query string,
+/// so anything larger is cut off rather than allocated.
+const MAX_SOURCE_MAP_LINES: usize = 1_000_000;
 
-    // Subscribe to shutdown signal
-    let state_guard = state.read().await;
-    let target_path = match target_name.as_str() {
-        "Dynamics" => state_guard.dynamics_path.clone(),
-        "Events" => state_guard.events_path.clone(),
-        _ => None,
+/// Mappings for the first column of each of `lines` lines, at most
+/// `MAX_SOURCE_MAP_LINES`.
+fn line_mappings(lines: usize) -> String {
+    match lines.min(MAX_SOURCE_MAP_LINES) {
+        0 => String::new(),
+        lines => format!("AAAA{}", ";AACA".repeat(lines - 1)),
+    }
+}
+
+/// Mappings for every token of `source` onto the same line and column: a
+/// segment where each run of identifier characters and each other
+/// non-blank character starts. Columns count UTF-16 code units, as CDP does.
+fn token_mappings(source: &str) -> String {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+    let mut mappings = String::new();
+    let (mut previous_line, mut previous_column) = (0i64, 0i64);
+    for (line, text) in source.split('\n').enumerate() {
+        if line > 0 {
+            mappings.push(';');
+        }
+        // Generated columns are relative within a line, source positions across lines
+        let mut segment_column = None;
+        let mut column = 0i64;
+        let mut previous = None;
+        for c in text.chars() {
+            let continues_word = is_word(c) && previous.is_some_and(is_word);
+            if !c.is_whitespace() && !continues_word {
+                if segment_column.is_some() {
+                    mappings.push(',');
+                }
+                push_vlq(&mut mappings, column - segment_column.unwrap_or(0));
+                push_vlq(&mut mappings, 0);
+                push_vlq(&mut mappings, line as i64 - previous_line);
+                push_vlq(&mut mappings, column - previous_column);
+                segment_column = Some(column);
+                previous_line = line as i64;
+                previous_column = column;
+            }
+            previous = Some(c);
+            column += c.len_utf16() as i64;
+        }
+    }
+    mappings
+}
+
+/// A source map that maps a runtime script onto the same lines and columns of
+/// `source`.
+fn identity_source_map(file: &str, source: &str, mappings: String) -> serde_json::Value {
+    serde_json::json!({
+        "version": 3,
+        "file": file,
+        "sources": [source],
+        "names": [],
+        "mappings": mappings
+    })
+}
+
+/// The text of a dumped script. VS Code asks for the source map as soon as the
+/// script is announced, which may be before the dumper has written it.
+async fn read_dumped_script(path: &std::path::Path) -> Option<String> {
+    for _ in 0..20 {
+        if let Ok(text) = tokio::fs::read_to_string(path).await {
+            return Some(text);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    None
+}
+
+async fn handle_source_map_request(
+    tail: warp::path::Tail,
+    query: HashMap<String, String>,
+    env: SharedEnv,
+    station: Arc<Station>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let Some(ref dump_dir) = station.dump_output else {
+        return Err(warp::reject::not_found());
+    };
+
+    let relative = urlencoding::decode(tail.as_str()).map_err(|_| warp::reject::not_found())?;
+    let source = dump_file_url(std::path::Path::new(dump_dir), &relative)
+        .ok_or_else(warp::reject::not_found)?;
+
+    env.log.verbose(&format!(
+        "{}[HTTP Proxy] Source map for {}",
+        station.prefix(),
+        relative
+    ));
+    // Without the dumped text, fall back to the first column of every line
+    let text = match source.to_file_path() {
+        Ok(path) => read_dumped_script(&path).await,
+        Err(_) => None,
     };
-    let mut shutdown_rx = match target_name.as_str() {
-        "Dynamics" => state_guard
-            .dynamics_clients_shutdown_tx
-            .as_ref()
-            .map(|tx| tx.subscribe()),
-        "Events" => state_guard
-            .events_clients_shutdown_tx
-            .as_ref()
-            .map(|tx| tx.subscribe()),
-        _ => None,
+    let mappings = match text {
+        Some(text) => token_mappings(&text),
+        None => line_mappings(query.get("lines").and_then(|l| l.parse().ok()).unwrap_or(1)),
     };
-    drop(state_guard);
+    let file = relative.rsplit('/').next().unwrap_or_default();
+    Ok(warp::reply::json(&identity_source_map(
+        file,
+        source.as_str(),
+        mappings,
+    )))
+}
 
-    if target_path.is_none() {
-        log_error(&format!(
-            "[{}] Client #{}: No target path available yet",
-            target_name_log, client_id
-        ));
-        return;
-    }
+// ============================================================================
+// WebSocket Proxy
+// ============================================================================
+
+/// Why a client's connection to its current upstream target ended.
+enum SessionEvent {
+    ClientClosed,
+    TargetClosed,
+    Retarget(String),
+    Shutdown(DisconnectReason),
+}
 
-    let target_path_str = target_path.unwrap();
-    let cfg = config();
+async fn connect_target(
+    log: &Logger,
+    station: &Station,
+    kind: &ContextKind,
+    target_path: &str,
+    label: &str,
+    client_id: u32,
+) -> Option<
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>,
+> {
     let target_url = format!(
         "ws://{}:{}/{}",
-        cfg.target_host, cfg.target_port, target_path_str
+        station.target_host, station.target_port, target_path
     );
 
     // Decode path for readable logging
-    let decoded_path = urlencoding::decode(&target_path_str)
-        .unwrap_or_else(|_| target_path_str.clone().into())
+    let decoded_path = urlencoding::decode(target_path)
+        .unwrap_or_else(|_| target_path.into())
         .into_owned();
 
-    log(&format!(
-        "[{}] Client #{}: Connecting to target: {}",
-        target_name_log, client_id, decoded_path
+    log.log(&format!(
+        "{} Client #{}: Connecting to target: {}",
+        label, client_id, decoded_path
     ));
 
-    // Connect to WinCC target
-    let (target_stream, _) = match tokio_tungstenite::connect_async(&target_url).await {
-        Ok(result) => result,
+    match tokio_tungstenite::connect_async(&target_url).await {
+        Ok((stream, _)) => {
+            log.event(ProxyEvent::ClientAttached {
+                origin: station.origin(kind),
+                client_id,
+                path: target_path.to_string(),
+            });
+            Some(stream)
+        }
         Err(e) => {
-            log_error(&format!(
-                "[{}] Client #{}: Failed to connect to target: {}",
-                target_name_log, client_id, e
-            ));
-            return;
+            log.event(ProxyEvent::ClientDisconnected {
+                origin: station.origin(kind),
+                client_id,
+                reason: DisconnectReason::TargetUnreachable(e.to_string()),
+            });
+            None
         }
+    }
+}
+
+/// Wait for the next broadcast signal. Returns `None` once the sender is gone,
+/// after which the receiver is dropped so the branch stays idle.
+async fn recv_signal<T: Clone>(rx: &mut Option<broadcast::Receiver<T>>) -> Option<T> {
+    let Some(receiver) = rx else {
+        return futures_util::future::pending().await;
     };
+    loop {
+        match receiver.recv().await {
+            Ok(value) => return Some(value),
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+    *rx = None;
+    None
+}
 
-    println!(
-        "{} {} [{}] Client #{}: Connected to target",
-        format!("[{}]", timestamp()).dimmed(),
-        "[CONN]".blue().bold(),
-        target_name_log,
-        client_id
-    );
+/// Restore the client's ids in replies the proxy sends on its own, dropping
+/// any that answer a request the client never sent.
+fn replies_for_client(ids: &mut IdMap, replies: Vec<String>) -> Vec<String> {
+    replies
+        .into_iter()
+        .filter_map(
+            |text| match serde_json::from_str::<serde_json::Value>(&text) {
+                Ok(mut parsed) => ids
+                    .restore_response(&mut parsed)
+                    .then(|| parsed.to_string()),
+                Err(_) => Some(text),
+            },
+        )
+        .collect()
+}
 
+/// Error responses for the client's requests that the old target will never
+/// answer, so the client does not wait for them forever.
+fn unanswered_requests(ids: &mut IdMap) -> Vec<String> {
+    ids.take_pending()
+        .into_iter()
+        .map(|id| {
+            serde_json::json!({
+                "id": id,
+                "error": { "code": -32000, "message": "Target changed before the request was answered" }
+            })
+            .to_string()
+        })
+        .collect()
+}
+
+/// Run a client message through id renumbering, URL translation and the
+/// session and breakpoint bookkeeping. Returns the messages to send to the
+/// target and the replies for the client.
+fn process_client_message(
+    text: &str,
+    ids: &mut IdMap,
+    urls: &Mutex<UrlMap>,
+    session: &mut Option<Session>,
+    breakpoints: &mut BreakpointSync,
+) -> (Vec<String>, Vec<String>) {
+    let Ok(mut parsed) = serde_json::from_str::<serde_json::Value>(text) else {
+        return (vec![text.to_string()], Vec::new());
+    };
+    let renumbered = ids.renumber_request(&mut parsed);
+    let text = if urls.lock().unwrap().rewrite_for_target(&mut parsed) || renumbered {
+        parsed.to_string()
+    } else {
+        text.to_string()
+    };
+    if let Some(session) = session {
+        session.record_client_message(&parsed);
+    }
+    let (mut to_target, to_client) = breakpoints.handle_client_message(&text, &parsed);
+    to_target.extend(breakpoints.after_sent(&parsed));
+    (to_target, replies_for_client(ids, to_client))
+}
+
+async fn handle_websocket(
+    ws: warp::ws::WebSocket,
+    host: Option<String>,
+    env: SharedEnv,
+    state: SharedState,
+    station: Arc<Station>,
+    kind: ContextKind,
+) {
+    let cfg = &env.cfg;
+    let client_id = rand::random::<u32>();
+    let target_name_log = format!("{}[{}]", station.prefix(), kind);
+    let origin = station.origin(&kind);
+    let fields = LogFields {
+        origin: Some(origin.clone()),
+        client_id: Some(client_id),
+        target: None,
+    };
+    let log = &env.log.scoped(fields.clone());
+    log.event(ProxyEvent::ClientConnected {
+        origin: origin.clone(),
+        client_id,
+    });
+
+    // Subscribe to shutdown and retarget signals
+    let state_guard = state.read().await;
+    let context = state_guard.contexts.get(&kind);
+    let target_path = context.and_then(|c| c.path.clone());
+    let mut shutdown_rx = context
+        .and_then(|c| c.clients_shutdown_tx.as_ref())
+        .map(|tx| tx.subscribe());
+    let mut retarget_rx = context
+        .and_then(|c| c.retarget_tx.as_ref())
+        .map(|tx| tx.subscribe());
+    let urls = context.map(|c| c.urls.clone()).unwrap_or_default();
+    let mut breakpoints =
+        BreakpointSync::new(context.map(|c| c.breakpoints.clone()).unwrap_or_default());
+    drop(state_guard);
+
+    let Some(mut target_path) = target_path else {
+        log.event(ProxyEvent::ClientDisconnected {
+            origin,
+            client_id,
+            reason: DisconnectReason::NoTarget,
+        });
+        return;
+    };
+
+    let keep_sessions = cfg.keep_sessions;
+    let url_style = match station.dump_output {
+        Some(ref dump_dir) if cfg.file_urls => UrlStyle::File {
+            dump_dir: dump_dir.into(),
+            context: kind.name().to_string(),
+            raw: cfg.dump_format == DumpFormat::Pretty,
+        },
+        _ if cfg.long_paths => UrlStyle::Long,
+        _ => UrlStyle::Short,
+    };
+    let port = station
+        .contexts
+        .iter()
+        .find(|c| c.kind == kind)
+        .map_or(0, |c| c.port);
+    let authority = client_authority(cfg, host, "127.0.0.1", port);
     let (mut client_tx, mut client_rx) = ws.split();
-    let (target_tx, mut target_rx) = target_stream.split();
-    let target_tx = Arc::new(tokio::sync::Mutex::new(target_tx));
-
-    let dump_output = config().dump_output.clone();
-
-    // Clone for each async block
-    let target_name_c2t = target_name_log.clone();
-    let target_name_t2c = target_name_log.clone();
-
-    // Forward messages from client to target
-    let target_tx_c2t = target_tx.clone();
-    let mut client_to_target = tokio::spawn(async move {
-        while let Some(Ok(msg)) = client_rx.next().await {
-            if let Ok(text) = msg.to_str() {
-                log_very_verbose(&format!(
-                    "[{}] Client #{}: Client -> Target ({} bytes)",
-                    target_name_c2t,
+    let mut dumper = station.dump_output.clone().map(|dir| {
+        ScriptDumper::new(dir, kind.name())
+            .with_format(cfg.dump_format)
+            .with_split(cfg.dump_split)
+            .with_git(cfg.dump_git)
+            .with_logger(log.clone())
+            .with_origin(origin.clone())
+    });
+    let mut session = keep_sessions.then(Session::new);
+    let mut ids = IdMap::new();
+    let mut queued: Vec<String> = Vec::new();
+
+    'session: loop {
+        let client_log = log;
+        let log = &client_log.scoped(LogFields {
+            target: Some(target_path.clone()),
+            ..fields.clone()
+        });
+        let Some(target_stream) = connect_target(
+            log,
+            &station,
+            &kind,
+            &target_path,
+            &target_name_log,
+            client_id,
+        )
+        .await
+        else {
+            break;
+        };
+        let (mut target_tx, mut target_rx) = target_stream.split();
+        breakpoints.reset_target();
+        if let Some(ref mut dumper) = dumper {
+            let vcs = state
+                .read()
+                .await
+                .contexts
+                .get(&kind)
+                .map_or(0, |c| c.highest_vcs);
+            dumper.set_target(&target_path, vcs);
+        }
+
+        // Bring the new target up to the state the client left the old one in
+        let mut to_target = Vec::new();
+        if let Some(ref mut session) = session {
+            let replay = session.replay_commands(&queued);
+            if !replay.is_empty() {
+                log.verbose(&format!(
+                    "{} Client #{}: Replaying {} commands",
+                    target_name_log,
                     client_id,
-                    text.len()
+                    replay.len()
                 ));
-
-                let mut tx = target_tx_c2t.lock().await;
-                if tx.send(Message::Text(text.to_string())).await.is_err() {
-                    break;
-                }
+            }
+            for text in replay {
+                let parsed = serde_json::from_str(&text).unwrap_or_default();
+                let restore = breakpoints.after_sent(&parsed);
+                to_target.push(text);
+                to_target.extend(restore);
+            }
+        }
+        let mut to_client = Vec::new();
+        for text in queued.drain(..) {
+            let (target, client) =
+                process_client_message(&text, &mut ids, &urls, &mut session, &mut breakpoints);
+            to_target.extend(target);
+            to_client.extend(client);
+        }
+        for text in to_target {
+            if target_tx.send(Message::Text(text)).await.is_err() {
+                break;
+            }
+        }
+        for text in to_client {
+            if client_tx.send(warp::ws::Message::text(text)).await.is_err() {
+                break 'session;
             }
         }
-    });
-
-    // Forward messages from target to client (with CDP rewriting + script dump)
-    let target_tx_t2c = target_tx.clone();
-    let mut target_to_client = tokio::spawn(async move {
-        let mut dumper = dump_output.map(|dir| ScriptDumper::new(dir, &target_name_t2c));
-
-        while let Some(Ok(msg)) = target_rx.next().await {
-            let Message::Text(text) = msg else { continue };
 
-            log_very_verbose(&format!(
-                "[{}] Client #{}: Target -> Client ({} bytes)",
-                target_name_t2c,
-                client_id,
-                text.len()
-            ));
+        let mut dump_retry = tokio::time::interval(Duration::from_secs(1));
+        let event = 'forward: loop {
+            tokio::select! {
+                msg = client_rx.next() => {
+                    let Some(Ok(msg)) = msg else { break SessionEvent::ClientClosed };
+                    let Ok(text) = msg.to_str() else { continue };
+
+                    log.very_verbose(&format!(
+                        "{} Client #{}: Client -> Target ({} bytes)",
+                        target_name_log,
+                        client_id,
+                        text.len()
+                    ));
+
+                    let (to_target, to_client) =
+                        process_client_message(text, &mut ids, &urls, &mut session, &mut breakpoints);
+                    for text in to_target {
+                        if target_tx.send(Message::Text(text)).await.is_err() {
+                            break 'forward SessionEvent::TargetClosed;
+                        }
+                    }
+                    for text in to_client {
+                        if client_tx.send(warp::ws::Message::text(text)).await.is_err() {
+                            break 'forward SessionEvent::ClientClosed;
+                        }
+                    }
+                }
+                msg = target_rx.next() => {
+                    let Some(Ok(msg)) = msg else { break SessionEvent::TargetClosed };
+                    let Message::Text(text) = msg else { continue };
+
+                    log.very_verbose(&format!(
+                        "{} Client #{}: Target -> Client ({} bytes)",
+                        target_name_log,
+                        client_id,
+                        text.len()
+                    ));
+
+                    let mut text = text;
+                    if let Ok(mut parsed) = serde_json::from_str::<serde_json::Value>(&text) {
+                        let mut changed = false;
+
+                        // --- Script dump interception ---
+                        if let Some(ref mut dumper) = dumper {
+                            if let Some(request) = dumper.handle_script_parsed(&parsed) {
+                                let _ = target_tx.send(Message::Text(request)).await;
+                                if !matches!(url_style, UrlStyle::File { .. }) {
+                                    changed |= dumper.attach_source_map(&mut parsed, &authority);
+                                }
+                            }
+                            if dumper.handle_response(&parsed) {
+                                if let Some(commit) = dumper.take_commit() {
+                                    commit.spawn();
+                                }
+                                continue;
+                            }
+                        }
+
+                        // --- Breakpoint restore ---
+                        if let Some(replies) = breakpoints.handle_target_message(&parsed) {
+                            for text in replies_for_client(&mut ids, replies) {
+                                if client_tx.send(warp::ws::Message::text(text)).await.is_err() {
+                                    break 'forward SessionEvent::ClientClosed;
+                                }
+                            }
+                            continue;
+                        }
+
+                        // --- Session tracking ---
+                        if let Some(ref mut session) = session {
+                            if session.consume_replay_response(&parsed) {
+                                continue;
+                            }
+                            session.observe_target_message(&parsed);
+                        }
+
+                        // --- Client id restoring ---
+                        if !ids.restore_response(&mut parsed) {
+                            log.verbose(&format!(
+                                "{} Client #{}: Dropped response to unknown request {}",
+                                target_name_log, client_id, parsed["id"]
+                            ));
+                            continue;
+                        }
+                        changed |= parsed.get("id").is_some();
+
+                        // --- Script URL rewriting ---
+                        // Quick bailout: most messages carry no script URL
+                        if text.contains("\"url\"") {
+                            changed |= urls.lock().unwrap().rewrite_for_client(&mut parsed, &url_style);
+                        }
+                        if changed {
+                            text = parsed.to_string();
+                        }
+                    }
 
-            // --- Script dump interception ---
-            if let Some(ref mut dumper) = dumper {
-                if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(&text) {
-                    if let Some(request) = dumper.handle_script_parsed(&parsed) {
-                        let mut tx = target_tx_t2c.lock().await;
-                        let _ = tx.send(Message::Text(request)).await;
+                    if client_tx.send(warp::ws::Message::text(text)).await.is_err() {
+                        break SessionEvent::ClientClosed;
+                    }
+                }
+                _ = dump_retry.tick(), if dumper.is_some() => {
+                    let requests = dumper.as_mut().map(|d| d.poll()).unwrap_or_default();
+                    if let Some(commit) = dumper.as_mut().and_then(|d| d.take_commit()) {
+                        commit.spawn();
                     }
-                    if dumper.handle_response(&parsed) {
-                        continue;
+                    for text in requests {
+                        if target_tx.send(Message::Text(text)).await.is_err() {
+                            break 'forward SessionEvent::TargetClosed;
+                        }
                     }
                 }
+                path = recv_signal(&mut retarget_rx) => {
+                    if let Some(path) = path {
+                        break SessionEvent::Retarget(path);
+                    }
+                }
+                reason = recv_signal(&mut shutdown_rx) => {
+                    break SessionEvent::Shutdown(reason.unwrap_or(DisconnectReason::ProxyStopped));
+                }
             }
+        };
 
-            // --- Existing CDP rewriting ---
-            let text = maybe_rewrite_cdp_message(&text);
-
-            if client_tx.send(warp::ws::Message::text(text)).await.is_err() {
-                break;
+        // The old target will not answer what is still outstanding
+        if matches!(event, SessionEvent::Retarget(_))
+            || (keep_sessions && matches!(event, SessionEvent::TargetClosed))
+        {
+            for text in unanswered_requests(&mut ids) {
+                let _ = client_tx.send(warp::ws::Message::text(text)).await;
             }
         }
 
-        if let Some(dumper) = dumper {
-            if dumper.count > 0 {
-                log(&format!(
-                    "[{}] Client #{}: Dumped {} scripts",
-                    target_name_t2c, client_id, dumper.count
+        let event = match event {
+            // The runtime went away; hold on to the client until the poller
+            // finds the replacement target
+            SessionEvent::TargetClosed if keep_sessions => {
+                log.log(&format!(
+                    "{} Client #{}: Target closed, waiting for a new target...",
+                    target_name_log, client_id
                 ));
+                loop {
+                    tokio::select! {
+                            msg = client_rx.next() => {
+                                let Some(Ok(msg)) = msg else { break SessionEvent::ClientClosed };
+                                if let Ok(text) = msg.to_str() {
+                                    queued.push(text.to_string());
+                                }
+                            }
+                            path = recv_signal(&mut retarget_rx) => {
+                                if let Some(path) = path {
+                                    break SessionEvent::Retarget(path);
+                                }
+                            }
+                            reason = recv_signal(&mut shutdown_rx) => {
+                        break SessionEvent::Shutdown(reason.unwrap_or(DisconnectReason::ProxyStopped));
+                    }
+                        }
+                }
             }
-        }
-    });
+            event => event,
+        };
 
-    // Wait for either direction to close OR shutdown signal
-    tokio::select! {
-        _ = &mut client_to_target => {
-            println!(
-                "{} {} [{}] Client #{} disconnected (client closed)",
-                format!("[{}]", timestamp()).dimmed(),
-                "[DISC]".magenta().bold(),
-                target_name_log,
-                client_id
-            );
-            target_to_client.abort();
-        },
-        _ = &mut target_to_client => {
-            println!(
-                "{} {} [{}] Client #{} disconnected (target closed)",
-                format!("[{}]", timestamp()).dimmed(),
-                "[DISC]".magenta().bold(),
-                target_name_log,
-                client_id
-            );
-            client_to_target.abort();
-        },
-        _ = async {
-            if let Some(rx) = &mut shutdown_rx {
-                let _ = rx.recv().await;
-            } else {
-                futures_util::future::pending::<()>().await;
+        let reason = match event {
+            SessionEvent::ClientClosed => DisconnectReason::ClientClosed,
+            SessionEvent::TargetClosed => DisconnectReason::TargetClosed,
+            SessionEvent::Shutdown(reason) => reason,
+            SessionEvent::Retarget(path) => {
+                log.event(ProxyEvent::ClientRetargeted {
+                    origin: origin.clone(),
+                    client_id,
+                    path: path.clone(),
+                });
+                if let Some(ref mut session) = session {
+                    for event in session.detach_events() {
+                        if client_tx
+                            .send(warp::ws::Message::text(event))
+                            .await
+                            .is_err()
+                        {
+                            break 'session;
+                        }
+                    }
+                }
+                target_path = path;
+                continue;
             }
-        } => {
-            println!(
-                "{} {} [{}] Client #{}: Closing due to target change",
-                format!("[{}]", timestamp()).dimmed(),
-                "[STOP]".magenta().bold(),
-                target_name_log,
-                client_id
-            );
-            // Abort both forwarding tasks to force close the connections
-            client_to_target.abort();
-            target_to_client.abort();
-        },
+        };
+        log.event(ProxyEvent::ClientDisconnected {
+            origin: origin.clone(),
+            client_id,
+            reason,
+        });
+        break;
+    }
+
+    let dropped = breakpoints.finish();
+    if dropped > 0 {
+        log.verbose(&format!(
+            "{} Client #{}: Dropped {} restored breakpoint(s) the client no longer has",
+            target_name_log, client_id, dropped
+        ));
+    }
+
+    if let Some(mut dumper) = dumper {
+        let stats = dumper.finish();
+        if let Some(commit) = dumper.take_commit() {
+            commit.spawn();
+        }
+        if stats.written + stats.failed + stats.dropped > 0 {
+            log.event(ProxyEvent::DumpFinished {
+                origin,
+                client_id,
+                stats,
+            });
+        }
     }
 }
 
 fn create_http_server(
+    env: SharedEnv,
     state: SharedState,
-    port: u16,
-    target_name: String,
+    station: Arc<Station>,
+    spec: ContextSpec,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let env_filter = warp::any().map(move || env.clone());
     let state_filter = warp::any().map(move || state.clone());
-    let target_filter = warp::any().map(move || target_name.clone());
+    let station_filter = warp::any().map(move || station.clone());
+    let kind = spec.kind.clone();
+    let spec_filter = warp::any().map(move || spec.clone());
+    let kind_filter = warp::any().map(move || kind.clone());
 
     // /json endpoint
     let json_route = warp::path("json")
         .and(warp::path::end())
-        .and(state_filter.clone())
-        .and(warp::any().map(move || port))
-        .and(target_filter.clone())
+        .and(warp::header::optional::<String>("host"))
+        .and(env_filter.clone())
+        .and(station_filter.clone())
+        .and(spec_filter.clone())
         .and_then(handle_json_request);
 
     // /json/list endpoint (same as /json)
     let json_list_route = warp::path!("json" / "list")
-        .and(state_filter.clone())
-        .and(warp::any().map(move || port))
-        .and(target_filter.clone())
+        .and(warp::header::optional::<String>("host"))
+        .and(env_filter.clone())
+        .and(station_filter.clone())
+        .and(spec_filter)
         .and_then(handle_json_request);
 
     // /json/version endpoint
-    let version_route = warp::path!("json" / "version").and_then(handle_version_request);
+    let version_route = warp::path!("json" / "version")
+        .and(env_filter.clone())
+        .and(station_filter.clone())
+        .and_then(handle_version_request);
+
+    // /sourcemap/<dumped file> endpoint
+    let source_map_route = warp::path("sourcemap")
+        .and(warp::path::tail())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(env_filter.clone())
+        .and(station_filter.clone())
+        .and_then(handle_source_map_request);
 
     // WebSocket upgrade
     let ws_route = warp::path::end()
         .and(warp::ws())
+        .and(warp::header::optional::<String>("host"))
+        .and(env_filter)
         .and(state_filter)
-        .and(target_filter)
-        .map(|ws: warp::ws::Ws, state, name| {
-            ws.on_upgrade(move |socket| handle_websocket(socket, state, name))
+        .and(station_filter)
+        .and(kind_filter)
+        .map(|ws: warp::ws::Ws, host, env, state, station, kind| {
+            ws.on_upgrade(move |socket| handle_websocket(socket, host, env, state, station, kind))
         });
 
     json_route
         .or(json_list_route)
         .or(version_route)
+        .or(source_map_route)
         .or(ws_route)
 }
 
@@ -973,292 +1351,281 @@ fn create_http_server(
 // Server Management
 // ============================================================================
 
-async fn start_dynamics_server(state: SharedState) {
-    let cfg = config();
-    let dynamics_port = cfg.dynamics_port;
+/// Start a context's server on the configured address. Fails if the port
+/// can't be bound, e.g. because another proxy is already running.
+async fn start_server(
+    env: SharedEnv,
+    state: SharedState,
+    station: Arc<Station>,
+    spec: ContextSpec,
+) -> Result<()> {
+    let prefix = station.prefix();
+    let kind = spec.kind.clone();
+    let port = spec.port;
+    let addr = std::net::SocketAddr::new(env.cfg.bind_address, port);
+    let log = env.log.scoped(LogFields {
+        origin: Some(station.origin(&kind)),
+        ..Default::default()
+    });
 
-    // Create broadcast channel for clients (capacity of 10 receivers)
+    // Create broadcast channels for clients (capacity of 10 receivers)
     let (clients_shutdown_tx, _) = broadcast::channel(10);
+    let (retarget_tx, _) = broadcast::channel(10);
 
     // Create oneshot channel for server graceful shutdown
     let (server_shutdown_tx, server_shutdown_rx) = tokio::sync::oneshot::channel();
 
-    // Create oneshot channel to signal server is ready
-    let (ready_tx, ready_rx) = tokio::sync::oneshot::channel();
-
-    let dynamics_state = state.clone();
-    let dynamics_server = create_http_server(dynamics_state, dynamics_port, "Dynamics".to_string());
+    let server = create_http_server(env.clone(), state.clone(), station, spec);
+    let (_, server) = warp::serve(server)
+        .try_bind_with_graceful_shutdown(addr, async move {
+            server_shutdown_rx.await.ok();
+        })
+        .with_context(|| format!("Cannot listen on {} for {}{}", addr, prefix, kind))?;
 
+    let stopped_label = format!("{}{}", prefix, kind);
+    let stopped_log = log.clone();
     let server_handle = tokio::spawn(async move {
-        let (_, server) = warp::serve(dynamics_server).bind_with_graceful_shutdown(
-            ([127, 0, 0, 1], dynamics_port),
-            async move {
-                server_shutdown_rx.await.ok();
-            },
-        );
-
-        // Signal that server is ready
-        let _ = ready_tx.send(());
-
         server.await;
-        log_success("Dynamics proxy server stopped");
+        stopped_log.success(&format!("{} proxy server stopped", stopped_label));
     });
 
     // Store shutdown senders and server handle in state
     {
         let mut state_guard = state.write().await;
-        state_guard.dynamics_clients_shutdown_tx = Some(clients_shutdown_tx);
-        state_guard.dynamics_server_shutdown_tx = Some(server_shutdown_tx);
-        state_guard.dynamics_server_handle = Some(server_handle);
+        let context = state_guard.context(&kind);
+        context.clients_shutdown_tx = Some(clients_shutdown_tx);
+        context.retarget_tx = Some(retarget_tx);
+        context.server_shutdown_tx = Some(server_shutdown_tx);
+        context.server_handle = Some(server_handle);
     }
 
-    // Wait for server to be ready before returning
-    let _ = ready_rx.await;
-
-    log_success(&format!("Dynamics proxy ready on port {}", dynamics_port));
+    log.success(&format!("{}{} proxy ready on port {}", prefix, kind, port));
+    Ok(())
 }
 
-async fn start_events_server(state: SharedState) {
-    let cfg = config();
-    let events_port = cfg.events_port;
-
-    // Create broadcast channel for clients (capacity of 10 receivers)
-    let (clients_shutdown_tx, _) = broadcast::channel(10);
-
-    // Create oneshot channel for server graceful shutdown
-    let (server_shutdown_tx, server_shutdown_rx) = tokio::sync::oneshot::channel();
-
-    // Create oneshot channel to signal server is ready
-    let (ready_tx, ready_rx) = tokio::sync::oneshot::channel();
-
-    let events_state = state.clone();
-    let events_server = create_http_server(events_state, events_port, "Events".to_string());
+/// Disconnect a station's clients and stop its servers.
+async fn stop_servers(state: &SharedState) {
+    let contexts: Vec<_> = state
+        .write()
+        .await
+        .contexts
+        .values_mut()
+        .map(|c| {
+            (
+                c.clients_shutdown_tx.take(),
+                c.server_shutdown_tx.take(),
+                c.server_handle.take(),
+            )
+        })
+        .collect();
+
+    for (clients_shutdown_tx, _, _) in &contexts {
+        if let Some(tx) = clients_shutdown_tx {
+            let _ = tx.send(DisconnectReason::ProxyStopped);
+        }
+    }
+    // Give clients a moment to close cleanly
+    tokio::time::sleep(Duration::from_millis(200)).await;
 
-    let server_handle = tokio::spawn(async move {
-        let (_, server) = warp::serve(events_server).bind_with_graceful_shutdown(
-            ([127, 0, 0, 1], events_port),
-            async move {
-                server_shutdown_rx.await.ok();
-            },
-        );
+    for (_, server_shutdown_tx, server_handle) in contexts {
+        if let Some(tx) = server_shutdown_tx {
+            let _ = tx.send(());
+        }
+        if let Some(handle) = server_handle {
+            let _ = handle.await;
+        }
+    }
+}
 
-        // Signal that server is ready
-        let _ = ready_tx.send(());
+/// Poll one station's targets until the proxy shuts down. Stations are polled
+/// independently, so a slow or unreachable runtime doesn't hold up the others.
+///
+/// Waiting stops as soon as `stop` is set, but a target update always runs
+/// to the end: a server it restarts is in `state` before the caller stops
+/// the servers.
+async fn poll_station(
+    env: SharedEnv,
+    state: SharedState,
+    station: Arc<Station>,
+    mut stop: watch::Receiver<bool>,
+) {
+    // Wait for target to be reachable before fetching /json
+    tokio::select! {
+        _ = wait_for_target_connectivity(&env, &station) => {}
+        _ = stop_requested(&mut stop) => return,
+    }
 
-        server.await;
-        log_success("Events proxy server stopped");
-    });
+    // Initial target fetch (after startup messages)
+    update_targets(env.clone(), state.clone(), station.clone()).await;
 
-    // Store shutdown senders and server handle in state
-    {
-        let mut state_guard = state.write().await;
-        state_guard.events_clients_shutdown_tx = Some(clients_shutdown_tx);
-        state_guard.events_server_shutdown_tx = Some(server_shutdown_tx);
-        state_guard.events_server_handle = Some(server_handle);
+    let mut interval_timer = tokio::time::interval(Duration::from_secs(env.cfg.poll_interval));
+    loop {
+        tokio::select! {
+            _ = interval_timer.tick() => {}
+            _ = stop_requested(&mut stop) => return,
+        }
+        update_targets(env.clone(), state.clone(), station.clone()).await;
     }
+}
 
-    // Wait for server to be ready before returning
-    let _ = ready_rx.await;
-
-    log_success(&format!("Events proxy ready on port {}", events_port));
+/// Resolves once `stop` is set. Never resolves if the sender is dropped
+/// without it, since a dropped `ProxyHandle` leaves the proxy running.
+async fn stop_requested(stop: &mut watch::Receiver<bool>) {
+    if stop.wait_for(|stop| *stop).await.is_err() {
+        futures_util::future::pending::<()>().await;
+    }
 }
 
 // ============================================================================
 // Public Entry Point
 // ============================================================================
 
-fn clean_dump_scripts(dump_dir: &str) {
-    for subdir in ["Dynamics", "Events"] {
-        let path = std::path::Path::new(dump_dir).join(subdir);
-        if path.exists() {
-            let _ = std::fs::remove_dir_all(&path);
+/// Events kept for subscribers that fall behind; older ones are dropped.
+const EVENT_CAPACITY: usize = 1024;
+
+/// A debug proxy for every station of a configuration, before it is started.
+///
+/// The proxy prints nothing itself. Everything it has to say arrives as a
+/// `ProxyEvent` at each subscriber; subscribe before `start` to see the
+/// startup messages too. Verbose records are sent only if the configured
+/// console shows them. A log file given to `with_log_file` is written by the
+/// proxy itself, so a subscriber falling behind can't leave gaps in it.
+pub struct Proxy {
+    cfg: Configuration,
+    events: broadcast::Sender<ProxyEvent>,
+    log_file: Option<Arc<LogFile>>,
+}
+
+impl Proxy {
+    pub fn new(cfg: Configuration) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        Self {
+            cfg,
+            events,
+            log_file: None,
         }
     }
-}
 
-pub async fn run_proxy() {
-    let cfg = config();
+    /// Write every event to `file` as well, at the file's own verbosity.
+    pub fn with_log_file(mut self, file: Arc<LogFile>) -> Self {
+        self.log_file = Some(file);
+        self
+    }
 
-    println!(
-        "{} {} Starting WinCC Debug Proxy...",
-        format!("[{}]", timestamp()).dimmed(),
-        "[START]".cyan().bold()
-    );
+    pub fn subscribe(&self) -> broadcast::Receiver<ProxyEvent> {
+        self.events.subscribe()
+    }
 
-    let state = Arc::new(RwLock::new(AppState::new()));
+    /// Clean the dump directories, start every context's server and begin
+    /// polling the targets. Sends `ProxyEvent::Ready` once every server
+    /// listens. If a server can't be started, the ones already running are
+    /// stopped again and the error is returned.
+    pub async fn start(self) -> Result<ProxyHandle> {
+        let verbosity = Verbosity {
+            verbose: self.cfg.verbose,
+            very_verbose: self.cfg.very_verbose,
+        };
+        let env = Arc::new(Env {
+            log: Logger::events(self.events.clone(), verbosity, self.log_file),
+            cfg: self.cfg,
+        });
+        env.log.tagged(Tag::Start, "Starting WinCC Debug Proxy...");
+        if !env.cfg.bind_address.is_loopback() {
+            env.log.warn(&format!(
+                "Listening on {} without authentication: anyone who can reach it can run code in the runtime",
+                env.cfg.bind_address
+            ));
+        }
 
-    // Start servers
-    start_dynamics_server(state.clone()).await;
-    start_events_server(state.clone()).await;
+        let mut stations = Vec::new();
+        for station in &env.cfg.stations {
+            let station = Arc::new(station.clone());
+            clean_dump_scripts(&station, env.cfg.dump_history, &env.log);
+            stations.push((
+                Arc::new(RwLock::new(AppState::new(env.log.clone()))),
+                station,
+            ));
+        }
 
-    println!(
-        "{} {} WinCC Debug Proxy is running!",
-        format!("[{}]", timestamp()).dimmed(),
-        "[READY]".green().bold()
-    );
-    println!();
-    println!("{}", "Configuration:".cyan().bold());
-    println!(
-        "   Target:        {}:{}",
-        cfg.target_host, cfg.target_port
-    );
-    println!("   Dynamics:      localhost:{}", cfg.dynamics_port);
-    println!("   Events:        localhost:{}", cfg.events_port);
-    println!("   Poll interval: {}s", cfg.poll_interval);
-    println!();
-    println!("{}", "VS Code launch.json ports:".cyan().bold());
-    println!("   Dynamics: {}", cfg.dynamics_port);
-    println!("   Events:   {}", cfg.events_port);
-    println!();
-    println!("{}", "Features:".cyan().bold());
-    println!("   {} Server restarts when targets change", "[+]".green());
-    println!("   {} Forces VS Code debugger reconnect", "[+]".green());
-    println!("   {} No manual intervention needed!", "[+]".green());
-    println!("   {} Separate debug sessions for Dynamics & Events", "[+]".green());
-    println!("   {} Script path shortening: {}", "[+]".green(),
-        if cfg.long_paths { "off (showing full paths)" } else { "on" });
-    // Track whether we need to run npm install after the banner
-    let mut npm_install_dir: Option<String> = None;
-
-    if let Some(ref dump_dir) = cfg.dump_output {
-        clean_dump_scripts(dump_dir);
-
-        println!("   {} Continuous script dump -> {}/", "[+]".green(), dump_dir);
-
-        if let Some(ref version) = cfg.styleguide_version {
-            match crate::styleguide::write_styleguide(version, dump_dir) {
-                Ok(_) => {
-                    println!("   {} Styleguide ({}) written to {}/", "[+]".green(), version, dump_dir);
-                    npm_install_dir = Some(dump_dir.to_string());
-                }
-                Err(e) => {
-                    log_error(&format!("Failed to write styleguide: {}", e));
+        let mut handle = ProxyHandle {
+            env,
+            events: self.events,
+            stations,
+            stop: watch::channel(false).0,
+            pollers: Vec::new(),
+        };
+        for (state, station) in &handle.stations {
+            for spec in &station.contexts {
+                if let Err(e) = start_server(
+                    handle.env.clone(),
+                    state.clone(),
+                    station.clone(),
+                    spec.clone(),
+                )
+                .await
+                {
+                    for (state, _) in &handle.stations {
+                        stop_servers(state).await;
+                    }
+                    return Err(e);
                 }
             }
         }
-        println!();
-        println!("   Start a VS Code debug session to trigger script dumping.");
-    }
-    println!();
-    println!("Press {} to stop", "Ctrl+C".yellow().bold());
-    println!();
-
-    // Run npm install in the background after the banner is fully printed
-    if let Some(dump_dir_owned) = npm_install_dir {
-        tokio::spawn(async move {
-            log("Running npm install...");
-            match tokio::task::spawn_blocking(move || {
-                std::process::Command::new("cmd")
-                    .args(["/C", "npm", "install"])
-                    .current_dir(&dump_dir_owned)
-                    .stdout(std::process::Stdio::null())
-                    .stderr(std::process::Stdio::piped())
-                    .status()
-            }).await {
-                Ok(Ok(status)) if status.success() => {
-                    log_success("npm install completed — ESLint ready");
-                }
-                Ok(Ok(_)) => {
-                    log_warn("npm install failed — run it manually in the dump directory");
-                }
-                Ok(Err(e)) => {
-                    log_warn(&format!("Could not run npm install: {}", e));
-                }
-                Err(e) => {
-                    log_warn(&format!("npm install task failed: {}", e));
-                }
-            }
-        });
-    }
+        handle.env.log.event(ProxyEvent::Ready);
 
-    // Wait for target to be reachable before fetching /json
-    wait_for_target_connectivity().await;
+        // Start target polling, one task per station
+        for (state, station) in &handle.stations {
+            let stop = handle.stop.subscribe();
+            let poller = poll_station(handle.env.clone(), state.clone(), station.clone(), stop);
+            handle.pollers.push(tokio::spawn(poller));
+        }
+        Ok(handle)
+    }
+}
 
-    // Initial target fetch (after startup messages)
-    update_targets(state.clone()).await;
+/// A running proxy. Dropping the handle leaves the proxy running until the
+/// runtime shuts down; `shutdown` stops it.
+pub struct ProxyHandle {
+    env: SharedEnv,
+    events: broadcast::Sender<ProxyEvent>,
+    stations: Vec<(SharedState, Arc<Station>)>,
+    /// Set once to stop the pollers
+    stop: watch::Sender<bool>,
+    pollers: Vec<tokio::task::JoinHandle<()>>,
+}
 
-    // Start target polling
-    let poll_state = state.clone();
-    let poll_interval = cfg.poll_interval;
-    tokio::spawn(async move {
-        let mut interval_timer = tokio::time::interval(Duration::from_secs(poll_interval));
+impl ProxyHandle {
+    pub fn subscribe(&self) -> broadcast::Receiver<ProxyEvent> {
+        self.events.subscribe()
+    }
 
-        loop {
-            interval_timer.tick().await;
-            update_targets(poll_state.clone()).await;
+    /// Stop polling, disconnect every client and stop every server. Sends
+    /// `ProxyEvent::Stopped` once the ports are free again.
+    pub async fn shutdown(self) {
+        self.env.log.tagged(Tag::Stop, "Shutting down...");
+        // Let a target update in progress finish, so no server it restarts is missed
+        self.stop.send_replace(true);
+        for poller in self.pollers {
+            let _ = poller.await;
         }
-    });
-
-    // Keep running forever
-    tokio::signal::ctrl_c().await.unwrap();
-    println!(
-        "{} {} Shutting down...",
-        format!("[{}]", timestamp()).dimmed(),
-        "[STOP]".magenta().bold()
-    );
-    std::process::exit(0);
+        for (state, _) in &self.stations {
+            stop_servers(state).await;
+        }
+        self.env.log.event(ProxyEvent::Stopped);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    // ================================================================
-    // shorten_script_url
-    // ================================================================
-
-    #[test]
-    fn shorten_full_faceplate_path() {
-        let url = "/screen_modules/Screen_Content/HMI_RT_1::HMI_Screen/faceplate_modules/CM_Freq/Events.js";
-        assert_eq!(
-            shorten_script_url(url),
-            Some("HMI_Screen/CM_Freq/Events.js".to_string())
-        );
-    }
-
-    #[test]
-    fn shorten_single_colon_path() {
-        let url = "/screen_modules/Screen_Content/HMI_RT_1:HMI_Screen/Dynamics.js";
-        assert_eq!(
-            shorten_script_url(url),
-            Some("HMI_Screen/Dynamics.js".to_string())
-        );
-    }
-
-    #[test]
-    fn shorten_no_faceplate_modules() {
-        let url = "/screen_modules/Screen_Content/HMI_RT_1::HMI_Screen/Dynamics.js";
-        assert_eq!(
-            shorten_script_url(url),
-            Some("HMI_Screen/Dynamics.js".to_string())
-        );
-    }
-
-    #[test]
-    fn shorten_no_leading_slash() {
-        let url = "screen_modules/Screen_Content/HMI_RT_1::HMI_Screen/Events.js";
-        assert_eq!(
-            shorten_script_url(url),
-            Some("HMI_Screen/Events.js".to_string())
-        );
-    }
-
-    #[test]
-    fn shorten_unrelated_url_returns_none() {
-        assert_eq!(shorten_script_url("https://example.com/foo.js"), None);
-        assert_eq!(shorten_script_url("eval-123.cdp"), None);
-    }
-
     // ================================================================
     // extract_vcs_number
     // ================================================================
 
     #[test]
     fn extract_vcs_from_typical_title() {
-        assert_eq!(
-            extract_vcs_number(" @localhost VCS_8 Dynamics"),
-            Some(8)
-        );
+        assert_eq!(extract_vcs_number(" @localhost VCS_8 Dynamics"), Some(8));
     }
 
     #[test]
@@ -1289,14 +1656,16 @@ mod tests {
 
     #[test]
     fn check_target_change_result_none() {
-        let change = check_target_change(None, &Some("old".into()), "Dynamics", 0);
+        let change =
+            check_target_change(&Logger::default(), None, &Some("old".into()), "Dynamics", 0);
         assert!(matches!(change, TargetChange::None { vcs: 0 }));
     }
 
     #[test]
     fn check_target_change_initial_discovery() {
         let target = make_target("ws://host/abc123", "VCS_5 Dynamics");
-        let change = check_target_change(Some((target, 5)), &None, "Dynamics", 1);
+        let change =
+            check_target_change(&Logger::default(), Some((target, 5)), &None, "Dynamics", 1);
         assert!(matches!(change, TargetChange::Initial { ref path, vcs: 5 } if path == "abc123"));
     }
 
@@ -1304,6 +1673,7 @@ mod tests {
     fn check_target_change_path_changed() {
         let target = make_target("ws://host/new_path", "VCS_6 Dynamics");
         let change = check_target_change(
+            &Logger::default(),
             Some((target, 6)),
             &Some("old_path".into()),
             "Dynamics",
@@ -1318,6 +1688,7 @@ mod tests {
     fn check_target_change_same_path() {
         let target = make_target("ws://host/same_path", "VCS_7 Dynamics");
         let change = check_target_change(
+            &Logger::default(),
             Some((target, 7)),
             &Some("same_path".into()),
             "Dynamics",
@@ -1327,98 +1698,546 @@ mod tests {
     }
 
     // ================================================================
-    // ScriptDumper::handle_script_parsed
+    // identity_source_map
     // ================================================================
 
-    fn make_dumper() -> ScriptDumper {
-        ScriptDumper::new("_test_dump".to_string(), "Dynamics")
+    #[test]
+    fn identity_source_map_maps_each_line() {
+        let map = identity_source_map("Events.js", "file:///dump/Events.js", line_mappings(3));
+        assert_eq!(map["mappings"], "AAAA;AACA;AACA");
+        assert_eq!(map["sources"][0], "file:///dump/Events.js");
     }
 
     #[test]
-    fn handle_script_parsed_valid_event() {
-        let mut dumper = make_dumper();
-        let event = serde_json::json!({
-            "method": "Debugger.scriptParsed",
-            "params": {
-                "scriptId": "42",
-                "url": "/screen_modules/Screen_Content/HMI_RT_1::HMI_Screen/Dynamics.js"
-            }
-        });
-        let result = dumper.handle_script_parsed(&event);
-        assert!(result.is_some());
-        let req: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
-        assert_eq!(req["method"], "Debugger.getScriptSource");
-        assert_eq!(req["params"]["scriptId"], "42");
-        assert_eq!(req["id"], 900_000);
+    fn line_mappings_are_capped() {
+        assert_eq!(line_mappings(0), "");
+        assert_eq!(line_mappings(1), "AAAA");
+        let capped = line_mappings(4_000_000_000);
+        assert_eq!(capped.split(';').count(), MAX_SOURCE_MAP_LINES);
     }
 
     #[test]
-    fn handle_script_parsed_eval_cdp_skipped() {
-        let mut dumper = make_dumper();
-        let event = serde_json::json!({
-            "method": "Debugger.scriptParsed",
-            "params": { "scriptId": "99", "url": "eval-abc.cdp" }
-        });
-        assert!(dumper.handle_script_parsed(&event).is_none());
+    fn token_mappings_map_every_token_onto_its_own_column() {
+        // Tokens at columns 0, 3, 4, 5 and 6; then from column 2 after an empty line
+        assert_eq!(
+            token_mappings("foo(a);\n\n  x = 'é';"),
+            "AAAA,GAAG,CAAC,CAAC,CAAC;;EAEJ,EAAE,EAAE,CAAC,CAAC,CAAC"
+        );
+        assert_eq!(token_mappings("a\nb"), "AAAA;AACA");
+        assert_eq!(token_mappings(""), "");
     }
 
-    #[test]
-    fn handle_script_parsed_empty_url_skipped() {
-        let mut dumper = make_dumper();
-        let event = serde_json::json!({
-            "method": "Debugger.scriptParsed",
-            "params": { "scriptId": "99", "url": "" }
+    // ================================================================
+    // Against a mock WinCC server
+    // ================================================================
+
+    use crate::mock::{MOCK_NO_REPLY, MockTarget, MockWinCC, wait_for};
+
+    type ClientStream = tokio_tungstenite::WebSocketStream<
+        tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+    >;
+
+    const DYNAMICS_URL: &str = "/screen_modules/Screen_Content/HMI_RT_1::Main/Dynamics.js";
+
+    fn dynamics_target(path: &str, vcs: u32) -> MockTarget {
+        MockTarget::new(path, &format!(" @localhost VCS_{} Dynamics", vcs)).with_script(
+            "11",
+            DYNAMICS_URL,
+            "export function Text_1_Text(item) {\n    return 1;\n}\n",
+        )
+    }
+
+    fn events_target(path: &str, vcs: u32) -> MockTarget {
+        MockTarget::new(path, &format!(" @localhost VCS_{} Events", vcs))
+    }
+
+    fn test_env() -> SharedEnv {
+        Arc::new(Env {
+            cfg: Configuration::default(),
+            log: Logger::default(),
+        })
+    }
+
+    /// A proxy for `station` with all servers running and targets polled once.
+    async fn start_proxy(station: &Arc<Station>) -> SharedState {
+        start_proxy_in(test_env(), station).await
+    }
+
+    async fn start_proxy_in(env: SharedEnv, station: &Arc<Station>) -> SharedState {
+        let state = Arc::new(RwLock::new(AppState::new(Logger::default())));
+        for spec in &station.contexts {
+            start_server(env.clone(), state.clone(), station.clone(), spec.clone())
+                .await
+                .unwrap();
+        }
+        update_targets(env, state.clone(), station.clone()).await;
+        state
+    }
+
+    async fn context_path(state: &SharedState, kind: &ContextKind) -> Option<String> {
+        state
+            .read()
+            .await
+            .contexts
+            .get(kind)
+            .and_then(|c| c.path.clone())
+    }
+
+    async fn connect_client(port: u16) -> ClientStream {
+        let (stream, _) = tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{}/", port))
+            .await
+            .expect("proxy accepts clients");
+        stream
+    }
+
+    /// Send a request and collect everything the client receives up to its response.
+    async fn request(client: &mut ClientStream, id: u64, method: &str) -> Vec<serde_json::Value> {
+        let text = serde_json::json!({ "id": id, "method": method }).to_string();
+        client.send(Message::Text(text)).await.unwrap();
+        let mut received = Vec::new();
+        loop {
+            let msg = tokio::time::timeout(Duration::from_secs(10), client.next())
+                .await
+                .expect("response in time")
+                .expect("connection open")
+                .unwrap();
+            let Message::Text(text) = msg else { continue };
+            let parsed: serde_json::Value = serde_json::from_str(&text).unwrap();
+            let done = parsed["id"] == id;
+            received.push(parsed);
+            if done {
+                return received;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn json_lists_only_each_contexts_targets() {
+        let mock = MockWinCC::start(vec![
+            dynamics_target("dyn1", 1),
+            events_target("ev1", 1),
+            MockTarget::new("task1", " @localhost Scheduler"),
+        ])
+        .await;
+        let station = Arc::new(mock.station(None));
+        let _state = start_proxy(&station).await;
+
+        for (spec, title) in station
+            .contexts
+            .iter()
+            .zip(["Dynamics", "Events", "Scheduler"])
+        {
+            let url = format!("http://127.0.0.1:{}/json", spec.port);
+            let targets: Vec<DebugTarget> = reqwest::get(&url).await.unwrap().json().await.unwrap();
+            assert_eq!(targets.len(), 1, "{}", spec.kind);
+            assert!(targets[0].title.ends_with(title));
+            assert_eq!(
+                targets[0].web_socket_debugger_url,
+                format!("ws://localhost:{}", spec.port)
+            );
+        }
+
+        let url = format!("http://127.0.0.1:{}/json/version", station.contexts[0].port);
+        let version = reqwest::get(&url).await.unwrap().text().await.unwrap();
+        assert!(version.contains("Protocol-Version"));
+    }
+
+    #[tokio::test]
+    async fn update_targets_follows_highest_vcs_and_survives_outages() {
+        let mock =
+            MockWinCC::start(vec![dynamics_target("dyn1", 1), dynamics_target("dyn3", 3)]).await;
+        let station = Arc::new(mock.station(None));
+        let state = start_proxy(&station).await;
+        assert_eq!(
+            context_path(&state, &ContextKind::Dynamics)
+                .await
+                .as_deref(),
+            Some("dyn3")
+        );
+        assert_eq!(context_path(&state, &ContextKind::Events).await, None);
+        assert!(state.read().await.target_available);
+
+        mock.go_offline();
+        update_targets(test_env(), state.clone(), station.clone()).await;
+        update_targets(test_env(), state.clone(), station.clone()).await;
+        assert_eq!(state.read().await.consecutive_failures, 2);
+        assert!(!state.read().await.target_available);
+
+        mock.set_targets(vec![dynamics_target("dyn3", 3)]);
+        update_targets(test_env(), state.clone(), station.clone()).await;
+        assert_eq!(state.read().await.consecutive_failures, 0);
+        assert_eq!(
+            context_path(&state, &ContextKind::Dynamics)
+                .await
+                .as_deref(),
+            Some("dyn3")
+        );
+    }
+
+    #[tokio::test]
+    async fn target_change_disconnects_clients_and_serves_the_new_target() {
+        let mock = MockWinCC::start(vec![dynamics_target("dyn1", 1)]).await;
+        let station = Arc::new(mock.station(None));
+        let state = start_proxy(&station).await;
+        let port = station.contexts[0].port;
+
+        let mut client = connect_client(port).await;
+        let received = request(&mut client, 1, "Debugger.enable").await;
+        assert_eq!(received[0]["method"], "Debugger.scriptParsed");
+        assert_eq!(received[0]["params"]["url"], "Main/Dynamics.js");
+        assert_eq!(
+            received.last().unwrap()["result"]["debuggerId"],
+            "mock-dyn1"
+        );
+
+        // A new download replaces the target
+        mock.set_targets(vec![dynamics_target("dyn2", 2)]);
+        update_targets(test_env(), state.clone(), station.clone()).await;
+        assert_eq!(
+            context_path(&state, &ContextKind::Dynamics)
+                .await
+                .as_deref(),
+            Some("dyn2")
+        );
+        let closed = tokio::time::timeout(Duration::from_secs(10), async {
+            while let Some(Ok(msg)) = client.next().await {
+                if msg.is_close() {
+                    break;
+                }
+            }
+        })
+        .await;
+        assert!(closed.is_ok(), "client was not disconnected");
+
+        // VS Code reconnects and ends up on the new target
+        let mut client = connect_client(port).await;
+        let received = request(&mut client, 1, "Debugger.enable").await;
+        assert_eq!(
+            received.last().unwrap()["result"]["debuggerId"],
+            "mock-dyn2"
+        );
+        assert_eq!(mock.connections(), vec!["dyn1", "dyn2"]);
+    }
+
+    /// Read what the client receives until the response to `id`.
+    async fn response_to(client: &mut ClientStream, id: u64) -> serde_json::Value {
+        loop {
+            let msg = tokio::time::timeout(Duration::from_secs(10), client.next())
+                .await
+                .expect("response in time")
+                .expect("connection open")
+                .unwrap();
+            let Message::Text(text) = msg else { continue };
+            let parsed: serde_json::Value = serde_json::from_str(&text).unwrap();
+            if parsed["id"] == id {
+                return parsed;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn kept_sessions_answer_lost_requests_and_replay_enables_once() {
+        let mock = MockWinCC::start(vec![dynamics_target("dyn1", 1)]).await;
+        let station = Arc::new(mock.station(None));
+        let env = Arc::new(Env {
+            cfg: Configuration {
+                keep_sessions: true,
+                ..Configuration::default()
+            },
+            log: Logger::default(),
         });
-        assert!(dumper.handle_script_parsed(&event).is_none());
+        let state = start_proxy_in(env.clone(), &station).await;
+        let mut client = connect_client(station.contexts[0].port).await;
+        request(&mut client, 1, "Debugger.enable").await;
+
+        // A request the old target never answers fails when the target changes
+        let lost = serde_json::json!({ "id": 2, "method": MOCK_NO_REPLY }).to_string();
+        client.send(Message::Text(lost)).await.unwrap();
+        wait_for("the request to reach the target", || {
+            !mock.received("dyn1", MOCK_NO_REPLY).is_empty()
+        })
+        .await;
+        mock.set_targets(vec![dynamics_target("dyn2", 2)]);
+        update_targets(env.clone(), state.clone(), station.clone()).await;
+        let response = response_to(&mut client, 2).await;
+        assert_eq!(response["error"]["code"], -32000);
+        wait_for("the replay on dyn2", || {
+            mock.received("dyn2", "Debugger.enable").len() == 1
+        })
+        .await;
+
+        // An enable sent while the runtime is down goes out once, not again as a replay
+        mock.go_offline();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let enable = serde_json::json!({ "id": 3, "method": "Debugger.enable" }).to_string();
+        client.send(Message::Text(enable)).await.unwrap();
+        mock.set_targets(vec![dynamics_target("dyn3", 3)]);
+        update_targets(env, state.clone(), station.clone()).await;
+        let response = response_to(&mut client, 3).await;
+        assert_eq!(response["result"]["debuggerId"], "mock-dyn3");
+        assert_eq!(mock.received("dyn3", "Debugger.enable").len(), 1);
     }
 
-    #[test]
-    fn handle_script_parsed_wrong_method() {
-        let mut dumper = make_dumper();
-        let event = serde_json::json!({
-            "method": "Debugger.paused",
-            "params": { "scriptId": "1", "url": "foo.js" }
+    #[tokio::test]
+    async fn clients_trigger_script_dumps() {
+        let scratch = tempfile::tempdir().unwrap();
+        let tmp = scratch.path();
+        let mock = MockWinCC::start(vec![dynamics_target("dyn1", 4)]).await;
+        let station = Arc::new(mock.station(Some(tmp.to_string_lossy().into_owned())));
+        let _state = start_proxy(&station).await;
+
+        let mut client = connect_client(station.contexts[0].port).await;
+        let received = request(&mut client, 7, "Debugger.enable").await;
+        let source_map = received[0]["params"]["sourceMapURL"].as_str().unwrap();
+        assert!(
+            source_map.contains("/sourcemap/Dynamics/screen_modules/"),
+            "{}",
+            source_map
+        );
+
+        let file = tmp.join("Dynamics/screen_modules/Screen_Content/HMI_RT_1__Main/Dynamics.js");
+        wait_for("the dumped script", || file.exists()).await;
+        let manifest = tmp.join("Dynamics").join(crate::dump::MANIFEST_FILE);
+        wait_for("the manifest", || manifest.exists()).await;
+        assert!(
+            std::fs::read_to_string(&file)
+                .unwrap()
+                .contains("Text_1_Text")
+        );
+        assert!(
+            std::fs::read_to_string(&manifest)
+                .unwrap()
+                .contains("\"vcs\": 4")
+        );
+
+        // The dump requests were the proxy's own; the client only sees its own ids
+        assert_eq!(mock.received("dyn1", "Debugger.getScriptSource").len(), 1);
+        let received = request(&mut client, 8, "Runtime.enable").await;
+        assert!(
+            received
+                .iter()
+                .all(|msg| msg.get("id").is_none_or(|id| id == 8))
+        );
+
+        drop(client);
+    }
+
+    #[tokio::test]
+    async fn source_maps_point_at_the_host_clients_use_on_open_binds() {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+        let scratch = tempfile::tempdir().unwrap();
+        let mock = MockWinCC::start(vec![dynamics_target("dyn1", 4)]).await;
+        let station = Arc::new(mock.station(Some(scratch.path().to_string_lossy().into_owned())));
+        let env = Arc::new(Env {
+            cfg: Configuration {
+                bind_address: std::net::Ipv4Addr::UNSPECIFIED.into(),
+                ..Default::default()
+            },
+            log: Logger::default(),
         });
-        assert!(dumper.handle_script_parsed(&event).is_none());
+        let _state = start_proxy_in(env.clone(), &station).await;
+
+        let port = station.contexts[0].port;
+        let mut upgrade = format!("ws://127.0.0.1:{}/", port)
+            .into_client_request()
+            .unwrap();
+        let host = format!("plc-pc:{}", port);
+        upgrade.headers_mut().insert("host", host.parse().unwrap());
+        let (mut client, _) = tokio_tungstenite::connect_async(upgrade).await.unwrap();
+        let received = request(&mut client, 7, "Debugger.enable").await;
+        let source_map = received[0]["params"]["sourceMapURL"].as_str().unwrap();
+        assert!(
+            source_map.starts_with(&format!("http://{}/sourcemap/", host)),
+            "{}",
+            source_map
+        );
+
+        // Without a Host header, the bind address it is
+        let v6 = Configuration {
+            bind_address: "::".parse().unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(client_authority(&v6, None, "localhost", 9230), "[::]:9230");
+        assert_eq!(
+            client_authority(&env.cfg, Some(host.clone()), "localhost", port),
+            host
+        );
+        assert_eq!(
+            client_authority(&Configuration::default(), Some(host), "localhost", 9230),
+            "localhost:9230"
+        );
     }
 
     // ================================================================
-    // ScriptDumper::handle_response
+    // Proxy
     // ================================================================
 
-    #[test]
-    fn handle_response_matching_id_consumed() {
-        use crate::config::{Configuration, CONFIG};
-        let _ = CONFIG.set(Configuration::default());
+    /// The next event that isn't a log line or dump progress.
+    async fn next_event(events: &mut broadcast::Receiver<ProxyEvent>) -> ProxyEvent {
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(10), events.recv())
+                .await
+                .expect("event in time")
+                .expect("channel open");
+            if !matches!(event, ProxyEvent::Log(_) | ProxyEvent::DumpProgress { .. }) {
+                return event;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn proxy_can_be_started_again_after_shutdown() {
+        let mock = MockWinCC::start(vec![dynamics_target("dyn1", 1)]).await;
+        let cfg = Configuration {
+            stations: vec![mock.station(None)],
+            ..Configuration::default()
+        };
+        let port = cfg.stations[0].contexts[0].port;
+
+        for _ in 0..2 {
+            let proxy = Proxy::new(cfg.clone());
+            let mut events = proxy.subscribe();
+            let handle = proxy.start().await.unwrap();
+            assert!(matches!(next_event(&mut events).await, ProxyEvent::Ready));
+            assert!(matches!(
+                next_event(&mut events).await,
+                ProxyEvent::TargetServerConnected { .. }
+            ));
+            assert!(matches!(
+                next_event(&mut events).await,
+                ProxyEvent::TargetDiscovered { .. }
+            ));
+            let mut client = connect_client(port).await;
+            let received = request(&mut client, 1, "Debugger.enable").await;
+            assert_eq!(
+                received.last().unwrap()["result"]["debuggerId"],
+                "mock-dyn1"
+            );
+            assert!(matches!(
+                next_event(&mut events).await,
+                ProxyEvent::ClientConnected { .. }
+            ));
+            assert!(matches!(
+                next_event(&mut events).await,
+                ProxyEvent::ClientAttached { .. }
+            ));
 
-        let tmp = std::env::temp_dir().join("wincc_test_dump");
-        let mut dumper = ScriptDumper::new(tmp.to_string_lossy().to_string(), "Dynamics");
+            handle.shutdown().await;
+            assert!(matches!(
+                next_event(&mut events).await,
+                ProxyEvent::ClientDisconnected {
+                    reason: DisconnectReason::ProxyStopped,
+                    ..
+                }
+            ));
+            assert!(matches!(next_event(&mut events).await, ProxyEvent::Stopped));
+            assert!(
+                tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{}/", port))
+                    .await
+                    .is_err()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn proxy_start_fails_when_a_port_is_taken() {
+        let mock = MockWinCC::start(vec![dynamics_target("dyn1", 1)]).await;
+        let station = mock.station(None);
+        let taken = std::net::TcpListener::bind(("127.0.0.1", station.contexts[1].port)).unwrap();
+        let cfg = Configuration {
+            stations: vec![station],
+            ..Configuration::default()
+        };
+        let free = cfg.stations[0].contexts[0].port;
 
-        // Insert a pending entry manually
-        dumper.pending.insert(900_000, format!("{}/Dynamics/test.js", tmp.display()));
+        let error = Proxy::new(cfg).start().await.err().expect("port conflict");
+        assert!(
+            format!("{:#}", error).contains("Cannot listen on"),
+            "{:#}",
+            error
+        );
+        // The server that did start was stopped again
+        assert!(std::net::TcpListener::bind(("127.0.0.1", free)).is_ok());
+        drop(taken);
+    }
 
-        let response = serde_json::json!({
-            "id": 900_000,
-            "result": { "scriptSource": "console.log('hello');" }
+    #[tokio::test]
+    async fn clients_and_targets_are_reported_as_events() {
+        let scratch = tempfile::tempdir().unwrap();
+        let tmp = scratch.path();
+        let mock = MockWinCC::start(vec![dynamics_target("dyn1", 1)]).await;
+        let station = Arc::new(mock.station(Some(tmp.to_string_lossy().into_owned())));
+        let (tx, mut events) = broadcast::channel(EVENT_CAPACITY);
+        let env = Arc::new(Env {
+            cfg: Configuration::default(),
+            log: Logger::events(tx, Verbosity::default(), None),
         });
-        assert!(dumper.handle_response(&response));
-        assert!(dumper.pending.is_empty());
+        let state = Arc::new(RwLock::new(AppState::new(env.log.clone())));
+        let spec = station.contexts[0].clone();
+        start_server(env.clone(), state.clone(), station.clone(), spec.clone())
+            .await
+            .unwrap();
+        let origin = station.origin(&ContextKind::Dynamics);
+
+        update_targets(env.clone(), state.clone(), station.clone()).await;
+        assert!(matches!(
+            next_event(&mut events).await,
+            ProxyEvent::TargetServerConnected {
+                after_failures: 0,
+                ..
+            }
+        ));
+        assert!(matches!(
+            next_event(&mut events).await,
+            ProxyEvent::TargetDiscovered { origin: ref o, ref path } if *o == origin && path == "dyn1"
+        ));
 
-        // Cleanup
-        let _ = std::fs::remove_dir_all(&tmp);
-    }
+        let mut client = connect_client(spec.port).await;
+        request(&mut client, 1, "Debugger.enable").await;
+        let ProxyEvent::ClientConnected { client_id, .. } = next_event(&mut events).await else {
+            panic!("expected the client to connect");
+        };
+        assert!(matches!(
+            next_event(&mut events).await,
+            ProxyEvent::ClientAttached { client_id: id, ref path, .. } if id == client_id && path == "dyn1"
+        ));
 
-    #[test]
-    fn handle_response_unknown_id_not_consumed() {
-        let mut dumper = make_dumper();
-        let response = serde_json::json!({ "id": 999_999, "result": {} });
-        assert!(!dumper.handle_response(&response));
-    }
+        // The script is dumped in the background
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(10), events.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            if let ProxyEvent::DumpProgress {
+                origin: ref o,
+                stats,
+            } = event
+            {
+                assert_eq!(*o, origin);
+                assert_eq!(stats.written, 1);
+                break;
+            }
+        }
 
-    #[test]
-    fn handle_response_no_id_field() {
-        let mut dumper = make_dumper();
-        let msg = serde_json::json!({ "method": "Debugger.paused" });
-        assert!(!dumper.handle_response(&msg));
+        drop(client);
+        assert!(matches!(
+            next_event(&mut events).await,
+            ProxyEvent::ClientDisconnected {
+                reason: DisconnectReason::ClientClosed,
+                ..
+            }
+        ));
+        assert!(matches!(
+            next_event(&mut events).await,
+            ProxyEvent::DumpFinished { stats, .. } if stats.written == 1
+        ));
+
+        mock.go_offline();
+        update_targets(env.clone(), state.clone(), station.clone()).await;
+        assert!(matches!(
+            next_event(&mut events).await,
+            ProxyEvent::TargetServerUnreachable { attempts: 1, .. }
+        ));
     }
 }
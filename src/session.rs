@@ -0,0 +1,274 @@
+use serde_json::{Value, json};
+use std::collections::HashSet;
+
+use crate::ids::REPLAY_ID_BASE;
+
+// ============================================================================
+// Session Preservation
+// ============================================================================
+
+/// Commands that change what the target reports or how it pauses. Everything
+/// else (evaluate, getProperties, stepping...) only makes sense against the
+/// target it was sent to and is not replayed. Breakpoints are restored
+/// separately by `BreakpointSync`.
+const REPLAYED_METHODS: &[&str] = &[
+    "Runtime.enable",
+    "Debugger.enable",
+    "Profiler.enable",
+    "Debugger.setPauseOnExceptions",
+    "Debugger.setAsyncCallStackDepth",
+    "Debugger.setBlackboxPatterns",
+    "Debugger.setBreakpointsActive",
+    "Debugger.setSkipAllPauses",
+];
+
+#[derive(Debug)]
+struct RecordedCommand {
+    method: String,
+    params: Value,
+}
+
+/// The CDP state of one VS Code connection that has to survive a switch to a
+/// new upstream target.
+///
+/// The session records the domain-enable and settings commands the client
+/// sent and watches the target for execution contexts and pauses. When the
+/// target changes, the old contexts are reported destroyed to the client and
+/// the recorded commands are replayed against the new target, which then
+/// announces its own contexts and scripts as if the client had just attached.
+#[derive(Debug, Default)]
+pub struct Session {
+    recorded: Vec<RecordedCommand>,
+    replay_ids: HashSet<u64>,
+    next_replay_id: u64,
+    execution_contexts: Vec<Value>,
+    paused: bool,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self {
+            next_replay_id: REPLAY_ID_BASE,
+            ..Default::default()
+        }
+    }
+
+    /// Record a client command if it has to be replayed after a target switch.
+    pub fn record_client_message(&mut self, parsed: &Value) {
+        let Some(method) = parsed.get("method").and_then(|m| m.as_str()) else {
+            return;
+        };
+        let params = parsed.get("params").cloned().unwrap_or_else(|| json!({}));
+
+        if let Some(domain) = method.strip_suffix(".disable") {
+            let enable = format!("{}.enable", domain);
+            self.recorded.retain(|c| c.method != enable);
+        } else if REPLAYED_METHODS.contains(&method) {
+            // Enables and settings only need their latest value
+            self.recorded.retain(|c| c.method != method);
+            self.recorded.push(RecordedCommand {
+                method: method.to_string(),
+                params,
+            });
+        }
+    }
+
+    /// Track execution contexts and pauses reported by the target.
+    pub fn observe_target_message(&mut self, parsed: &Value) {
+        let params = parsed.get("params");
+        match parsed.get("method").and_then(|m| m.as_str()) {
+            Some("Runtime.executionContextCreated") => {
+                if let Some(context) = params.and_then(|p| p.get("context")) {
+                    self.execution_contexts.push(context.clone());
+                }
+            }
+            Some("Runtime.executionContextDestroyed") => {
+                let id = params.and_then(|p| p.get("executionContextId"));
+                self.execution_contexts.retain(|c| c.get("id") != id);
+            }
+            Some("Runtime.executionContextsCleared") => self.execution_contexts.clear(),
+            Some("Debugger.paused") => self.paused = true,
+            Some("Debugger.resumed") => self.paused = false,
+            _ => {}
+        }
+    }
+
+    /// Synthetic events that tell the client the old target is gone: a resume
+    /// if it was paused, then every execution context it knew is destroyed.
+    pub fn detach_events(&mut self) -> Vec<String> {
+        let mut events = Vec::new();
+        if self.paused {
+            events.push(json!({ "method": "Debugger.resumed", "params": {} }).to_string());
+            self.paused = false;
+        }
+        for context in self.execution_contexts.drain(..) {
+            let mut params = json!({ "executionContextId": context.get("id") });
+            if let Some(unique_id) = context.get("uniqueId") {
+                params["executionContextUniqueId"] = unique_id.clone();
+            }
+            events.push(
+                json!({ "method": "Runtime.executionContextDestroyed", "params": params })
+                    .to_string(),
+            );
+        }
+        events
+    }
+
+    /// The recorded commands re-issued with proxy-owned ids, in the order the
+    /// client originally sent them. Commands the client sent again while no
+    /// target was connected (`queued`) are left out: those are forwarded
+    /// themselves, and the client waits for their responses.
+    pub fn replay_commands(&mut self, queued: &[String]) -> Vec<String> {
+        let resent: Vec<String> = queued
+            .iter()
+            .filter_map(|text| {
+                let parsed = serde_json::from_str::<Value>(text).ok()?;
+                parsed.get("method")?.as_str().map(String::from)
+            })
+            .collect();
+        let mut commands = Vec::new();
+        for command in self.recorded.iter().filter(|c| !resent.contains(&c.method)) {
+            let id = self.next_replay_id;
+            self.next_replay_id += 1;
+            self.replay_ids.insert(id);
+            commands.push(
+                json!({ "id": id, "method": command.method, "params": command.params }).to_string(),
+            );
+        }
+        commands
+    }
+
+    /// Returns true if this is the response to a replayed command. Those are
+    /// swallowed since the client never sent the request.
+    pub fn consume_replay_response(&mut self, parsed: &Value) -> bool {
+        parsed
+            .get("id")
+            .and_then(|id| id.as_u64())
+            .is_some_and(|id| self.replay_ids.remove(&id))
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn methods(commands: &[String]) -> Vec<String> {
+        commands
+            .iter()
+            .map(|c| {
+                let parsed: Value = serde_json::from_str(c).unwrap();
+                parsed["method"].as_str().unwrap().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replays_enables_and_settings_in_order() {
+        let mut session = Session::new();
+        session.record_client_message(&json!({"id": 1, "method": "Runtime.enable"}));
+        session.record_client_message(&json!({"id": 2, "method": "Debugger.enable"}));
+        session.record_client_message(
+            &json!({"id": 3, "method": "Runtime.evaluate", "params": {"expression": "1"}}),
+        );
+        session.record_client_message(&json!({
+            "id": 4,
+            "method": "Debugger.setAsyncCallStackDepth",
+            "params": {"maxDepth": 32}
+        }));
+        session.record_client_message(&json!({
+            "id": 5,
+            "method": "Debugger.setBreakpointByUrl",
+            "params": {"lineNumber": 3, "url": "Events.js"}
+        }));
+
+        let commands = session.replay_commands(&[]);
+        assert_eq!(
+            methods(&commands),
+            vec![
+                "Runtime.enable",
+                "Debugger.enable",
+                "Debugger.setAsyncCallStackDepth"
+            ]
+        );
+        let depth: Value = serde_json::from_str(&commands[2]).unwrap();
+        assert_eq!(depth["params"]["maxDepth"], 32);
+        assert!(depth["id"].as_u64().unwrap() >= REPLAY_ID_BASE);
+    }
+
+    #[test]
+    fn settings_keep_latest_value() {
+        let mut session = Session::new();
+        session.record_client_message(&json!({"id": 1, "method": "Debugger.setPauseOnExceptions", "params": {"state": "all"}}));
+        session.record_client_message(&json!({"id": 2, "method": "Debugger.setPauseOnExceptions", "params": {"state": "none"}}));
+
+        let commands = session.replay_commands(&[]);
+        assert_eq!(commands.len(), 1);
+        assert!(commands[0].contains("\"none\""));
+    }
+
+    #[test]
+    fn disable_drops_enable() {
+        let mut session = Session::new();
+        session.record_client_message(&json!({"id": 1, "method": "Profiler.enable"}));
+        session.record_client_message(&json!({"id": 2, "method": "Profiler.disable"}));
+        assert!(session.replay_commands(&[]).is_empty());
+    }
+
+    #[test]
+    fn queued_commands_are_not_replayed_twice() {
+        let mut session = Session::new();
+        session.record_client_message(&json!({"id": 1, "method": "Runtime.enable"}));
+        session.record_client_message(&json!({"id": 2, "method": "Debugger.enable"}));
+        let queued = vec![json!({"id": 3, "method": "Debugger.enable"}).to_string()];
+        assert_eq!(
+            methods(&session.replay_commands(&queued)),
+            vec!["Runtime.enable"]
+        );
+    }
+
+    #[test]
+    fn replay_responses_are_consumed_once() {
+        let mut session = Session::new();
+        session.record_client_message(&json!({"id": 1, "method": "Debugger.enable"}));
+        let commands = session.replay_commands(&[]);
+        let id = serde_json::from_str::<Value>(&commands[0]).unwrap()["id"].clone();
+
+        let response = json!({"id": id, "result": {}});
+        assert!(session.consume_replay_response(&response));
+        assert!(!session.consume_replay_response(&response));
+        assert!(!session.consume_replay_response(&json!({"id": 1, "result": {}})));
+    }
+
+    #[test]
+    fn detach_resumes_and_destroys_contexts() {
+        let mut session = Session::new();
+        session.observe_target_message(&json!({
+            "method": "Runtime.executionContextCreated",
+            "params": {"context": {"id": 1, "uniqueId": "abc", "origin": ""}}
+        }));
+        session.observe_target_message(&json!({
+            "method": "Runtime.executionContextCreated",
+            "params": {"context": {"id": 2, "origin": ""}}
+        }));
+        session.observe_target_message(&json!({
+            "method": "Runtime.executionContextDestroyed",
+            "params": {"executionContextId": 2}
+        }));
+        session.observe_target_message(&json!({"method": "Debugger.paused", "params": {}}));
+
+        let events = session.detach_events();
+        assert_eq!(
+            methods(&events),
+            vec!["Debugger.resumed", "Runtime.executionContextDestroyed"]
+        );
+        let destroyed: Value = serde_json::from_str(&events[1]).unwrap();
+        assert_eq!(destroyed["params"]["executionContextId"], 1);
+        assert_eq!(destroyed["params"]["executionContextUniqueId"], "abc");
+
+        assert!(session.detach_events().is_empty());
+    }
+}
@@ -0,0 +1,268 @@
+use oxc_allocator::Allocator;
+use oxc_ast::ast::{Declaration, ExportDefaultDeclarationKind, Expression, Statement};
+use oxc_parser::Parser;
+use oxc_span::{GetSpan, SourceType, Span};
+use serde::{Deserialize, Serialize};
+
+use crate::pathmap::{disambiguate, script_path};
+
+// ============================================================================
+// Script Bundle Splitting
+// ============================================================================
+
+/// Name of the file in a split directory that maps its files back to the script.
+pub const INDEX_FILE: &str = "index.json";
+
+/// `index.json` of a split script.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SplitIndex {
+    /// File name of the script that was split
+    pub script: String,
+    pub functions: Vec<SplitEntry>,
+}
+
+/// One top-level function of a split script.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SplitEntry {
+    /// Path of the function's file, relative to the split directory
+    pub file: String,
+    pub name: String,
+    /// First line of the function in the script, counting from 1
+    pub start_line: usize,
+    /// Last line of the function in the script, counting from 1
+    pub end_line: usize,
+}
+
+/// A top-level function cut out of a script.
+#[derive(Debug)]
+pub struct SplitFunction {
+    pub entry: SplitEntry,
+    pub code: String,
+}
+
+/// Directory a script is split into, next to the script: `Events.js` becomes
+/// `Events.split`.
+pub fn split_dir(path: &str) -> String {
+    let (dir, file) = match path.rsplit_once('/') {
+        Some((dir, file)) => (format!("{}/", dir), file),
+        None => (String::new(), path),
+    };
+    let stem = match file.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => file,
+    };
+    format!("{}{}.split", dir, stem)
+}
+
+/// Where a generated function is written, relative to the split directory.
+///
+/// WinCC names the functions it generates after the screen item followed by
+/// the event (`Button_1_OnTapped`) or the dynamized property
+/// (`Rectangle_1_BackColor`). Item names contain underscores themselves, so
+/// the first part starting with `On` and a capital letter begins the event;
+/// without one, the last part is the property. The result is
+/// `Button_1/OnTapped.js`; names without an underscore stay at the top.
+fn function_path(name: &str) -> String {
+    let parts: Vec<&str> = name.split('_').collect();
+    let is_event = |part: &&str| {
+        part.strip_prefix("On")
+            .and_then(|rest| rest.chars().next())
+            .is_some_and(|c| c.is_ascii_uppercase())
+    };
+    let split_at = match parts.iter().skip(1).position(is_event) {
+        Some(pos) => pos + 1,
+        None => parts.len() - 1,
+    };
+    let item = parts[..split_at].join("_");
+    let member = parts[split_at..].join("_");
+    if item.is_empty() || member.is_empty() {
+        return script_path(&format!("{}.js", name));
+    }
+    script_path(&format!("{}/{}.js", item, member))
+}
+
+/// The name and span of a statement that declares a function.
+fn declared_function<'a>(statement: &Statement<'a>) -> Option<(String, Span)> {
+    let name = match statement {
+        Statement::FunctionDeclaration(function) => {
+            function.id.as_ref().map(|id| id.name.to_string())
+        }
+        Statement::ExportNamedDeclaration(export) => match export.declaration {
+            Some(Declaration::FunctionDeclaration(ref function)) => {
+                function.id.as_ref().map(|id| id.name.to_string())
+            }
+            Some(Declaration::VariableDeclaration(ref variables)) => {
+                function_variable(&variables.declarations)
+            }
+            _ => None,
+        },
+        Statement::ExportDefaultDeclaration(export) => match export.declaration {
+            ExportDefaultDeclarationKind::FunctionDeclaration(ref function) => Some(
+                function
+                    .id
+                    .as_ref()
+                    .map_or_else(|| "default".to_string(), |id| id.name.to_string()),
+            ),
+            _ => None,
+        },
+        Statement::VariableDeclaration(variables) => function_variable(&variables.declarations),
+        _ => None,
+    }?;
+    Some((name, statement.span()))
+}
+
+/// The name of a `const name = function ...` or `const name = () => ...`
+/// declaration with a single variable.
+fn function_variable(declarations: &[oxc_ast::ast::VariableDeclarator]) -> Option<String> {
+    let [declarator] = declarations else {
+        return None;
+    };
+    let is_function = matches!(
+        declarator.init,
+        Some(Expression::FunctionExpression(_) | Expression::ArrowFunctionExpression(_))
+    );
+    is_function
+        .then(|| declarator.id.get_identifier_name())?
+        .map(|name| name.to_string())
+}
+
+/// Line of a byte offset, counting from 1.
+fn line_at(source: &str, offset: u32) -> usize {
+    source.as_bytes()[..offset as usize]
+        .iter()
+        .filter(|b| **b == b'\n')
+        .count()
+        + 1
+}
+
+/// The top-level functions of a script, each with the file it is written to
+/// and its lines in the script. Returns `None` if the script does not parse.
+///
+/// Functions that would land in the same file, which happens when WinCC
+/// generates names that only differ in replaced characters or case, get a
+/// hash of their name added to the file name.
+pub fn split_script(source: &str) -> Option<Vec<SplitFunction>> {
+    let allocator = Allocator::default();
+    let parsed = Parser::new(&allocator, source, SourceType::unambiguous()).parse();
+    if parsed.panicked || !parsed.errors.is_empty() {
+        return None;
+    }
+
+    let mut functions: Vec<SplitFunction> = Vec::new();
+    for (name, span) in parsed.program.body.iter().filter_map(declared_function) {
+        let mut file = function_path(&name);
+        if functions
+            .iter()
+            .any(|f| f.entry.file.eq_ignore_ascii_case(&file))
+        {
+            file = disambiguate(&file, &name);
+        }
+        functions.push(SplitFunction {
+            entry: SplitEntry {
+                file,
+                name,
+                start_line: line_at(source, span.start),
+                end_line: line_at(source, span.end),
+            },
+            code: format!("{}\n", &source[span.start as usize..span.end as usize]),
+        });
+    }
+    Some(functions)
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EVENTS: &str = r#"import * as HMIRuntime from "HMIRuntime";
+export async function Button_1_OnTapped(item, x, y, modifiers, trigger) {
+    HMIRuntime.Tags("Start").Write(1);
+}
+export async function Button_1_OnPressed(item, x, y, modifiers, trigger) {}
+export function Rectangle_Status_1_BackColor(item) {
+    return 0xFF00FF00;
+}
+const helper = (a) => a + 1;
+function Screen_OnLoaded(item) { helper(1); }
+let counter = 0;
+"#;
+
+    #[test]
+    fn functions_are_split_per_item_and_event() {
+        let functions = split_script(EVENTS).unwrap();
+        let files: Vec<(&str, &str, usize, usize)> = functions
+            .iter()
+            .map(|f| {
+                (
+                    f.entry.file.as_str(),
+                    f.entry.name.as_str(),
+                    f.entry.start_line,
+                    f.entry.end_line,
+                )
+            })
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                ("Button_1/OnTapped.js", "Button_1_OnTapped", 2, 4),
+                ("Button_1/OnPressed.js", "Button_1_OnPressed", 5, 5),
+                (
+                    "Rectangle_Status_1/BackColor.js",
+                    "Rectangle_Status_1_BackColor",
+                    6,
+                    8
+                ),
+                ("helper.js", "helper", 9, 9),
+                ("Screen/OnLoaded.js", "Screen_OnLoaded", 10, 10),
+            ]
+        );
+        assert_eq!(
+            functions[0].code,
+            "export async function Button_1_OnTapped(item, x, y, modifiers, trigger) {\n    HMIRuntime.Tags(\"Start\").Write(1);\n}\n"
+        );
+    }
+
+    #[test]
+    fn event_names_need_a_capital_after_on() {
+        assert_eq!(
+            function_path("Online_Status_Visible"),
+            "Online_Status/Visible.js"
+        );
+        assert_eq!(function_path("OnTapped"), "OnTapped.js");
+        assert_eq!(
+            function_path("IOField_1_OnLostFocus_Extra"),
+            "IOField_1/OnLostFocus_Extra.js"
+        );
+        assert_eq!(function_path("_Hidden"), "_Hidden.js");
+    }
+
+    #[test]
+    fn colliding_functions_get_different_files() {
+        let functions = split_script("function A_B(){}\nfunction a_b(){}\n").unwrap();
+        assert_eq!(functions[0].entry.file, "A/B.js");
+        assert!(
+            functions[1].entry.file.starts_with("a/b~"),
+            "{}",
+            functions[1].entry.file
+        );
+    }
+
+    #[test]
+    fn unparsable_scripts_are_not_split() {
+        assert!(split_script("function (").is_none());
+    }
+
+    #[test]
+    fn split_dir_replaces_extension() {
+        assert_eq!(
+            split_dir("Screen/HMI_RT_1__Main/Events.js"),
+            "Screen/HMI_RT_1__Main/Events.split"
+        );
+        assert_eq!(split_dir("Dynamics"), "Dynamics.split");
+    }
+}
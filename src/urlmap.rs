@@ -0,0 +1,571 @@
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::logging::Logger;
+use crate::pathmap::script_path;
+use crate::pretty::raw_path;
+
+// ============================================================================
+// Script URL Shortening
+// ============================================================================
+
+/// Shorten a WinCC script URL by stripping known prefixes and intermediate segments.
+///
+/// Transforms paths like:
+///   /screen_modules/Screen_Content/HMI_RT_1::HMI_Screen/faceplate_modules/CM_Freq/Events.js
+/// Into:
+///   HMI_Screen/CM_Freq/Events.js
+pub fn shorten_script_url(url: &str) -> Option<String> {
+    shorten_with_separator(url, ':', usize::MAX)
+}
+
+/// `shorten_script_url` for the path of a dumped script relative to its
+/// context directory, where the colons after `HMI_RT_1` became underscores.
+/// Only the two underscores that replaced `::` are stripped, since screen
+/// names may start with `_`.
+pub fn shorten_dumped_path(path: &str) -> Option<String> {
+    shorten_with_separator(path, '_', 2)
+}
+
+fn shorten_with_separator(url: &str, separator: char, max_separators: usize) -> Option<String> {
+    // Strip optional leading slash, then the known prefix
+    let rest = url.strip_prefix('/').unwrap_or(url);
+    let rest = rest.strip_prefix("screen_modules/Screen_Content/")?;
+
+    // Strip HMI_RT_\d+:: (double colon) or HMI_RT_\d+: (single colon) prefix
+    let rest = match rest.strip_prefix("HMI_RT_") {
+        Some(after) => {
+            let digits = after.len() - after.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            let after_digits = &after[digits..];
+            if after_digits.starts_with(separator) {
+                // Skip past consecutive separators (handles both : and ::)
+                let mut after_sep = after_digits;
+                for _ in 0..max_separators {
+                    match after_sep.strip_prefix(separator) {
+                        Some(next) => after_sep = next,
+                        None => break,
+                    }
+                }
+                after_sep
+            } else {
+                rest
+            }
+        }
+        None => rest,
+    };
+
+    // Strip faceplate_modules/ intermediate segment
+    let result = rest.replace("/faceplate_modules/", "/");
+
+    Some(result)
+}
+
+// ============================================================================
+// Dumped Script Paths
+// ============================================================================
+
+/// Whether a script gets dumped: everything with a URL except the snippets the
+/// debugger evaluates itself.
+pub fn is_dumpable(script_url: &str) -> bool {
+    !(script_url.is_empty() || (script_url.starts_with("eval-") && script_url.ends_with(".cdp")))
+}
+
+/// Where a script is dumped, relative to the dump directory. See
+/// `pathmap::script_path` for how the URL becomes a path.
+pub fn dump_relative_path(context_name: &str, script_url: &str) -> String {
+    format!("{}/{}", context_name, script_path(script_url))
+}
+
+/// The `file://` URL of a dumped script. Empty, `.` and `..` segments of the
+/// relative path are skipped.
+pub fn dump_file_url(dump_dir: &Path, relative: &str) -> Option<url::Url> {
+    let mut path = dump_dir.to_path_buf();
+    for segment in relative.split('/') {
+        if !segment.is_empty() && segment != "." && segment != ".." {
+            path.push(segment);
+        }
+    }
+    url::Url::from_file_path(std::path::absolute(path).ok()?).ok()
+}
+
+/// Escape a URL for use in a JavaScript regular expression.
+fn escape_js_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\^$.*+?()[]{}|/".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// ============================================================================
+// Script URL Mapping
+// ============================================================================
+
+/// How script URLs are presented to the client.
+#[derive(Debug, Clone)]
+pub enum UrlStyle {
+    /// Shortened paths like `HMI_Screen/Events.js`
+    Short,
+    /// The runtime URLs, unchanged
+    Long,
+    /// `file://` URLs of the dumped copies, so VS Code works on the local
+    /// files. With `raw`, the `.raw.js` copies of pretty-printed scripts.
+    File {
+        dump_dir: PathBuf,
+        context: String,
+        raw: bool,
+    },
+}
+
+/// Pairs of full and client-facing script URLs, shared by the connections of a
+/// context.
+///
+/// Only the CDP fields that carry script URLs are rewritten: on the way to the
+/// client, `Debugger.scriptParsed`, the call frames of `Debugger.paused`, and
+/// the stack traces and exception details of `Runtime` events and responses;
+/// on the way back, the `url` and `urlRegex` of `Debugger.setBreakpointByUrl`.
+/// Argument and result payloads are never touched. The pairs outlive
+/// connections, since the script URLs of a context stay the same when its
+/// target changes.
+///
+/// A short URL that two full URLs shorten to (the same screen in two
+/// `HMI_RT_n` runtimes) keeps the first one; the second stays long.
+#[derive(Debug, Default)]
+pub struct UrlMap {
+    to_long: HashMap<String, String>,
+    to_short: HashMap<String, String>,
+    log: Logger,
+}
+
+impl UrlMap {
+    pub fn new(log: Logger) -> Self {
+        Self {
+            log,
+            ..Default::default()
+        }
+    }
+
+    /// The client form of a target URL, learning the pair on first sight.
+    fn present(&mut self, long: &str, style: &UrlStyle) -> Option<String> {
+        if let Some(short) = self.to_short.get(long) {
+            return (short != long).then(|| short.clone());
+        }
+        let short = match style {
+            UrlStyle::Short => match shorten_script_url(long) {
+                Some(short) if self.to_long.contains_key(&short) => {
+                    self.log
+                        .verbose(&format!("Kept script URL {}: {} is taken", long, short));
+                    self.to_short.insert(long.to_string(), long.to_string());
+                    return None;
+                }
+                short => short?,
+            },
+            UrlStyle::Long => return None,
+            UrlStyle::File {
+                dump_dir,
+                context,
+                raw,
+            } => {
+                if !is_dumpable(long) {
+                    return None;
+                }
+                let relative = dump_relative_path(context, long);
+                let relative = if *raw { raw_path(&relative) } else { relative };
+                dump_file_url(dump_dir, &relative)?.to_string()
+            }
+        };
+        self.log
+            .verbose(&format!("Rewrote script URL: {} -> {}", long, short));
+        self.to_long.insert(short.clone(), long.to_string());
+        self.to_short.insert(long.to_string(), short.clone());
+        Some(short)
+    }
+
+    /// The target form of a client URL. File URLs are compared without case,
+    /// since VS Code may change the case of the drive letter.
+    fn to_target(&self, url: &str) -> Option<String> {
+        self.to_long
+            .get(url)
+            .or_else(|| {
+                url.get(..5)
+                    .is_some_and(|scheme| scheme.eq_ignore_ascii_case("file:"))
+                    .then(|| {
+                        self.to_long
+                            .iter()
+                            .find(|(k, _)| k.eq_ignore_ascii_case(url))
+                    })
+                    .flatten()
+                    .map(|(_, long)| long)
+            })
+            .cloned()
+    }
+
+    /// Extend a client URL pattern so it also matches the full form of every
+    /// known short URL it matches. Returns `None` if nothing needs to change
+    /// or the pattern can't be evaluated here.
+    fn regex_to_target(&self, pattern: &str) -> Option<String> {
+        let regex = Regex::new(pattern).ok()?;
+        let mut longs: Vec<&str> = self
+            .to_long
+            .iter()
+            .filter(|(short, _)| regex.is_match(short))
+            .map(|(_, long)| long.as_str())
+            .collect();
+        if longs.is_empty() {
+            return None;
+        }
+        longs.sort_unstable();
+        let alternatives: Vec<String> = longs.into_iter().map(escape_js_regex).collect();
+        Some(format!("(?:{})|^(?:{})$", pattern, alternatives.join("|")))
+    }
+
+    /// Rewrite the script URLs in a message from the target to `style`.
+    /// Returns true if the message changed.
+    pub fn rewrite_for_client(&mut self, value: &mut Value, style: &UrlStyle) -> bool {
+        if matches!(style, UrlStyle::Long) {
+            return false;
+        }
+        let method = value
+            .get("method")
+            .and_then(|m| m.as_str())
+            .map(String::from);
+        let (params, result) = match method.as_deref() {
+            Some("Debugger.scriptParsed" | "Debugger.scriptFailedToParse") => {
+                let Some(params) = value.get_mut("params") else {
+                    return false;
+                };
+                let changed = self.rewrite_url(params.get_mut("url"), style);
+                return self.rewrite_stack_trace(params.get_mut("stackTrace"), style) | changed;
+            }
+            Some("Debugger.paused" | "Runtime.exceptionThrown" | "Runtime.consoleAPICalled") => {
+                (value.get_mut("params"), None)
+            }
+            Some(_) => return false,
+            None => (None, value.get_mut("result")),
+        };
+        // Event parameters and command results share these fields
+        let Some(fields) = params.or(result) else {
+            return false;
+        };
+        let mut changed = self.rewrite_call_frames(fields.get_mut("callFrames"), style);
+        changed |= self.rewrite_stack_trace(fields.get_mut("asyncStackTrace"), style);
+        changed |= self.rewrite_stack_trace(fields.get_mut("stackTrace"), style);
+        if let Some(details) = fields.get_mut("exceptionDetails") {
+            changed |= self.rewrite_url(details.get_mut("url"), style);
+            changed |= self.rewrite_stack_trace(details.get_mut("stackTrace"), style);
+        }
+        changed
+    }
+
+    fn rewrite_url(&mut self, field: Option<&mut Value>, style: &UrlStyle) -> bool {
+        if let Some(Value::String(url)) = field
+            && let Some(short) = self.present(url, style)
+        {
+            *url = short;
+            return true;
+        }
+        false
+    }
+
+    /// The `url` of every frame in a `callFrames` array.
+    fn rewrite_call_frames(&mut self, frames: Option<&mut Value>, style: &UrlStyle) -> bool {
+        let Some(Value::Array(frames)) = frames else {
+            return false;
+        };
+        frames.iter_mut().fold(false, |changed, frame| {
+            self.rewrite_url(frame.get_mut("url"), style) | changed
+        })
+    }
+
+    /// A `Runtime.StackTrace` and the parents it chains to.
+    fn rewrite_stack_trace(&mut self, trace: Option<&mut Value>, style: &UrlStyle) -> bool {
+        let Some(trace) = trace else {
+            return false;
+        };
+        let changed = self.rewrite_call_frames(trace.get_mut("callFrames"), style);
+        self.rewrite_stack_trace(trace.get_mut("parent"), style) | changed
+    }
+
+    /// Translate the script URL or URL pattern of a `Debugger.setBreakpointByUrl`
+    /// from the client back to the form the target knows. Returns true if the
+    /// message changed.
+    pub fn rewrite_for_target(&self, value: &mut Value) -> bool {
+        if value.get("method").and_then(|m| m.as_str()) != Some("Debugger.setBreakpointByUrl") {
+            return false;
+        }
+        let Some(params) = value.get_mut("params") else {
+            return false;
+        };
+        let mut changed = false;
+        if let Some(Value::String(url)) = params.get_mut("url")
+            && let Some(long) = self.to_target(url)
+        {
+            *url = long;
+            changed = true;
+        }
+        if let Some(Value::String(pattern)) = params.get_mut("urlRegex")
+            && let Some(extended) = self.regex_to_target(pattern)
+        {
+            *pattern = extended;
+            changed = true;
+        }
+        changed
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // ================================================================
+    // shorten_script_url
+    // ================================================================
+
+    #[test]
+    fn shorten_full_faceplate_path() {
+        let url = "/screen_modules/Screen_Content/HMI_RT_1::HMI_Screen/faceplate_modules/CM_Freq/Events.js";
+        assert_eq!(
+            shorten_script_url(url),
+            Some("HMI_Screen/CM_Freq/Events.js".to_string())
+        );
+    }
+
+    #[test]
+    fn shorten_single_colon_path() {
+        let url = "/screen_modules/Screen_Content/HMI_RT_1:HMI_Screen/Dynamics.js";
+        assert_eq!(
+            shorten_script_url(url),
+            Some("HMI_Screen/Dynamics.js".to_string())
+        );
+    }
+
+    #[test]
+    fn shorten_no_faceplate_modules() {
+        let url = "/screen_modules/Screen_Content/HMI_RT_1::HMI_Screen/Dynamics.js";
+        assert_eq!(
+            shorten_script_url(url),
+            Some("HMI_Screen/Dynamics.js".to_string())
+        );
+    }
+
+    #[test]
+    fn shorten_no_leading_slash() {
+        let url = "screen_modules/Screen_Content/HMI_RT_1::HMI_Screen/Events.js";
+        assert_eq!(
+            shorten_script_url(url),
+            Some("HMI_Screen/Events.js".to_string())
+        );
+    }
+
+    #[test]
+    fn shorten_skips_every_colon_after_runtime() {
+        let url = "/screen_modules/Screen_Content/HMI_RT_1:::HMI_Screen/Events.js";
+        assert_eq!(
+            shorten_script_url(url),
+            Some("HMI_Screen/Events.js".to_string())
+        );
+    }
+
+    #[test]
+    fn shorten_dumped_path_with_replaced_colons() {
+        assert_eq!(
+            shorten_dumped_path(
+                "screen_modules/Screen_Content/HMI_RT_1__HMI_Screen/faceplate_modules/CM_Freq/Events.js"
+            ),
+            Some("HMI_Screen/CM_Freq/Events.js".to_string())
+        );
+        assert_eq!(
+            shorten_dumped_path("/screen_modules/Screen_Content/HMI_RT_12_HMI_Screen/Dynamics.js"),
+            Some("HMI_Screen/Dynamics.js".to_string())
+        );
+        assert_eq!(
+            shorten_dumped_path("screen_modules/Screen_Content/HMI_RT_1___Hidden/Events.js"),
+            Some("_Hidden/Events.js".to_string())
+        );
+    }
+
+    #[test]
+    fn shorten_unrelated_url_returns_none() {
+        assert_eq!(shorten_script_url("https://example.com/foo.js"), None);
+        assert_eq!(shorten_script_url("eval-123.cdp"), None);
+    }
+
+    // ================================================================
+    // UrlMap
+    // ================================================================
+
+    const LONG: &str =
+        "/screen_modules/Screen_Content/HMI_RT_1::HMI_Screen/faceplate_modules/CM_Freq/Events.js";
+    const SHORT: &str = "HMI_Screen/CM_Freq/Events.js";
+
+    fn learned() -> UrlMap {
+        let mut urls = UrlMap::default();
+        let mut parsed =
+            json!({"method": "Debugger.scriptParsed", "params": {"scriptId": "1", "url": LONG}});
+        assert!(urls.rewrite_for_client(&mut parsed, &UrlStyle::Short));
+        assert_eq!(parsed["params"]["url"], SHORT);
+        urls
+    }
+
+    #[test]
+    fn rewrites_stack_traces_for_client() {
+        let mut urls = UrlMap::default();
+        let mut paused = json!({
+            "method": "Debugger.paused",
+            "params": {
+                "callFrames": [{"callFrameId": "1", "url": LONG, "location": {"scriptId": "1", "lineNumber": 2}}],
+                "asyncStackTrace": {"callFrames": [{"url": LONG, "lineNumber": 5}]}
+            }
+        });
+        assert!(urls.rewrite_for_client(&mut paused, &UrlStyle::Short));
+        assert_eq!(paused["params"]["callFrames"][0]["url"], SHORT);
+        assert_eq!(
+            paused["params"]["asyncStackTrace"]["callFrames"][0]["url"],
+            SHORT
+        );
+
+        let mut exception = json!({
+            "method": "Runtime.exceptionThrown",
+            "params": {"exceptionDetails": {"url": LONG, "stackTrace": {"callFrames": [{"url": LONG}]}}}
+        });
+        assert!(urls.rewrite_for_client(&mut exception, &UrlStyle::Short));
+        assert_eq!(exception["params"]["exceptionDetails"]["url"], SHORT);
+        assert_eq!(
+            exception["params"]["exceptionDetails"]["stackTrace"]["callFrames"][0]["url"],
+            SHORT
+        );
+
+        let mut console = json!({"method": "Runtime.consoleAPICalled", "params": {"stackTrace": {"callFrames": [{"url": "https://example.com/a.js"}]}}});
+        assert!(!urls.rewrite_for_client(&mut console, &UrlStyle::Short));
+    }
+
+    #[test]
+    fn translates_short_url_for_target() {
+        let urls = learned();
+        let mut request = json!({"id": 1, "method": "Debugger.setBreakpointByUrl", "params": {"url": SHORT, "lineNumber": 3}});
+        assert!(urls.rewrite_for_target(&mut request));
+        assert_eq!(request["params"]["url"], LONG);
+
+        let mut unknown = json!({"id": 2, "method": "Debugger.setBreakpointByUrl", "params": {"url": "Other/Events.js"}});
+        assert!(!urls.rewrite_for_target(&mut unknown));
+    }
+
+    #[test]
+    fn extends_url_regex_with_known_long_urls() {
+        let urls = learned();
+        let mut request = json!({
+            "id": 1,
+            "method": "Debugger.setBreakpointByUrl",
+            "params": {"urlRegex": "[hH][mM][iI]_[sS]creen/CM_Freq/Events\\.js($|\\?)", "lineNumber": 3}
+        });
+        assert!(urls.rewrite_for_target(&mut request));
+        let pattern = request["params"]["urlRegex"].as_str().unwrap();
+        let regex = Regex::new(pattern).unwrap();
+        assert!(regex.is_match(LONG));
+        assert!(regex.is_match(SHORT));
+        assert!(!regex.is_match("/screen_modules/Screen_Content/HMI_RT_1::Other/Events.js"));
+
+        let mut unmatched =
+            json!({"method": "Debugger.setBreakpointByUrl", "params": {"urlRegex": "Other\\.js$"}});
+        assert!(!urls.rewrite_for_target(&mut unmatched));
+    }
+
+    #[test]
+    fn leaves_payloads_alone() {
+        let mut urls = learned();
+        let mut evaluate = json!({
+            "id": 1,
+            "method": "Runtime.evaluate",
+            "params": {"expression": "open(x)", "url": SHORT}
+        });
+        assert!(!urls.rewrite_for_target(&mut evaluate));
+        let mut call = json!({
+            "id": 2,
+            "method": "Runtime.callFunctionOn",
+            "params": {"arguments": [{"value": {"url": SHORT, "urlRegex": "Events"}}]}
+        });
+        assert!(!urls.rewrite_for_target(&mut call));
+
+        let mut result =
+            json!({"id": 1, "result": {"result": {"type": "object", "value": {"url": LONG}}}});
+        assert!(!urls.rewrite_for_client(&mut result, &UrlStyle::Short));
+        let mut console = json!({
+            "method": "Runtime.consoleAPICalled",
+            "params": {"args": [{"type": "string", "value": {"url": LONG}}], "stackTrace": {"callFrames": [{"url": LONG}]}}
+        });
+        assert!(urls.rewrite_for_client(&mut console, &UrlStyle::Short));
+        assert_eq!(console["params"]["args"][0]["value"]["url"], LONG);
+        assert_eq!(
+            console["params"]["stackTrace"]["callFrames"][0]["url"],
+            SHORT
+        );
+
+        let mut failed = json!({"id": 3, "result": {"exceptionDetails": {"url": LONG, "stackTrace": {"callFrames": [], "parent": {"callFrames": [{"url": LONG}]}}}}});
+        assert!(urls.rewrite_for_client(&mut failed, &UrlStyle::Short));
+        assert_eq!(failed["result"]["exceptionDetails"]["url"], SHORT);
+        assert_eq!(
+            failed["result"]["exceptionDetails"]["stackTrace"]["parent"]["callFrames"][0]["url"],
+            SHORT
+        );
+    }
+
+    #[test]
+    fn colliding_short_urls_keep_the_long_form() {
+        let mut urls = learned();
+        let other = LONG.replace("HMI_RT_1", "HMI_RT_2");
+        let mut parsed =
+            json!({"method": "Debugger.scriptParsed", "params": {"scriptId": "2", "url": other}});
+        assert!(!urls.rewrite_for_client(&mut parsed, &UrlStyle::Short));
+        assert_eq!(parsed["params"]["url"], other);
+        assert!(!urls.rewrite_for_client(&mut parsed, &UrlStyle::Short));
+
+        let mut request =
+            json!({"id": 1, "method": "Debugger.setBreakpointByUrl", "params": {"url": SHORT}});
+        assert!(urls.rewrite_for_target(&mut request));
+        assert_eq!(request["params"]["url"], LONG);
+        let mut request =
+            json!({"id": 2, "method": "Debugger.setBreakpointByUrl", "params": {"url": other}});
+        assert!(!urls.rewrite_for_target(&mut request));
+    }
+
+    #[test]
+    fn file_style_points_at_dumped_copy() {
+        let mut urls = UrlMap::default();
+        let style = UrlStyle::File {
+            dump_dir: PathBuf::from("/work/dump"),
+            context: "Events".to_string(),
+            raw: false,
+        };
+        let mut parsed =
+            json!({"method": "Debugger.scriptParsed", "params": {"scriptId": "1", "url": LONG}});
+        assert!(urls.rewrite_for_client(&mut parsed, &style));
+        let file_url = parsed["params"]["url"].as_str().unwrap().to_string();
+        assert!(file_url.starts_with("file:///"));
+        assert!(file_url.ends_with("/Events/screen_modules/Screen_Content/HMI_RT_1__HMI_Screen/faceplate_modules/CM_Freq/Events.js"));
+
+        let mut request = json!({"id": 1, "method": "Debugger.setBreakpointByUrl", "params": {"url": file_url.to_uppercase()}});
+        assert!(urls.rewrite_for_target(&mut request));
+        assert_eq!(request["params"]["url"], LONG);
+
+        let mut eval = json!({"method": "Debugger.scriptParsed", "params": {"url": "eval-1.cdp"}});
+        assert!(!urls.rewrite_for_client(&mut eval, &style));
+    }
+
+    #[test]
+    fn long_style_leaves_urls_alone() {
+        let mut urls = UrlMap::default();
+        let mut parsed = json!({"method": "Debugger.scriptParsed", "params": {"url": LONG}});
+        assert!(!urls.rewrite_for_client(&mut parsed, &UrlStyle::Long));
+        assert_eq!(parsed["params"]["url"], LONG);
+    }
+}